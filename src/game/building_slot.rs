@@ -0,0 +1,281 @@
+//! The buildings standing in a city, grouped by tier.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use super::market::{Building, Resources};
+use super::strategic_map::Faction;
+use super::wonders::is_wonder;
+use crate::network::message::PlayerId;
+use crate::prelude::*;
+
+pub const TIERS: usize = 5;
+/// Extra input and output per level above the first.
+const LEVEL_BONUS: f32 = 0.5;
+
+/// Where a player building gets its inputs from.
+#[derive(Reflect, Clone, Copy, Default, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum InputSource {
+    #[default]
+    Market,
+    Warehouse,
+}
+
+/// Where a player building puts its outputs.
+#[derive(Reflect, Clone, Copy, Default, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum OutputDestination {
+    #[default]
+    Market,
+    Warehouse,
+}
+
+#[derive(Reflect, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BuildingSlot {
+    /// Key into the `BuildinTable`.
+    pub building_id: String,
+    pub owner: Faction,
+    pub input: InputSource,
+    pub output: OutputDestination,
+    /// Upkeep state, 1.0 for a building in perfect shape.
+    pub condition: f32,
+    pub level: u8,
+    /// Sale offered by the owner to another player.
+    #[serde(default)]
+    pub offer: Option<BuildingOffer>,
+    /// Share of its output the building produced last turn.
+    #[serde(default)]
+    pub efficiency: f32,
+    /// The input that held production back last turn.
+    #[serde(default)]
+    pub bottleneck: Option<Resources>,
+}
+
+#[derive(Reflect, Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BuildingOffer {
+    pub buyer: PlayerId,
+    pub price: f64,
+}
+
+impl BuildingSlot {
+    pub fn new(building_id: impl Into<String>, owner: Faction) -> Self {
+        BuildingSlot {
+            building_id: building_id.into(),
+            owner,
+            input: InputSource::Market,
+            output: OutputDestination::Market,
+            condition: 1.0,
+            level: 1,
+            offer: None,
+            efficiency: 1.0,
+            bottleneck: None,
+        }
+    }
+
+    pub fn neutral(building_id: impl Into<String>) -> Self {
+        Self::new(building_id, Faction::Neutral)
+    }
+
+    pub fn is_owned_by(&self, player_id: PlayerId) -> bool {
+        self.owner == Faction::Player(player_id)
+    }
+
+    /// How much more than a level 1 building this one consumes and produces.
+    pub fn level_multiplier(&self) -> f32 {
+        1.0 + LEVEL_BONUS * self.level.saturating_sub(1) as f32
+    }
+
+    pub fn describe_efficiency(&self) -> String {
+        match self.bottleneck {
+            Some(res) => format!(
+                "Running at {:.0}%, short on {}",
+                self.efficiency * 100.0,
+                res.get_name()
+            ),
+            None => format!("Running at {:.0}%", self.efficiency * 100.0),
+        }
+    }
+
+    /// Hands the building to a new owner with default settings.
+    pub fn transfer(&mut self, owner: Faction) {
+        self.owner = owner;
+        self.input = InputSource::Market;
+        self.output = OutputDestination::Market;
+        self.offer = None;
+    }
+}
+
+/// All buildings of a city, indexed by tier starting at 1. Slots keep their
+/// index for as long as the city stands, so that queued upgrades can refer to
+/// them; a demolished building leaves its slot empty.
+#[derive(Reflect, Clone, Default, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Buildings([Vec<Option<BuildingSlot>>; TIERS]);
+
+impl From<[Vec<BuildingSlot>; TIERS]> for Buildings {
+    fn from(tiers: [Vec<BuildingSlot>; TIERS]) -> Self {
+        Buildings(tiers.map(|slots| slots.into_iter().map(Some).collect()))
+    }
+}
+
+impl Buildings {
+    /// Buildings standing at a tier.
+    pub fn built(&self, tier: usize) -> usize {
+        self.0[tier - 1].iter().flatten().count()
+    }
+
+    pub fn slot(&self, tier: usize, slot: usize) -> Option<&BuildingSlot> {
+        self.0.get(tier.checked_sub(1)?)?.get(slot)?.as_ref()
+    }
+
+    pub fn slot_mut(&mut self, tier: usize, slot: usize) -> Option<&mut BuildingSlot> {
+        self.0
+            .get_mut(tier.checked_sub(1)?)?
+            .get_mut(slot)?
+            .as_mut()
+    }
+
+    /// Puts a building into the first empty slot of its tier and returns the
+    /// slot's index.
+    pub fn place(&mut self, tier: usize, building: BuildingSlot) -> usize {
+        let slots = &mut self.0[tier - 1];
+        match slots.iter().position(Option::is_none) {
+            Some(slot) => {
+                slots[slot] = Some(building);
+                slot
+            }
+            None => {
+                slots.push(Some(building));
+                slots.len() - 1
+            }
+        }
+    }
+
+    /// Tears a building down, leaving its slot empty.
+    pub fn demolish(&mut self, tier: usize, slot: usize) -> Option<BuildingSlot> {
+        self.0.get_mut(tier.checked_sub(1)?)?.get_mut(slot)?.take()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &BuildingSlot> {
+        self.0.iter().flatten().flatten()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut BuildingSlot> {
+        self.0.iter_mut().flatten().flatten()
+    }
+
+    /// Every building with its tier and slot index.
+    pub fn enumerate(&self) -> impl Iterator<Item = (usize, usize, &BuildingSlot)> {
+        self.0.iter().enumerate().flat_map(|(tier, slots)| {
+            slots
+                .iter()
+                .enumerate()
+                .filter_map(move |(slot, building)| Some((tier + 1, slot, building.as_ref()?)))
+        })
+    }
+
+    pub fn owned_by(&self, player_id: PlayerId) -> impl Iterator<Item = &BuildingSlot> {
+        self.iter().filter(move |b| b.is_owned_by(player_id))
+    }
+
+    /// How many buildings each player owns.
+    pub fn count_by_owner(&self) -> BTreeMap<PlayerId, usize> {
+        let mut counts = BTreeMap::new();
+        for building in self.iter() {
+            if let Faction::Player(player_id) = building.owner {
+                *counts.entry(player_id).or_default() += 1;
+            }
+        }
+        counts
+    }
+
+    pub fn neutral(&self) -> impl Iterator<Item = &BuildingSlot> {
+        self.iter().filter(|b| b.owner == Faction::Neutral)
+    }
+
+    /// Slots a city of the given population has at a tier, built or not.
+    pub fn capacity(tier: usize, population: u8) -> usize {
+        (population as usize + 1).saturating_sub(tier)
+    }
+
+    pub fn free_slots(&self, tier: usize, population: u8) -> usize {
+        Self::capacity(tier, population).saturating_sub(self.built(tier))
+    }
+}
+
+/// A building waiting for its materials or being worked on. Holds on to a free
+/// slot of its tier until it is finished.
+#[derive(Reflect, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ConstructionProject {
+    pub building_id: String,
+    pub owner: Faction,
+    pub tier: usize,
+    /// Materials that still have to be delivered before work starts.
+    pub materials: HashMap<Resources, isize>,
+    pub turns_left: usize,
+    /// Slot of the building being raised a level, `None` for a new building.
+    #[serde(default)]
+    pub upgrade: Option<usize>,
+    /// Money the owner paid to start the project.
+    #[serde(default)]
+    pub paid: isize,
+    /// Materials delivered so far, handed back if the project is called off.
+    #[serde(default)]
+    pub delivered: HashMap<Resources, isize>,
+}
+
+impl ConstructionProject {
+    pub fn new(building_id: impl Into<String>, owner: Faction, building: &Building) -> Self {
+        ConstructionProject {
+            building_id: building_id.into(),
+            owner,
+            tier: building.tier,
+            materials: building.construction.materials.clone(),
+            turns_left: building.construction.turns,
+            upgrade: None,
+            paid: building.construction.cost,
+            delivered: HashMap::new(),
+        }
+    }
+
+    pub fn upgrade(slot: usize, current: &BuildingSlot, building: &Building) -> Self {
+        let construction = building.construction.upgrade_to(current.level + 1);
+        ConstructionProject {
+            building_id: current.building_id.clone(),
+            owner: current.owner,
+            tier: building.tier,
+            materials: construction.materials,
+            turns_left: construction.turns,
+            upgrade: Some(slot),
+            paid: construction.cost,
+            delivered: HashMap::new(),
+        }
+    }
+
+    /// Whether the project reserves a free slot of its tier. Upgrades work on an
+    /// existing slot and wonders stand apart from the tiers.
+    pub fn takes_slot(&self) -> bool {
+        self.upgrade.is_none() && !is_wonder(&self.building_id)
+    }
+
+    pub fn has_materials(&self) -> bool {
+        self.materials.values().all(|amount| *amount <= 0)
+    }
+
+    pub fn describe(&self) -> String {
+        let name = match self.upgrade {
+            Some(_) => format!("{} upgrade", self.building_id),
+            None => self.building_id.clone(),
+        };
+        if self.has_materials() {
+            return format!("{name}: {} turns left", self.turns_left);
+        }
+        let mut missing: Vec<_> = self
+            .materials
+            .iter()
+            .filter(|(_, amount)| **amount > 0)
+            .map(|(res, amount)| format!("{amount} {}", res.get_name()))
+            .collect();
+        missing.sort();
+        format!("{name}: waiting for {}", missing.join(", "))
+    }
+}
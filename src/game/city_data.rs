@@ -2,23 +2,45 @@ use super::strategic_map::*;
 use crate::prelude::*;
 use crate::{game::market, network::message::PlayerId};
 
+use super::building_slot::*;
 use super::market::*;
+use super::risk::Incident;
+use super::warehouse::{Tenure, Warehouse};
+use super::wonders::{WONDER_POPULATION, WonderBonuses, is_wonder};
+use super::world_events::EventEffects;
 use std::collections::{HashMap, HashSet};
 
 use serde::{Deserialize, Serialize};
 
+/// How much more illegal goods cost on the black market than their base value.
+const BLACK_MARKET_PREMIUM: f64 = 2.5;
+
 #[derive(Reflect, Component, Default, Clone, Debug, Serialize, Deserialize)]
 pub struct CityData {
     pub id: String,
     pub race: BuildingType,
     pub population: u8,
-    pub buildings_t1: Vec<(String, Faction, (bool, bool))>,
-    pub buildings_t2: Vec<(String, Faction, (bool, bool))>,
-    pub buildings_t3: Vec<(String, Faction, (bool, bool))>,
-    pub buildings_t4: Vec<(String, Faction, (bool, bool))>,
-    pub buildings_t5: Vec<(String, Faction, (bool, bool))>,
+    pub buildings: Buildings,
+    #[serde(default)]
+    pub wonder: Option<BuildingSlot>,
+    #[serde(default)]
+    pub construction: Vec<ConstructionProject>,
+    /// Money owed to players who sold a building to another player or whose
+    /// upgrade was called off, paid out by the host at the end of the turn.
+    #[serde(default)]
+    pub payouts: Vec<(PlayerId, f64)>,
     pub market: HashMap<Resources, isize>,
-    pub warehouses: HashMap<PlayerId, HashMap<Resources, isize>>,
+    /// Stock of the illegal goods, which never reach the open market.
+    #[serde(default)]
+    pub black_market: HashMap<Resources, isize>,
+    /// Raids the city guard carried out lately.
+    #[serde(default)]
+    pub raids: Vec<Incident>,
+    /// Foreign goods caravans brought across the border and sold here, which
+    /// the city's people use up over the next turns.
+    #[serde(default)]
+    pub imports: HashMap<Resources, isize>,
+    pub warehouses: HashMap<PlayerId, Warehouse>,
     pub tier_up_counter: u8,
 }
 
@@ -27,8 +49,7 @@ impl CityData {
         name: String,
         race: BuildingType,
         tier: u8,
-        mut rng: &mut ResMut<GlobalRng>,
-        players: Query<&Player>,
+        rng: &mut GlobalRng,
     ) -> CityData {
         let buildings_per_tier = match tier {
             1 => (1, 0, 0, 0, 0),
@@ -40,82 +61,144 @@ impl CityData {
                 panic!("Tried to generate a city of tier {:?}", tier)
             }
         };
-        let (mut t1, mut t2, mut t3, mut t4, mut t5) = (vec![], vec![], vec![], vec![], vec![]);
-
-        for _i in 0..buildings_per_tier.0 {
-            t1.push((
-                (market::gen_random_building(1, &mut rng, race)),
-                Faction::Neutral,
-                (false, false),
-            ));
+        let per_tier = [
+            buildings_per_tier.0,
+            buildings_per_tier.1,
+            buildings_per_tier.2,
+            buildings_per_tier.3,
+            buildings_per_tier.4,
+        ];
+        let mut buildings = Buildings::default();
+        for (i, count) in per_tier.into_iter().enumerate() {
+            let tier = i + 1;
+            for _i in 0..count {
+                buildings.place(
+                    tier,
+                    BuildingSlot::neutral(market::gen_random_building(tier as u8, rng, race)),
+                );
+            }
         }
 
-        for _i in 0..buildings_per_tier.1 {
-            t2.push((
-                (market::gen_random_building(2, &mut rng, race)),
-                Faction::Neutral,
-                (false, false),
-            ));
+        let mut market = HashMap::new();
+        for res in Resources::all_resources() {
+            market.insert(res, 0);
         }
 
-        for _i in 0..buildings_per_tier.2 {
-            t3.push((
-                (market::gen_random_building(3, &mut rng, race)),
-                Faction::Neutral,
-                (false, false),
-            ));
+        CityData {
+            id: name,
+            race: race,
+            population: tier,
+            buildings: buildings,
+            wonder: None,
+            construction: vec![],
+            payouts: vec![],
+            market: market,
+            black_market: HashMap::new(),
+            raids: vec![],
+            imports: HashMap::new(),
+            // Players have to rent or build their storage
+            warehouses: HashMap::new(),
+            tier_up_counter: 0,
         }
+    }
 
-        for _i in 0..buildings_per_tier.3 {
-            t4.push((
-                (market::gen_random_building(4, &mut rng, race)),
-                Faction::Neutral,
-                (false, false),
-            ));
+    /// Units of a resource on sale, illegal goods are counted on the black market.
+    pub fn stock(&self, res: &Resources) -> isize {
+        if res.is_illegal() {
+            return self.black_market.get(res).copied().unwrap_or(0);
         }
+        *self.market.get(res).expect(
+            format!(
+                "tried to find resource {:?} but the resource was missing in internal market",
+                res
+            )
+            .as_str(),
+        )
+    }
 
-        for _i in 0..buildings_per_tier.4 {
-            t5.push((
-                (market::gen_random_building(5, &mut rng, race)),
-                Faction::Neutral,
-                (false, false),
-            ));
+    pub fn stock_mut(&mut self, res: &Resources) -> &mut isize {
+        if res.is_illegal() {
+            self.black_market.entry(*res).or_insert(0)
+        } else {
+            self.market.entry(*res).or_insert(0)
         }
+    }
 
-        let mut market = HashMap::new();
-        for res in Resources::all_resources() {
-            market.insert(res, 0);
-        }
+    pub fn warehouse(&self, player_id: PlayerId) -> Option<&Warehouse> {
+        self.warehouses.get(&player_id)
+    }
 
-        let mut warehouses = HashMap::new();
+    /// A player's warehouse, created empty and without capacity if they have
+    /// none here yet.
+    pub fn warehouse_mut(&mut self, player_id: PlayerId) -> &mut Warehouse {
+        self.warehouses.entry(player_id).or_default()
+    }
 
-        println!("players: {0:?}", players);
-        for player in players {
-            warehouses.insert(player.player_id, market.clone());
+    /// Units of a resource a player has stored here.
+    pub fn stored(&self, player_id: PlayerId, res: Resources) -> isize {
+        self.warehouse(player_id).map_or(0, |w| w[res])
+    }
+
+    /// Puts up to `amount` into a player's warehouse and returns how much fit.
+    /// Nothing fits for players without a warehouse here.
+    pub fn store(&mut self, player_id: PlayerId, res: Resources, amount: isize) -> isize {
+        self.warehouses
+            .get_mut(&player_id)
+            .map_or(0, |w| w.store(res, amount))
+    }
+
+    /// Takes up to `amount` from a player's warehouse and returns how much was
+    /// there.
+    pub fn take(&mut self, player_id: PlayerId, res: Resources, amount: isize) -> isize {
+        let taken = self.stored(player_id, res).clamp(0, amount);
+        if taken > 0 {
+            self.warehouse_mut(player_id)[res] -= taken;
         }
+        taken
+    }
 
-        CityData {
-            id: name,
-            race: race,
-            population: tier,
-            buildings_t1: t1,
-            buildings_t2: t2,
-            buildings_t3: t3,
-            buildings_t4: t4,
-            buildings_t5: t5,
-            market: market,
-            warehouses: warehouses,
-            tier_up_counter: 0,
+    /// Charges every player for their warehouse here, refreshes wonder capacity
+    /// and lets the goods spoil. Warehouses a player never rented or built are
+    /// dropped once emptied. Returns whether anything stored changed.
+    pub fn keep_warehouses(
+        &mut self,
+        players: &mut Query<&mut Player>,
+        bonuses: &HashMap<PlayerId, WonderBonuses>,
+    ) -> bool {
+        let before = self.warehouses.len();
+        self.warehouses.retain(|_, w| w.tenure != Tenure::None || w.used() > 0);
+        let mut changed = self.warehouses.len() != before;
+        let population = self.population;
+        for (player_id, warehouse) in self.warehouses.iter_mut() {
+            let bonus = bonuses
+                .get(player_id)
+                .map_or(0, |b| b.extra_capacity(warehouse.capacity));
+            changed |= warehouse.bonus_capacity != bonus;
+            warehouse.bonus_capacity = bonus;
+            if let Some(mut player) = players.iter_mut().find(|p| p.player_id == *player_id) {
+                player.money -= warehouse.upkeep(population);
+            }
+            for (res, lost) in warehouse.spoil() {
+                info!("{lost} {} spoiled in a warehouse", res.get_name());
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    fn premium(res: &Resources) -> f64 {
+        if res.is_illegal() {
+            BLACK_MARKET_PREMIUM
+        } else {
+            1.0
         }
     }
 
     pub fn get_resource_value_modifier(&self, res: &Resources) -> f64 {
-        let Some(total) = self.market.get(res) else {
-            panic!("tried to find resource {res:?} but the resource was missing")
-        };
+        let total = self.stock(res);
 
         let sigmoid =
-            2.0 / (1.0 + (std::f64::consts::E).powf((*total) as f64 * 1.0 / 200.0)) as f64;
+            2.0 / (1.0 + (std::f64::consts::E).powf(total as f64 * 1.0 / 200.0)) as f64;
         sigmoid.max(0.3)
     }
 
@@ -125,22 +208,18 @@ impl CityData {
     }
 
     pub fn get_resource_value(&self, res: &Resources) -> f64 {
-        self.get_resource_value_modifier(res) * res.get_base_value() as f64
+        self.get_resource_value_modifier(res) * res.get_base_value() as f64 * Self::premium(res)
     }
 
     fn get_theoretical_resource_value(&self, res: &Resources, amount: isize) -> f64 {
-        self.get_theoretical_resource_value_modifier(res, amount) * res.get_base_value() as f64
+        self.get_theoretical_resource_value_modifier(res, amount)
+            * res.get_base_value() as f64
+            * Self::premium(res)
     }
 
     pub fn get_bulk_buy_price(&self, res: &Resources, amount: usize) -> f64 {
         info!("called bulkprice getter with arguments: {0} x{1}", res.get_name(), amount);
-        let mut amount_available = *self.market.get(res).expect(
-            format!(
-                "tried to find resource {:?} but the resource was missing in internal market",
-                res
-            )
-            .as_str(),
-        );
+        let mut amount_available = self.stock(res);
         info!("found {amount_available} resources in local market");
         let mut total_cost = 0.0;
         info!("running cost tally...");
@@ -154,13 +233,7 @@ impl CityData {
     }
 
     pub fn get_bulk_sell_price(&self, res: &Resources, amount: usize) -> f64 {
-        let mut amount_available = *self.market.get(res).expect(
-            format!(
-                "tried to find resource {:?} but the resource was missing in internal market",
-                res
-            )
-            .as_str(),
-        );
+        let mut amount_available = self.stock(res);
         let mut total_profit = 0.0;
         for i in 0..amount {
             let price = self.get_theoretical_resource_value(res, amount_available + i as isize);
@@ -170,53 +243,184 @@ impl CityData {
         total_profit
     }
 
-    pub fn available_commodities(&self, building_table: &Res<BuildinTable>) -> Vec<Resources> {
-        let mut resources: HashMap<Resources, isize> = HashMap::new();
-        macro_rules! get_outputs {
-            ($list:expr) => {
-                for b in &$list {
-                    if b.1 != Faction::Neutral {
+    /// Buildings can't be raised above the city's population.
+    pub fn max_level(&self) -> u8 {
+        self.population
+    }
+
+    /// The player owning more than half of the city's buildings.
+    pub fn controller(&self) -> Faction {
+        let total = self.buildings.iter().count();
+        self.buildings
+            .count_by_owner()
+            .into_iter()
+            .find(|(_, owned)| 2 * owned > total)
+            .map_or(Faction::Neutral, |(player_id, _)| Faction::Player(player_id))
+    }
+
+    pub fn upgrade_queued(&self, tier: usize, slot: usize) -> bool {
+        self.construction
+            .iter()
+            .any(|p| p.tier == tier && p.upgrade == Some(slot))
+    }
+
+    /// Tears a building down along with any upgrade queued for it. The other
+    /// buildings keep their slots.
+    pub fn demolish(&mut self, tier: usize, slot: usize) -> Option<BuildingSlot> {
+        let building = self.buildings.demolish(tier, slot)?;
+        self.cancel_upgrade(tier, slot);
+        Some(building)
+    }
+
+    /// Calls off the upgrade queued for a slot and refunds its owner.
+    pub fn cancel_upgrade(&mut self, tier: usize, slot: usize) {
+        let (cancelled, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.construction)
+            .into_iter()
+            .partition(|p| p.tier == tier && p.upgrade == Some(slot));
+        self.construction = kept;
+        for project in cancelled {
+            self.refund(project);
+        }
+    }
+
+    /// Pays back what the owner of a project put in. Delivered materials go back
+    /// into their warehouse, whatever doesn't fit is sold here.
+    fn refund(&mut self, project: ConstructionProject) {
+        let Faction::Player(player_id) = project.owner else {
+            return;
+        };
+        info!("Refunding {} in {}", project.describe(), self.id);
+        let mut money = project.paid as f64;
+        let mut delivered: Vec<_> = project.delivered.into_iter().collect();
+        delivered.sort();
+        for (res, amount) in delivered {
+            let unsold = amount - self.store(player_id, res, amount);
+            if unsold > 0 {
+                money += self.get_bulk_sell_price(&res, unsold as usize);
+                self.market.insert(res, self.market[&res] + unsold);
+            }
+        }
+        self.payouts.push((player_id, money));
+    }
+
+    /// Slots at a tier that are neither built on nor reserved by construction.
+    pub fn free_slots(&self, tier: usize) -> usize {
+        let queued = self
+            .construction
+            .iter()
+            .filter(|p| p.tier == tier && p.takes_slot())
+            .count();
+        self.buildings.free_slots(tier, self.population).saturating_sub(queued)
+    }
+
+    pub fn can_hold_wonder(&self) -> bool {
+        self.population >= WONDER_POPULATION
+            && self.wonder.is_none()
+            && !self.construction.iter().any(|p| is_wonder(&p.building_id))
+    }
+
+    /// Delivers materials to the construction projects from their owner's
+    /// warehouse or the market, and finishes buildings whose time is up. Wonders
+    /// already standing in `built_wonders` are dropped, the rivals lose what they
+    /// put in. Returns whether anything changed.
+    pub fn advance_construction(
+        &mut self,
+        players: &mut Query<&mut Player>,
+        built_wonders: &mut Vec<String>,
+    ) -> bool {
+        let mut changed = false;
+        let mut construction = std::mem::take(&mut self.construction);
+        construction.retain(|project| {
+            let lost = built_wonders.contains(&project.building_id);
+            if lost {
+                info!("{} lost the race for {}", self.id, project.building_id);
+                changed = true;
+            }
+            !lost
+        });
+        for project in &mut construction {
+            let Faction::Player(player_id) = project.owner else {
+                continue;
+            };
+            let mut materials: Vec<_> = project.materials.iter_mut().collect();
+            materials.sort_by_key(|(res, _)| **res);
+            for (res, missing) in materials {
+                if *missing <= 0 {
+                    continue;
+                }
+                let taken = self.take(player_id, *res, *missing);
+                if taken > 0 {
+                    *missing -= taken;
+                    *project.delivered.entry(*res).or_default() += taken;
+                    changed = true;
+                }
+
+                let bought = self.market[res].clamp(0, *missing);
+                if bought > 0 {
+                    let price = self.get_bulk_buy_price(res, bought as usize);
+                    let Some(mut player) = players.iter_mut().find(|p| p.player_id == player_id)
+                    else {
                         continue;
-                    }
-                    for (res, amount) in &building_table
-                        .0
-                        .get(&b.0)
-                        .expect(format!("Couldn't retrieve value for {:?}", &b.0).as_str())
-                        .input
-                    {
-                        resources.insert(
-                            *res,
-                            resources
-                                .get(res)
-                                .or_else(|| -> Option<&isize> { Some(&0) })
-                                .expect(format!("bruh value {:?}", res).as_str())
-                                - amount,
-                        );
-                    }
-                    for (res, amount) in &building_table
-                        .0
-                        .get(&b.0)
-                        .expect(format!("Couldn't retrieve value for {:?}", &b.0).as_str())
-                        .output
-                    {
-                        resources.insert(
-                            *res,
-                            resources
-                                .get(res)
-                                .or_else(|| -> Option<&isize> { Some(&0) })
-                                .expect(format!("bruh value {:?}", res).as_str())
-                                + amount,
-                        );
-                    }
+                    };
+                    player.money -= price;
+                    self.market.insert(*res, self.market[res] - bought);
+                    *missing -= bought;
+                    *project.delivered.entry(*res).or_default() += bought;
+                    changed = true;
+                }
+            }
+            if project.has_materials() && project.turns_left > 0 {
+                project.turns_left -= 1;
+                changed = true;
+            }
+        }
+
+        let (finished, building): (Vec<_>, Vec<_>) = construction
+            .into_iter()
+            .partition(|project| project.has_materials() && project.turns_left == 0);
+        self.construction = building;
+        for project in finished {
+            if is_wonder(&project.building_id) {
+                if built_wonders.contains(&project.building_id) {
+                    info!("{} lost the race for {}", self.id, project.building_id);
+                    continue;
                 }
+                built_wonders.push(project.building_id.clone());
+                self.wonder = Some(BuildingSlot::new(project.building_id, project.owner));
+                continue;
+            }
+            let Some(slot) = project.upgrade else {
+                self.buildings.place(
+                    project.tier,
+                    BuildingSlot::new(project.building_id, project.owner),
+                );
+                continue;
             };
+            // The slot may have changed hands in a way that didn't call the upgrade off
+            match self.buildings.slot_mut(project.tier, slot) {
+                Some(b) if b.building_id == project.building_id && b.owner == project.owner => {
+                    b.level += 1;
+                }
+                _ => self.refund(project),
+            }
         }
+        changed
+    }
 
-        get_outputs!(self.buildings_t1);
-        get_outputs!(self.buildings_t2);
-        get_outputs!(self.buildings_t3);
-        get_outputs!(self.buildings_t4);
-        get_outputs!(self.buildings_t5);
+    pub fn available_commodities(&self, building_table: &Res<BuildinTable>) -> Vec<Resources> {
+        let mut resources: HashMap<Resources, isize> = HashMap::new();
+        for b in self.buildings.neutral() {
+            let building = building_table
+                .0
+                .get(&b.building_id)
+                .expect(format!("Couldn't retrieve value for {:?}", &b.building_id).as_str());
+            for (res, amount) in &building.input {
+                *resources.entry(*res).or_insert(0) -= amount;
+            }
+            for (res, amount) in &building.output {
+                *resources.entry(*res).or_insert(0) += amount;
+            }
+        }
 
         let mut hash = resources
             .iter()
@@ -234,131 +438,82 @@ impl CityData {
     }
 
     #[rustfmt::skip]
-    pub fn update_market(&mut self, building_table: &Res<BuildinTable>, mut players: &mut Query<&mut Player>) {
-        macro_rules! update_market_over_buildings {
-            ($list:expr) => {
-                for b in &$list {
-                    if b.1 != Faction::Neutral {
-                        continue;
-                    }
-                    for (res, amount) in &building_table
-                        .0
-                        .get(&b.0)
-                        .expect(format!("Couldn't retrieve value for {:?}", &b.0).as_str())
-                        .input
-                    {
-                        self.market.insert(*res, self.market[&res] - amount);
-                    }
-                    for (res, amount) in &building_table
-                        .0
-                        .get(&b.0)
-                        .expect(format!("Couldn't retrieve value for {:?}", &b.0).as_str())
-                        .output
-                    {
-                        self.market.insert(*res, self.market[&res] + amount);
-                    }
-                }
-            };
+    pub fn update_market(&mut self, building_table: &Res<BuildinTable>, mut players: &mut Query<&mut Player>, effects: &EventEffects) {
+        let mut buildings = std::mem::take(&mut self.buildings);
+
+        for b in buildings.iter_mut().filter(|b| b.owner == Faction::Neutral) {
+            let building = building_table
+                .0
+                .get(&b.building_id)
+                .expect(format!("Couldn't retrieve value for {:?}", &b.building_id).as_str());
+            let multiplier = b.level_multiplier();
+            let (efficiency, bottleneck) = input_efficiency(&building.input, multiplier, |res| self.stock(res));
+            for (res, amount) in &building.input {
+                *self.stock_mut(res) -= scaled(*amount, efficiency * multiplier);
+            }
+            for (res, amount) in &building.output {
+                *self.stock_mut(res) += scaled(*amount, efficiency * multiplier * effects.output_factor(res));
+            }
+            b.efficiency = efficiency;
+            b.bottleneck = bottleneck;
         }
 
-        macro_rules! update_player_buildings {
-            ($list:expr) => {
-                for b in &$list {
-                    let Faction::Player(player_id) = b.1 else { 
-                        //println!("building didnt have proper faction");
-                        continue; 
-                    };
-                    //println!("processing player building: {b:?}");
-                    let building = &building_table.0
-                                                .get(&b.0)
-                                                .expect(format!("Couldn't retrieve value for {:?}", &b.0).as_str());
-                    let mut demands_met = false;
-                    if b.2.0 {
-                        let mut warehouse_meets_demands = true;
-                        //info!("building wants {0}", &building.input);
-                        for (res, amount) in &building.input {
-                            //info!("checking city for stored {0}", &res.get_name());
-                            warehouse_meets_demands = warehouse_meets_demands
-                                && amount <= self.warehouses
-                                                    .get(&(player_id as u64))
-                                                    .expect(format!("PlayerId {:?} doesn't exist", player_id).as_str())
-                                                    .get(&res)
-                                                    .expect(format!("Warehouse for player {:?} improperly initialized", player_id).as_str());
-                            //info!("city has {0} {1} stored, compared to {2} wanted",
-                            //                    self.warehouses
-                            //                        .get(&(player_id as u64))
-                            //                        .expect(format!("PlayerId {:?} doesn't exist", player_id).as_str())
-                            //                        .get(&res)
-                            //                        .expect(format!("Warehouse for player {:?} improperly initialized", player_id).as_str()), 
-                            //                        &res.get_name(),
-                            //                        amount);
-                        }
-                        if warehouse_meets_demands {
-                            demands_met = true;
-                            for (res, amount) in &building.input {
-                                let cur_amount = self.warehouses
-                                                    .get_mut(&(player_id as u64))
-                                                    .expect(&format!("PlayerId {:?} doesn't exist", player_id))
-                                                    .entry(*res)
-                                                    .or_insert(0);
-                                *cur_amount -= amount;
-                            }
-                        }
-                    } else {
-                        let mut market_meets_demands = true;
-                        for (res, _) in &building.input {
-                            market_meets_demands = market_meets_demands
-                                && self.available_commodities(&building_table).contains(&res);
-                        }
-                        if market_meets_demands {
-                            for (res, amount) in &building.input {
-                                let price = self.get_bulk_buy_price(&res, *amount as usize);
-                                players.iter_mut().find(|x| x.player_id == player_id as u64).expect("building belongs to player {player_id} but no such player exists").money -= price;
-                                let market_amount = self.market.entry(*res).or_insert(0);
-                                *market_amount -= amount;
-                            }
-                            demands_met = true;
-                        }
+        for b in buildings.iter_mut() {
+            let Faction::Player(player_id) = b.owner else {
+                continue;
+            };
+            let building = &building_table.0
+                                        .get(&b.building_id)
+                                        .expect(format!("Couldn't retrieve value for {:?}", &b.building_id).as_str());
+            let multiplier = b.level_multiplier();
+            let (efficiency, bottleneck) = match b.input {
+                InputSource::Warehouse => input_efficiency(&building.input, multiplier, |res| self.stored(player_id, *res)),
+                InputSource::Market => input_efficiency(&building.input, multiplier, |res| self.stock(res)),
+            };
+            b.efficiency = efficiency;
+            b.bottleneck = bottleneck;
+
+            for (res, amount) in &building.input {
+                let amount = scaled(*amount, efficiency * multiplier);
+                match b.input {
+                    InputSource::Warehouse => {
+                        self.take(player_id, *res, amount);
                     }
-
-                    if demands_met {
-                        if b.2.1 {
-                            for (res, amount) in &building.output {
-                                let cur_amount = self.warehouses.get_mut(&(player_id as u64))
-                                                                .expect(&format!("PlayerId {player_id} doesn't exist"))
-                                                                .entry(*res)
-                                                                .or_insert(0);
-
-                                *cur_amount += amount;
-                            }
+                    InputSource::Market => {
+                        let price = self.get_bulk_buy_price(&res, amount as usize);
+                        if let Some(mut player) = players.iter_mut().find(|x| x.player_id == player_id) {
+                            player.money -= price;
                         } else {
-                            for (res, amount) in &building.output {
-                                let price = self.get_bulk_sell_price(&res, *amount as usize);
-                                players.iter_mut().find(|x| x.player_id == player_id as u64)
-                                                    .expect("building belongs to player {player_id} but no such player exists")
-                                                    .money += price;
-
-                                let market_amount = self.market.entry(*res).or_insert(0);
-                                *market_amount += amount;
-                            }
+                            warn!("Building belongs to player {player_id} but no such player exists");
                         }
+                        *self.stock_mut(res) -= amount;
                     }
-                    println!("New value of warehouses in current city: {:?}", self.warehouses);
+                }
+            }
+
+            for (res, amount) in &building.output {
+                let mut amount = scaled(*amount, efficiency * multiplier * effects.output_factor(res));
+                if b.output == OutputDestination::Warehouse {
+                    // Whatever doesn't fit into the warehouse is sold instead
+                    amount -= self.store(player_id, *res, amount);
+                }
+                if amount > 0 {
+                    let price = self.get_bulk_sell_price(&res, amount as usize);
+                    if let Some(mut player) = players.iter_mut().find(|x| x.player_id == player_id) {
+                        player.money += price;
+                    } else {
+                        warn!("Building belongs to player {player_id} but no such player exists");
+                    }
+                    *self.stock_mut(res) += amount;
                 }
             }
         }
 
-        update_market_over_buildings!(self.buildings_t1);
-        update_market_over_buildings!(self.buildings_t2);
-        update_market_over_buildings!(self.buildings_t3);
-        update_market_over_buildings!(self.buildings_t4);
-        update_market_over_buildings!(self.buildings_t5);
+        self.buildings = buildings;
 
-        update_player_buildings!(self.buildings_t1);
-        update_player_buildings!(self.buildings_t2);
-        update_player_buildings!(self.buildings_t3);
-        update_player_buildings!(self.buildings_t4);
-        update_player_buildings!(self.buildings_t5);
+        for (res, amount) in effects.demand() {
+            *self.stock_mut(res) -= amount;
+        }
 
         let match_condition = self.population;
 
@@ -403,8 +558,8 @@ impl CityData {
                         self.market.insert(Resources::ManufacturedGoods, self.market[&Resources::ManufacturedGoods] - 3).expect("error in city market") - 3 >= 0 &&
                         self.market.insert(Resources::Luxuries, self.market[&Resources::Luxuries] - 15).expect("error in city market") - 15 >= 0 &&
                         self.market.insert(Resources::Transportation, self.market[&Resources::Transportation] - 15).expect("error in city market") - 15 >= 0);
-                self.market.insert(Resources::Drugs, self.market[&Resources::Drugs] - 5).expect("error in city market");
-                self.market.insert(Resources::Slaves, self.market[&Resources::Slaves] - 5).expect("error in city market");
+                *self.stock_mut(&Resources::Drugs) -= 5;
+                *self.stock_mut(&Resources::Slaves) -= 5;
                 self.market.insert(Resources::SimpleLabour, self.market[&Resources::SimpleLabour] + 45);
                 self.market.insert(Resources::ComplexLabour, self.market[&Resources::ComplexLabour] + 20);
             },
@@ -420,9 +575,9 @@ impl CityData {
                         self.market.insert(Resources::Luxuries, self.market[&Resources::Luxuries] - 25).expect("error in city market") - 25 >= 0 &&
                         self.market.insert(Resources::Transportation, self.market[&Resources::Transportation] - 25).expect("error in city market") - 25 >= 0 &&
                         self.market.insert(Resources::Military, self.market[&Resources::Military] - 15).expect("error in city market") - 15 >= 0);
-                self.market.insert(Resources::Drugs, self.market[&Resources::Drugs] - 10).expect("error in city market");
-                self.market.insert(Resources::Slaves, self.market[&Resources::Slaves] - 10).expect("error in city market");
-                self.market.insert(Resources::Vitae, self.market[&Resources::Vitae] - 2).expect("error in city market");
+                *self.stock_mut(&Resources::Drugs) -= 10;
+                *self.stock_mut(&Resources::Slaves) -= 10;
+                *self.stock_mut(&Resources::Vitae) -= 2;
                 self.market.insert(Resources::SimpleLabour, self.market[&Resources::SimpleLabour] + 80);
                 self.market.insert(Resources::ComplexLabour, self.market[&Resources::ComplexLabour] + 45);
             },
@@ -438,9 +593,9 @@ impl CityData {
                 self.market.insert(Resources::Luxuries, self.market[&Resources::Luxuries] - 60);
                 self.market.insert(Resources::Transportation, self.market[&Resources::Transportation] - 60);
                 self.market.insert(Resources::Military, self.market[&Resources::Military] - 50);
-                self.market.insert(Resources::Drugs, self.market[&Resources::Drugs] - 20).expect("error in city market");
-                self.market.insert(Resources::Slaves, self.market[&Resources::Slaves] - 20).expect("error in city market");
-                self.market.insert(Resources::Vitae, self.market[&Resources::Vitae] - 5).expect("error in city market");
+                *self.stock_mut(&Resources::Drugs) -= 20;
+                *self.stock_mut(&Resources::Slaves) -= 20;
+                *self.stock_mut(&Resources::Vitae) -= 5;
                 self.market.insert(Resources::SimpleLabour, self.market[&Resources::SimpleLabour] + 125);
                 self.market.insert(Resources::ComplexLabour, self.market[&Resources::ComplexLabour] + 80);
             }
@@ -448,3 +603,44 @@ impl CityData {
         }
     }
 }
+
+/// Share of a building's inputs that is available, along with the input that is
+/// most lacking when it can't run fully. Inputs are used up in whole units only,
+/// so the share is cut down to what those whole units pay for and the building
+/// never makes more than its inputs allow.
+fn input_efficiency(
+    input: &HashMap<Resources, isize>,
+    multiplier: f32,
+    available: impl Fn(&Resources) -> isize,
+) -> (f32, Option<Resources>) {
+    let mut efficiency = 1.0;
+    let mut bottleneck = None;
+    let mut inputs: Vec<_> = input.iter().collect();
+    inputs.sort();
+    for &(res, amount) in &inputs {
+        if *amount <= 0 {
+            continue;
+        }
+        let share = (available(res).max(0) as f32 / (*amount as f32 * multiplier)).min(1.0);
+        if share < efficiency {
+            efficiency = share;
+            bottleneck = Some(*res);
+        }
+    }
+    let mut paid_for = efficiency;
+    for &(res, amount) in inputs.iter().filter(|(_, amount)| **amount > 0) {
+        let needed = *amount as f32 * multiplier;
+        let share = scaled(*amount, efficiency * multiplier) as f32 / needed;
+        if share < paid_for {
+            paid_for = share;
+            bottleneck = Some(*res);
+        }
+    }
+    (paid_for, bottleneck)
+}
+
+/// Whole units of `amount` at the given share, with some slack for rounding
+/// errors so that a share worked out from whole units gives them back.
+fn scaled(amount: isize, efficiency: f32) -> isize {
+    (amount as f32 * efficiency + 1e-4).floor() as isize
+}
@@ -1,15 +1,24 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::f32::consts::PI;
+use std::fmt;
+use std::sync::Mutex;
 
+use super::building_slot::{BuildingSlot, Buildings};
 use super::city_data::CityData;
+use super::map_definition::MapDefinition;
+use super::map_seed::MapRng;
 use super::market::*;
-use super::strategic_map::Faction;
+use super::roads::RoadType;
+use super::terrain::{territory_of, Terrain, TerrainMap};
 use crate::game::namelists::{generate_city_names, CityNameList};
-use crate::game::strategic_map::{spawn_player, Player};
+use crate::game::strategic_map::spawn_player;
 use crate::{prelude::*, GameState, NetworkState};
 
-use petgraph::algo::astar;
-use petgraph::{algo::connected_components, graph::NodeIndex, Graph, Undirected};
+use petgraph::algo::{astar, floyd_warshall::floyd_warshall_path};
+use petgraph::graph::EdgeIndex;
+use petgraph::unionfind::UnionFind;
+use petgraph::visit::EdgeRef;
+use petgraph::{graph::NodeIndex, Graph, Undirected};
 
 pub fn plugin(app: &mut App) {
     app.add_systems(
@@ -19,6 +28,7 @@ pub fn plugin(app: &mut App) {
             setup,
             gen_edges,
             remove_random_edges,
+            check_generated_map,
         )
             .chain()
             .in_set(NodeGenSet),
@@ -30,24 +40,252 @@ pub fn plugin(app: &mut App) {
 pub struct Node(pub NodeIndex, pub Vec2, pub Color);
 
 #[derive(Reflect, Component, Clone, Debug)]
-pub struct CityEdge(f32);
+pub struct CityEdge {
+    pub distance: f32,
+    /// Roughest terrain the road crosses. Painted maps only know water, where a
+    /// road runs through an impassable region, and plains.
+    pub terrain: Terrain,
+    pub road: RoadType,
+}
+
+impl CityEdge {
+    pub fn cost(&self) -> f32 {
+        self.distance * self.terrain.travel_cost() * self.road.cost_factor()
+    }
+}
 
 #[derive(Reflect, Resource, Default)]
 pub struct CityGraph {
     #[reflect(ignore)]
     pub graph: CGraph,
+    /// World position of every node, used as the A* heuristic.
+    #[reflect(ignore)]
+    positions: HashMap<NodeIndex, Vec2>,
+    /// Edges that caravans currently can't use at all.
+    #[reflect(ignore)]
+    blockades: HashSet<EdgeIndex>,
+    /// Multiplier applied on top of the edge length, 1.0 when missing.
+    #[reflect(ignore)]
+    route_costs: HashMap<EdgeIndex, f32>,
+    /// Cheapest route between every pair of connected cities, worked out all at
+    /// once after the roads, blockades or costs changed.
+    #[reflect(ignore)]
+    route_cache: Mutex<Option<HashMap<(NodeIndex, NodeIndex), Route>>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Route {
+    pub cost: f32,
+    pub path: Vec<Entity>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PathError {
+    UnknownNode(NodeIndex),
+    NoRoute(NodeIndex, NodeIndex),
+    /// Every way leads across the border between these races, closed by an
+    /// embargo.
+    Embargo(BuildingType, BuildingType),
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::UnknownNode(node) => write!(f, "node {node:?} is not part of the map"),
+            PathError::NoRoute(from, to) => {
+                write!(f, "no open route between {from:?} and {to:?}")
+            }
+            PathError::Embargo(a, b) => {
+                write!(f, "the border between {a:?} and {b:?} is closed")
+            }
+        }
+    }
+}
+
+/// Route costs can make edges cheaper than their length. Together with the
+/// cheapest road this bounds how little a crossing can cost per unit of
+/// distance, which keeps the A* heuristic admissible.
+pub const MIN_ROUTE_COST: f32 = 0.25;
+
+impl CityGraph {
+    pub fn add_city(&mut self, entity: Entity, pos: Vec2) -> NodeIndex {
+        let idx = self.graph.add_node(entity);
+        self.positions.insert(idx, pos);
+        idx
+    }
+
+    pub fn position(&self, node: NodeIndex) -> Option<Vec2> {
+        self.positions.get(&node).copied()
+    }
+
+    pub fn edge_between(&self, a: NodeIndex, b: NodeIndex) -> Option<EdgeIndex> {
+        self.graph.find_edge(a, b)
+    }
+
+    pub fn is_blockaded(&self, edge: EdgeIndex) -> bool {
+        self.blockades.contains(&edge)
+    }
+
+    pub fn set_blockade(&mut self, edge: EdgeIndex, blocked: bool) {
+        let changed = if blocked {
+            self.blockades.insert(edge)
+        } else {
+            self.blockades.remove(&edge)
+        };
+        if changed {
+            self.invalidate_routes();
+        }
+    }
+
+    pub fn route_cost(&self, edge: EdgeIndex) -> f32 {
+        self.route_costs.get(&edge).copied().unwrap_or(1.0)
+    }
+
+    pub fn set_road(&mut self, edge: EdgeIndex, road: RoadType) {
+        let Some(weight) = self.graph.edge_weight_mut(edge) else {
+            return;
+        };
+        weight.road = road;
+        self.invalidate_routes();
+    }
+
+    pub fn set_route_cost(&mut self, edge: EdgeIndex, cost: f32) {
+        let cost = cost.max(MIN_ROUTE_COST);
+        if cost == self.route_cost(edge) {
+            return;
+        }
+        if cost == 1.0 {
+            self.route_costs.remove(&edge);
+        } else {
+            self.route_costs.insert(edge, cost);
+        }
+        self.invalidate_routes();
+    }
+
+    /// Cost of travelling along a single edge, infinite when blockaded.
+    pub fn edge_cost(&self, edge: EdgeIndex) -> f32 {
+        if self.is_blockaded(edge) {
+            return f32::INFINITY;
+        }
+        self.graph[edge].cost() * self.route_cost(edge)
+    }
+
+    /// Drops the routes, they are worked out again when the next one is asked for.
+    pub fn invalidate_routes(&mut self) {
+        *self.route_cache.get_mut().expect("route cache poisoned") = None;
+    }
+
+    /// Works out the routes between all cities right away.
+    pub fn rebuild_routes(&mut self) {
+        let routes = self.all_routes();
+        *self.route_cache.get_mut().expect("route cache poisoned") = Some(routes);
+    }
+
+    pub fn find_route(&self, from: NodeIndex, to: NodeIndex) -> Result<Route, PathError> {
+        for node in [from, to] {
+            if self.graph.node_weight(node).is_none() {
+                return Err(PathError::UnknownNode(node));
+            }
+        }
+        let mut cache = self.route_cache.lock().expect("route cache poisoned");
+        cache
+            .get_or_insert_with(|| self.all_routes())
+            .get(&(from, to))
+            .cloned()
+            .ok_or(PathError::NoRoute(from, to))
+    }
+
+    /// Cheapest route between every pair of cities that are connected by roads
+    /// that aren't blockaded.
+    fn all_routes(&self) -> HashMap<(NodeIndex, NodeIndex), Route> {
+        let Ok((costs, previous)) = floyd_warshall_path(&self.graph, |e| self.edge_cost(e.id()))
+        else {
+            return HashMap::new();
+        };
+        let mut routes = HashMap::new();
+        for ((from, to), cost) in costs {
+            // Cities without a route between them keep the largest cost
+            if cost >= f32::MAX {
+                continue;
+            }
+            let mut path = vec![to];
+            let mut node = to.index();
+            while node != from.index() {
+                let Some(before) = previous[from.index()][node] else {
+                    break;
+                };
+                path.push(NodeIndex::new(before));
+                node = before;
+            }
+            path.reverse();
+            let path = path.into_iter().map(|idx| self.graph[idx]).collect();
+            routes.insert((from, to), Route { cost, path });
+        }
+        routes
+    }
+
+    /// The cheapest route that doesn't use any edge `avoid` rejects. Not
+    /// cached, since what is avoided differs between callers.
+    pub fn find_route_avoiding(
+        &self,
+        from: NodeIndex,
+        to: NodeIndex,
+        avoid: impl Fn(EdgeIndex) -> bool,
+    ) -> Result<Route, PathError> {
+        self.search_route(from, to, avoid)
+    }
+
+    fn search_route(
+        &self,
+        from: NodeIndex,
+        to: NodeIndex,
+        avoid: impl Fn(EdgeIndex) -> bool,
+    ) -> Result<Route, PathError> {
+        for node in [from, to] {
+            if self.graph.node_weight(node).is_none() {
+                return Err(PathError::UnknownNode(node));
+            }
+        }
+
+        let goal = self.position(to);
+        let (cost, path) = astar(
+            &self.graph,
+            from,
+            |x| x == to,
+            |e| {
+                if avoid(e.id()) {
+                    f32::INFINITY
+                } else {
+                    self.edge_cost(e.id())
+                }
+            },
+            |n| match (self.position(n), goal) {
+                (Some(a), Some(b)) => a.distance(b) * MIN_ROUTE_COST * RoadType::MIN_COST_FACTOR,
+                _ => 0.0,
+            },
+        )
+        .ok_or(PathError::NoRoute(from, to))?;
+
+        if !cost.is_finite() {
+            return Err(PathError::NoRoute(from, to));
+        }
+
+        Ok(Route {
+            cost,
+            path: path.into_iter().map(|idx| self.graph[idx]).collect(),
+        })
+    }
 }
 
 const CIRCLE_DIST: f32 = 100.0;
 const ANGULAR_CONSTRAINT: f32 = PI / 9.0;
 const JITTER: f32 = 15.0;
-pub const CITY_COUNTS: [usize; 9] = [3, 4, 4, 5, 8, 12, 15, 20, 15];
 const MIN_CITY_DIST: f32 = 25.0;
 const SCALE: f32 = 1.0;
 
 type CGraph = Graph<Entity, CityEdge, Undirected>;
 
-fn gen_rand_circle(i: i32, min: f32, max: f32, rng: &mut ResMut<GlobalRng>) -> Vec2 {
+fn gen_rand_circle(i: i32, min: f32, max: f32, rng: &mut GlobalRng) -> Vec2 {
     let ang = rng.random_range(min..=max);
     let d = (i + 1) as f32 * CIRCLE_DIST;
     let jx = rng.random_range(-JITTER..JITTER);
@@ -55,21 +293,13 @@ fn gen_rand_circle(i: i32, min: f32, max: f32, rng: &mut ResMut<GlobalRng>) -> V
     Vec2::from_angle(ang) * d + vec2(jx, jy)
 }
 
-pub fn get_path(graph: &Res<CityGraph>, node1: NodeIndex, node2: NodeIndex) -> (f32, Vec<Entity>) {
-    let (cost, mut path) = astar(
-        &graph.graph,
-        node1,
-        |x| x == node2,
-        |e| e.weight().0,
-        |_| 0.0,
-    )
-    .expect(format!("Graph does not connect node {0:?} and {1:?}", node1, node2).as_str());
-
-    let path = path
-        .iter_mut()
-        .map(|node_idx| graph.graph[*node_idx])
-        .collect::<Vec<Entity>>();
-    (cost, path)
+pub fn get_path(
+    graph: &Res<CityGraph>,
+    node1: NodeIndex,
+    node2: NodeIndex,
+) -> Result<(f32, Vec<Entity>), PathError> {
+    let Route { cost, path } = graph.find_route(node1, node2)?;
+    Ok((cost, path))
 }
 
 fn spawn_city(
@@ -80,338 +310,174 @@ fn spawn_city(
     tier: u8,
     capital: bool,
     commands: &mut Commands,
-    mut rng: &mut ResMut<GlobalRng>,
-    g: &mut Graph<Entity, CityEdge, Undirected>,
-    players: Query<&Player>,
+    rng: &mut GlobalRng,
+    g: &mut CityGraph,
 ) {
     let mut ent = commands.spawn_empty();
     info!("spawning node on {}", ent.id());
-    let idx = g.add_node(ent.id());
-    let mut data = CityData::new(name, race, tier, &mut rng, players);
+    let idx = g.add_city(ent.id(), pos);
+    let mut data = CityData::new(name, race, tier, rng);
     let mut empty_market: HashMap<Resources, isize> = HashMap::new();
     for res in Resources::all_resources() {
         empty_market.insert(res, 0);
     }
-    let mut empty_warehouses = HashMap::new();
-    for player in players {
-        empty_warehouses.insert(player.player_id, empty_market.clone());
-    }
+    let empty_warehouses = HashMap::new();
     if capital {
         data = match race {
             BuildingType::Dwarven => CityData {
                 id: "Terez-e-Palaz".to_string(),
                 race: BuildingType::Dwarven,
                 population: 5,
-                buildings_t1: vec![
-                    ("Gem Cutters".to_string(), Faction::Neutral, (false, false)),
-                    ("Gem Cutters".to_string(), Faction::Neutral, (false, false)),
-                    (
-                        "Standard Mines".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Standard Mines".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Standard Mines".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                ],
-                buildings_t2: vec![
-                    ("Growth Vats".to_string(), Faction::Neutral, (false, false)),
-                    ("Core Drill".to_string(), Faction::Neutral, (false, false)),
-                    (
-                        "Preparatory Facilities".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Educated Workers".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                ],
-                buildings_t3: vec![
-                    (
-                        "Automation Components".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Megabreweries".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Megabreweries".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                ],
-                buildings_t4: vec![
-                    (
-                        "Industrial Smeltery".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Dwarven Assembly Lines".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                ],
-                buildings_t5: vec![(
-                    "The Great Red Forges".to_string(),
-                    Faction::Neutral,
-                    (false, false),
-                )],
+                buildings: Buildings::from([
+                    vec![
+                        BuildingSlot::neutral("Gem Cutters"),
+                        BuildingSlot::neutral("Gem Cutters"),
+                        BuildingSlot::neutral("Standard Mines"),
+                        BuildingSlot::neutral("Standard Mines"),
+                        BuildingSlot::neutral("Standard Mines"),
+                    ],
+                    vec![
+                        BuildingSlot::neutral("Growth Vats"),
+                        BuildingSlot::neutral("Core Drill"),
+                        BuildingSlot::neutral("Preparatory Facilities"),
+                        BuildingSlot::neutral("Educated Workers"),
+                    ],
+                    vec![
+                        BuildingSlot::neutral("Automation Components"),
+                        BuildingSlot::neutral("Megabreweries"),
+                        BuildingSlot::neutral("Megabreweries"),
+                    ],
+                    vec![
+                        BuildingSlot::neutral("Industrial Smeltery"),
+                        BuildingSlot::neutral("Dwarven Assembly Lines"),
+                    ],
+                    vec![BuildingSlot::neutral("The Great Red Forges")],
+                ]),
+                wonder: None,
                 market: empty_market,
+                black_market: HashMap::new(),
+                raids: vec![],
+                imports: HashMap::new(),
                 warehouses: empty_warehouses,
+                construction: vec![],
+                payouts: vec![],
                 tier_up_counter: 0,
             },
             BuildingType::Elven => CityData {
                 id: "Jewel of All Creation".to_string(),
                 race: BuildingType::Elven,
                 population: 5,
-                buildings_t1: vec![
-                    (
-                        "Earth Spirit Aid".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Ironwood Forestry".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Forest Foraging".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Standard Mines".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Standard Mines".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                ],
-                buildings_t2: vec![
-                    (
-                        "Amber Plantations".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Amber Plantations".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Gardens of Wonder".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Gardens of Wonder".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                ],
-                buildings_t3: vec![
-                    (
-                        "Integrated Farms".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Elemental Springs".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Basic Industry".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                ],
-                buildings_t4: vec![
-                    (
-                        "Gaian Meadows".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Self-spinning Weavers".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                ],
-                buildings_t5: vec![(
-                    "Tower of the Luminous Science".to_string(),
-                    Faction::Neutral,
-                    (false, false),
-                )],
+                buildings: Buildings::from([
+                    vec![
+                        BuildingSlot::neutral("Earth Spirit Aid"),
+                        BuildingSlot::neutral("Ironwood Forestry"),
+                        BuildingSlot::neutral("Forest Foraging"),
+                        BuildingSlot::neutral("Standard Mines"),
+                        BuildingSlot::neutral("Standard Mines"),
+                    ],
+                    vec![
+                        BuildingSlot::neutral("Amber Plantations"),
+                        BuildingSlot::neutral("Amber Plantations"),
+                        BuildingSlot::neutral("Gardens of Wonder"),
+                        BuildingSlot::neutral("Gardens of Wonder"),
+                    ],
+                    vec![
+                        BuildingSlot::neutral("Integrated Farms"),
+                        BuildingSlot::neutral("Elemental Springs"),
+                        BuildingSlot::neutral("Basic Industry"),
+                    ],
+                    vec![
+                        BuildingSlot::neutral("Gaian Meadows"),
+                        BuildingSlot::neutral("Self-spinning Weavers"),
+                    ],
+                    vec![BuildingSlot::neutral("Tower of the Luminous Science")],
+                ]),
+                wonder: None,
                 market: empty_market,
+                black_market: HashMap::new(),
+                raids: vec![],
+                imports: HashMap::new(),
                 warehouses: empty_warehouses,
+                construction: vec![],
+                payouts: vec![],
                 tier_up_counter: 0,
             },
             BuildingType::Goblin => CityData {
                 id: "Tevet Pekhep Dered".to_string(),
                 race: BuildingType::Goblin,
                 population: 5,
-                buildings_t1: vec![
-                    ("Deep Mines".to_string(), Faction::Neutral, (false, false)),
-                    ("Deep Mines".to_string(), Faction::Neutral, (false, false)),
-                    (
-                        "Animated Objects".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Alchemical Enhancements".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Alchemical Enhancements".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                ],
-                buildings_t2: vec![
-                    ("Glaziery".to_string(), Faction::Neutral, (false, false)),
-                    ("Glaziery".to_string(), Faction::Neutral, (false, false)),
-                    (
-                        "Charcoal Kilns".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Hill Quarries".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                ],
-                buildings_t3: vec![
-                    (
-                        "Artisan District".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    ("Trains".to_string(), Faction::Neutral, (false, false)),
-                    (
-                        "Apothecary's Workshop".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                ],
-                buildings_t4: vec![
-                    (
-                        "Siege-Factories".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Golem Automatons".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                ],
-                buildings_t5: vec![(
-                    "Cauldronworks of the Four Clans".to_string(),
-                    Faction::Neutral,
-                    (false, false),
-                )],
+                buildings: Buildings::from([
+                    vec![
+                        BuildingSlot::neutral("Deep Mines"),
+                        BuildingSlot::neutral("Deep Mines"),
+                        BuildingSlot::neutral("Animated Objects"),
+                        BuildingSlot::neutral("Alchemical Enhancements"),
+                        BuildingSlot::neutral("Alchemical Enhancements"),
+                    ],
+                    vec![
+                        BuildingSlot::neutral("Glaziery"),
+                        BuildingSlot::neutral("Glaziery"),
+                        BuildingSlot::neutral("Charcoal Kilns"),
+                        BuildingSlot::neutral("Hill Quarries"),
+                    ],
+                    vec![
+                        BuildingSlot::neutral("Artisan District"),
+                        BuildingSlot::neutral("Trains"),
+                        BuildingSlot::neutral("Apothecary's Workshop"),
+                    ],
+                    vec![
+                        BuildingSlot::neutral("Siege-Factories"),
+                        BuildingSlot::neutral("Golem Automatons"),
+                    ],
+                    vec![BuildingSlot::neutral("Cauldronworks of the Four Clans")],
+                ]),
+                wonder: None,
                 market: empty_market,
+                black_market: HashMap::new(),
+                raids: vec![],
+                imports: HashMap::new(),
                 warehouses: empty_warehouses,
+                construction: vec![],
+                payouts: vec![],
                 tier_up_counter: 0,
             },
             BuildingType::Human => CityData {
                 id: "Great Lancastershire".to_string(),
                 race: BuildingType::Human,
                 population: 5,
-                buildings_t1: vec![
-                    (
-                        "Large Industrial District".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Large Industrial District".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    ("Fishing Port".to_string(), Faction::Neutral, (false, false)),
-                    ("Fishing Port".to_string(), Faction::Neutral, (false, false)),
-                    (
-                        "Tree Plantations".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                ],
-                buildings_t2: vec![
-                    (
-                        "Water Cleaning Facilities".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Water Cleaning Facilities".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Hired Workforces".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Small-scale Forges".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                ],
-                buildings_t3: vec![
-                    (
-                        "Manufactories".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Mercenary Guild".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    (
-                        "Apothecary's Workshop".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                ],
-                buildings_t4: vec![
-                    (
-                        "Teleportation Circle Network".to_string(),
-                        Faction::Neutral,
-                        (false, false),
-                    ),
-                    ("Strip Mines".to_string(), Faction::Neutral, (false, false)),
-                ],
-                buildings_t5: vec![(
-                    "Sunstrider Headquarters".to_string(),
-                    Faction::Neutral,
-                    (false, false),
-                )],
+                buildings: Buildings::from([
+                    vec![
+                        BuildingSlot::neutral("Large Industrial District"),
+                        BuildingSlot::neutral("Large Industrial District"),
+                        BuildingSlot::neutral("Fishing Port"),
+                        BuildingSlot::neutral("Fishing Port"),
+                        BuildingSlot::neutral("Tree Plantations"),
+                    ],
+                    vec![
+                        BuildingSlot::neutral("Water Cleaning Facilities"),
+                        BuildingSlot::neutral("Water Cleaning Facilities"),
+                        BuildingSlot::neutral("Hired Workforces"),
+                        BuildingSlot::neutral("Small-scale Forges"),
+                    ],
+                    vec![
+                        BuildingSlot::neutral("Manufactories"),
+                        BuildingSlot::neutral("Mercenary Guild"),
+                        BuildingSlot::neutral("Apothecary's Workshop"),
+                    ],
+                    vec![
+                        BuildingSlot::neutral("Teleportation Circle Network"),
+                        BuildingSlot::neutral("Strip Mines"),
+                    ],
+                    vec![BuildingSlot::neutral("Sunstrider Headquarters")],
+                ]),
+                wonder: None,
                 market: empty_market,
+                black_market: HashMap::new(),
+                raids: vec![],
+                imports: HashMap::new(),
                 warehouses: empty_warehouses,
+                construction: vec![],
+                payouts: vec![],
                 tier_up_counter: 0,
             },
             _ => {
@@ -430,47 +496,32 @@ fn spawn_city(
     ));
 }
 
-fn setup(
-    mut rng: ResMut<GlobalRng>,
+pub(super) fn setup(
+    mut streams: ResMut<MapRng>,
     mut commands: Commands,
-    players: Query<&Player>,
     namelists: ResMut<CityNameList>,
+    map: Res<MapDefinition>,
 ) {
-    let vec2 = |x, y| Vec2::new(x, y);
     let mut namelists = namelists.0.clone();
-
-    let mut g = Graph::new_undirected();
-
-    const M: f32 = 2000.0 - 110.0;
-
-    let rect = |a, b, c, d| Rect {
-        min: vec2(a as f32, b as f32),
-        max: vec2(c as f32, d as f32),
-    };
-
-    let map_rect = Rect {
-        min: -vec2(M, M),
-        max: vec2(M, M),
+    let MapRng {
+        positions,
+        buildings,
+        ..
+    } = &mut *streams;
+
+    let mut g = CityGraph::default();
+
+    let terrain = map
+        .procedural
+        .map(|_| TerrainMap::generate(map.half_extent, positions));
+    let capitals = match (&terrain, map.procedural) {
+        (Some(terrain), Some(procedural)) => terrain.place_capitals(procedural.races, positions),
+        _ => map.capitals.clone(),
     };
-
-    let lake_rects = [
-        rect(-430, 620, 330, 950),
-        rect(-700, 340, 360, 620),
-        rect(-650, 220, 160, 340),
-        rect(-650, 70, -30, 220),
-        rect(-390, -40, -250, 70),
-        rect(-250, -270, 130, 70),
-        rect(1060, 1200, 1750, 1650),
-        rect(1220, 800, 1640, 1200),
-    ];
-
-    let check_boxes = |p| {
-        let contains = map_rect.contains(p);
-        let mut not_underwater = true;
-        for rect in lake_rects {
-            not_underwater = not_underwater && !rect.contains(p);
-        }
-        contains && not_underwater
+    // Procedural races keep to their own territory, painted maps are tuned by hand
+    let can_settle = |p: Vec2, capital: usize| match &terrain {
+        Some(terrain) => terrain.is_passable(p) && territory_of(p, &capitals) == Some(capital),
+        None => map.is_passable(p),
     };
 
     let colors = [0.0, 30.0, 60.0, 90.0, 120.0, 150.0, 180.0];
@@ -479,12 +530,9 @@ fn setup(
 
     let mut other_pos = Vec::new();
 
-    for (race, capital_pos) in [
-        (BuildingType::Goblin, vec2(635., -1460.)),
-        (BuildingType::Human, vec2(20., -150.)),
-        (BuildingType::Elven, vec2(35., 1400.)),
-        (BuildingType::Dwarven, vec2(-1495., 1100.)),
-    ] {
+    for (capital_idx, capital) in capitals.iter().enumerate() {
+        let race = capital.race;
+        let capital_pos = capital.position();
         spawn_city(
             "".to_string(),
             capital_pos * SCALE,
@@ -493,17 +541,13 @@ fn setup(
             5,
             true,
             &mut commands,
-            &mut rng,
+            buildings,
             &mut g,
-            players,
         );
 
-        let (min, max) = match race {
-            BuildingType::Dwarven => (-(260f32.to_radians()), 0.0),
-            _ => (-PI, PI),
-        };
+        let (min, max) = capital.arc();
 
-        for (c, j) in CITY_COUNTS.into_iter().enumerate() {
+        for (c, j) in map.rings.iter().copied().enumerate() {
             other_pos.clear();
             let race_idx = match race {
                 BuildingType::Dwarven => 0,
@@ -516,7 +560,7 @@ fn setup(
             };
 
             for _ in 0..j {
-                let mut city_pos = capital_pos + gen_rand_circle(c as i32, min, max, &mut rng);
+                let mut city_pos = capital_pos + gen_rand_circle(c as i32, min, max, positions);
                 let mut attempts = 0;
                 'x: loop {
                     attempts += 1;
@@ -526,52 +570,84 @@ fn setup(
                     }
                     for v in &other_pos {
                         if city_pos.distance(*v) < MIN_CITY_DIST {
-                            city_pos = capital_pos + gen_rand_circle(c as i32, min, max, &mut rng);
+                            city_pos = capital_pos + gen_rand_circle(c as i32, min, max, positions);
                             continue 'x;
                         }
                     }
                     break;
                 }
-                if check_boxes(city_pos) {
-                    let tier = match c {
-                        0 => 4,
-                        1..3 => 3,
-                        3..5 => 2,
-                        _ => 1,
-                    };
+                if can_settle(city_pos, capital_idx) {
+                    let tier = map.tier_of_ring(c);
                     other_pos.push(city_pos);
                     //println!("Missing a city spawn");
+                    let Some(name) = namelists[race_idx].pop() else {
+                        error!("Ran out of {race:?} city names");
+                        continue;
+                    };
                     spawn_city(
-                        namelists[race_idx].pop().unwrap(),
+                        name,
                         city_pos,
                         make_color(colors[(1 + c) % colors.len()]),
                         race,
                         tier,
                         false,
                         &mut commands,
-                        &mut rng,
+                        buildings,
                         &mut g,
-                        players,
                     );
                 }
             }
         }
     }
 
-    commands.insert_resource(CityGraph { graph: g });
+    commands.insert_resource(g);
+    match terrain {
+        Some(terrain) => commands.insert_resource(terrain),
+        None => commands.remove_resource::<TerrainMap>(),
+    }
+}
+
+/// Reports cities the map definition put somewhere they shouldn't be.
+fn check_generated_map(
+    map: Res<MapDefinition>,
+    g: Res<CityGraph>,
+    terrain: Option<Res<TerrainMap>>,
+    cities: Query<(&Node, &CityData)>,
+) {
+    for issue in map.validate_generated(&g, terrain.as_deref(), cities.iter()) {
+        warn!("{issue}");
+    }
 }
 
-fn gizmo_nodes(mut gizmos: Gizmos, nodes: Query<&Node>, g: Res<CityGraph>) {
+fn gizmo_nodes(
+    mut gizmos: Gizmos,
+    nodes: Query<&Node>,
+    cities: Query<(&Node, &CityData)>,
+    g: Res<CityGraph>,
+) {
     /*    for n in &nodes {
         gizmos.circle_2d(n.1, 5.0, n.2);
     }*/
 
-    let g = &g.graph;
-    for n1 in &nodes {
-        for neighbor in g.neighbors(n1.0) {
-            let n2 = nodes.get(g[neighbor]).expect("lol");
+    // Mark the cities holding a wonder
+    for (n, _) in cities.iter().filter(|(_, city)| city.wonder.is_some()) {
+        gizmos.circle_2d(n.1, 14.0, Color::srgb(1.0, 0.84, 0.0));
+        gizmos.circle_2d(n.1, 18.0, Color::srgb(1.0, 0.84, 0.0));
+    }
 
-            gizmos.line_2d(n1.1, n2.1, Color::linear_rgb(0.55, 0.27, 0.075));
+    let g = &g.graph;
+    for edge in g.edge_references() {
+        let Ok([n1, n2]) = nodes.get_many([g[edge.source()], g[edge.target()]]) else {
+            continue;
+        };
+        let (a, b) = (n1.1, n2.1);
+        let color = edge.weight().road.color();
+        gizmos.line_2d(a, b, color);
+        // Upgraded roads are drawn wider
+        if matches!(edge.weight().road, RoadType::Road | RoadType::Highway) {
+            let offset = (b - a).perp().normalize_or_zero() * 1.5;
+            gizmos.line_2d(a + offset, b + offset, color);
+            gizmos.line_2d(a - offset, b - offset, color);
         }
     }
 }
@@ -613,16 +689,28 @@ fn intersect([a, b, c, d]: [Vec2; 4]) -> bool {
     ccw(a, c, d) != ccw(b, c, d) && ccw(a, b, c) != ccw(a, b, d)
 }
 
-fn gen_edges(nodes: Query<&Node>, mut g: ResMut<CityGraph>) {
+pub(super) fn gen_edges(
+    nodes: Query<&Node>,
+    mut g: ResMut<CityGraph>,
+    terrain: Option<Res<TerrainMap>>,
+    map: Res<MapDefinition>,
+) {
     let g = &mut g.graph;
 
-    let mut all_nodes: Vec<_> = nodes.iter().collect();
+    // Query order isn't guaranteed to match between peers, node indices are
+    let mut ordered: Vec<_> = nodes.iter().collect();
+    ordered.sort_by_key(|n| n.0);
+    let mut all_nodes = ordered.clone();
     let mut scratch = Vec::new();
 
-    for n in &nodes {
+    for n in ordered {
         scratch.clear();
 
-        all_nodes.sort_by_key(|&Node(_, t2, _)| (n.1.distance(*t2) * 1_000_000.0) as u64);
+        all_nodes.sort_by(|a, b| {
+            n.1.distance(a.1)
+                .total_cmp(&n.1.distance(b.1))
+                .then(a.0.cmp(&b.0))
+        });
 
         'outer: for other in all_nodes.iter().skip(1).take(10) {
             if scratch.len() > 3 {
@@ -640,44 +728,145 @@ fn gen_edges(nodes: Query<&Node>, mut g: ResMut<CityGraph>) {
                 continue;
             } else {
                 scratch.push(other.1 - n.1);
-                g.add_edge(n.0, other.0, CityEdge(n.1.distance(other.1)));
+                let terrain = match &terrain {
+                    Some(terrain) => terrain.roughest_between(n.1, other.1),
+                    None => map.roughest_between(n.1, other.1),
+                };
+                let distance = n.1.distance(other.1);
+                g.add_edge(
+                    n.0,
+                    other.0,
+                    CityEdge {
+                        distance,
+                        terrain,
+                        road: RoadType::natural(terrain, distance),
+                    },
+                );
             }
         }
     }
 }
 
-fn remove_random_edges(mut rng: ResMut<GlobalRng>, mut g: ResMut<CityGraph>) {
-    const REMOVAL_FACTOR: f64 = 0.25;
-
-    let g = &mut g.graph;
-    let starting_components = connected_components(&*g);
-
-    let mut g_test = CGraph::new_undirected();
-    g_test.clone_from(g);
+pub(super) fn remove_random_edges(
+    mut streams: ResMut<MapRng>,
+    mut g: ResMut<CityGraph>,
+    map: Res<MapDefinition>,
+) {
+    prune_edges(&mut g.graph, map.removal_factor, &mut streams.positions.0);
+    g.rebuild_routes();
+}
 
+/// Thins the road network by removing `removal_factor` of the edges at random,
+/// never one of a random spanning forest, so every city keeps the connections
+/// it had before.
+fn prune_edges(g: &mut CGraph, removal_factor: f64, rng: &mut impl Rng) {
     let mut edges: Vec<_> = g.edge_indices().collect();
     edges.sort();
-    let mut removed_edges = 0;
-    let required = (REMOVAL_FACTOR * edges.len() as f64) as usize;
-
-    while removed_edges < required {
-        // try removing an edge
-        let Some(&random_edge) = edges.choose(&mut rng) else {
-            error!("This fucking blows dude");
-            return;
-        };
-        let Some((_n1, _n2)) = g.edge_endpoints(random_edge) else {
+    edges.shuffle(rng);
+    let required = (removal_factor * edges.len() as f64) as usize;
+
+    // Kruskal's algorithm over the shuffled edges keeps a random spanning
+    // forest, anything outside of it can go without splitting the graph
+    let mut forest = UnionFind::new(g.node_count());
+    let mut removable = HashSet::new();
+    for &edge in &edges {
+        let Some((n1, n2)) = g.edge_endpoints(edge) else {
             continue;
         };
-        g_test.remove_edge(random_edge);
-        // where performance goes to die
-        if connected_components(&g_test) == starting_components {
-            g.remove_edge(random_edge);
-            edges.pop();
-            removed_edges += 1;
-        } else {
-            info!("Failed at removing edge, retrying");
-            g_test.clone_from(g);
+        if !forest.union(n1.index(), n2.index()) && removable.len() < required {
+            removable.insert(edge);
+        }
+    }
+    if removable.len() < required {
+        info!(
+            "Only {} of {required} edges could be removed without splitting the map",
+            removable.len()
+        );
+    }
+
+    g.retain_edges(|_, edge| !removable.contains(&edge));
+}
+
+#[cfg(test)]
+mod tests {
+    use petgraph::algo::connected_components;
+    use rand_xoshiro::Xoshiro256StarStar;
+
+    use super::*;
+
+    /// Two clusters of randomly joined cities and one city on its own.
+    fn islands(rng: &mut impl Rng) -> CGraph {
+        let mut g = CGraph::default();
+        let nodes: Vec<_> = (0..17).map(|_| g.add_node(Entity::PLACEHOLDER)).collect();
+        for cluster in [&nodes[0..10], &nodes[10..16]] {
+            for (i, a) in cluster.iter().enumerate() {
+                for b in &cluster[i + 1..] {
+                    if rng.random_bool(0.6) {
+                        let road = CityEdge {
+                            distance: 1.0,
+                            terrain: Terrain::Plains,
+                            road: RoadType::Trail,
+                        };
+                        g.add_edge(*a, *b, road);
+                    }
+                }
+            }
+        }
+        g
+    }
+
+    #[test]
+    fn pruning_keeps_components() {
+        for seed in 0..8 {
+            for removal_factor in [0.0, 0.25, 0.5, 0.9, 1.0] {
+                let mut rng = Xoshiro256StarStar::seed_from_u64(seed);
+                let mut g = islands(&mut rng);
+                let components = connected_components(&g);
+                let edges = g.edge_count();
+                // Everything outside a spanning forest can go
+                let spare = edges - (g.node_count() - components);
+                let removed = ((removal_factor * edges as f64) as usize).min(spare);
+
+                prune_edges(&mut g, removal_factor, &mut rng);
+
+                let case = format!("seed {seed}, removal factor {removal_factor}");
+                assert_eq!(connected_components(&g), components, "{case}");
+                assert_eq!(g.edge_count(), edges - removed, "{case}");
+            }
         }
     }
+
+    #[test]
+    fn routes_go_around_blockades() {
+        let mut graph = CityGraph::default();
+        let cities: Vec<_> = (0..4)
+            .map(|i| Entity::from_raw_u32(i).expect("valid entity"))
+            .collect();
+        let [a, b, c, d] = [0, 1, 2, 3].map(|i| graph.add_city(cities[i], Vec2::ZERO));
+        let road = |distance| CityEdge {
+            distance,
+            terrain: Terrain::Plains,
+            road: RoadType::Trail,
+        };
+        let ab = graph.graph.add_edge(a, b, road(10.0));
+        graph.graph.add_edge(b, c, road(10.0));
+        let ad = graph.graph.add_edge(a, d, road(20.0));
+        graph.graph.add_edge(d, c, road(20.0));
+        graph.rebuild_routes();
+
+        let short = graph.find_route(a, c).expect("route");
+        assert_eq!(short.path, vec![cities[0], cities[1], cities[2]]);
+
+        graph.set_blockade(ab, true);
+        let detour = graph.find_route(a, c).expect("route");
+        assert_eq!(detour.path, vec![cities[0], cities[3], cities[2]]);
+        assert!(detour.cost > short.cost);
+
+        graph.set_blockade(ad, true);
+        assert_eq!(graph.find_route(a, c), Err(PathError::NoRoute(a, c)));
+
+        // The failure isn't remembered once the road opens again
+        graph.set_blockade(ab, false);
+        assert_eq!(graph.find_route(a, c), Ok(short));
+    }
 }
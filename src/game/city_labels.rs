@@ -0,0 +1,165 @@
+//! The cities' markers and labels on the map, kept in step with each city's
+//! tier and the player controlling it.
+
+use bevy_ui_anchor::AnchoredUiNodes;
+
+use super::city_data::CityData;
+use super::market::BuildingType;
+use super::strategic_map::{CityImageMarker, CityNodeMarker, Faction};
+use super::tooltip::{TooltipOf, Tooltips};
+use crate::GameState;
+use crate::network::message::PlayerId;
+use crate::prelude::*;
+
+/// Cities from this tier on get the large marker.
+const LARGE_MARKER_TIER: u8 = 3;
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(
+        Update,
+        refresh_city_markers
+            .run_if(in_state(GameState::Game).and(any_match_filter::<Changed<CityData>>)),
+    );
+}
+
+/// Name, tier and race of a city, drawn under its marker, with the tier and
+/// controller it was last drawn for.
+#[derive(Component, Default, Clone, Debug)]
+pub struct CityLabel {
+    shown: Option<(u8, Faction)>,
+}
+
+/// The color players are shown in, matching their player sprites.
+pub fn player_color(player_id: PlayerId) -> Color {
+    match player_id {
+        0 => Color::srgb(0.85, 0.15, 0.15),
+        1 => Color::srgb(0.2, 0.35, 0.9),
+        2 => Color::srgb(0.2, 0.75, 0.25),
+        3 => Color::srgb(0.95, 0.85, 0.2),
+        _ => Color::srgb(0.6, 0.25, 0.8),
+    }
+}
+
+pub fn race_color(race: BuildingType) -> Color {
+    match race {
+        BuildingType::Human => Color::srgb(0.8, 0.65, 0.45),
+        BuildingType::Elven => Color::srgb(0.3, 0.65, 0.35),
+        BuildingType::Goblin => Color::srgb(0.55, 0.6, 0.15),
+        BuildingType::Dwarven => Color::srgb(0.6, 0.35, 0.2),
+        _ => Color::srgb(0.5, 0.5, 0.5),
+    }
+}
+
+fn describe_city(city: &CityData) -> String {
+    match city.population {
+        0..3 => format!("{:?} town", city.race),
+        3..6 => format!("{:?} city", city.race),
+        _ => format!("GREAT AREA OF {:?} (error in tooltip code btw)", city.race),
+    }
+}
+
+fn describe_controller(city: &CityData) -> String {
+    match city.controller() {
+        Faction::Player(player_id) => format!("Controlled by player {player_id}"),
+        Faction::Neutral => "Nobody controls this city".to_string(),
+    }
+}
+
+fn text_node(text: String) -> impl Bundle {
+    (
+        Text::new(text),
+        TextLayout::new_with_justify(Justify::Center),
+        Node { ..default() },
+        BackgroundColor(Srgba::new(0.05, 0.05, 0.05, 1.0).into()),
+    )
+}
+
+/// The first letter of the race's name on the race's color.
+fn race_icon(race: BuildingType) -> impl Bundle {
+    let initial = format!("{race:?}").chars().next().unwrap_or('?');
+    (
+        Node {
+            width: px(18),
+            height: px(18),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        BorderRadius::MAX,
+        BackgroundColor(race_color(race)),
+        children![(
+            Text::new(initial.to_string()),
+            TextFont::from_font_size(12.0),
+            TextColor(Color::BLACK),
+        )],
+    )
+}
+
+fn refresh_city_markers(
+    mut commands: Commands,
+    mut sylt: Sylt,
+    cities: Query<(&CityData, &AnchoredUiNodes), Changed<CityData>>,
+    mut images: Query<&mut ImageNode, With<CityImageMarker>>,
+    mut labels: Query<(&mut CityLabel, &mut BorderColor)>,
+    tooltips: Query<&Tooltips, With<CityNodeMarker>>,
+) {
+    for (city, nodes) in &cities {
+        let state = (city.population, city.controller());
+        let Some(label_ent) = nodes
+            .collection()
+            .iter()
+            .copied()
+            .find(|node| labels.contains(*node))
+        else {
+            continue;
+        };
+        let Ok((mut label, mut border)) = labels.get_mut(label_ent) else {
+            continue;
+        };
+        // Markets change every turn, the marker only on tier or controller
+        if label.shown == Some(state) {
+            continue;
+        }
+        label.shown = Some(state);
+
+        *border = BorderColor::all(match state.1 {
+            Faction::Player(player_id) => player_color(player_id),
+            Faction::Neutral => Color::BLACK,
+        });
+        commands
+            .entity(label_ent)
+            .despawn_related::<Children>()
+            .with_children(|label| {
+                label.spawn(race_icon(city.race));
+                label.spawn((
+                    Text::new(format!("{} (tier {})", city.id, city.population)),
+                    TextFont::from_font_size(14.0),
+                ));
+            });
+
+        for node in nodes.collection() {
+            if let Ok(mut image) = images.get_mut(*node) {
+                image.image = sylt.get_image(if city.population >= LARGE_MARKER_TIER {
+                    "town_ui_icon"
+                } else {
+                    "town_map_icon"
+                });
+            }
+
+            if let Ok(old) = tooltips.get(*node) {
+                for tooltip in old.iter() {
+                    commands.entity(tooltip).despawn();
+                }
+                let target = *node;
+                for line in [
+                    city.id.clone(),
+                    format!("Tier: {}", city.population),
+                    describe_city(city),
+                    describe_controller(city),
+                ] {
+                    commands.spawn((text_node(line), TooltipOf { target }));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! What each player knows about the cities' markets. Players only see live
+//! prices where they have a caravan, a building or a warehouse, or where one of
+//! their caravans was a few turns ago. Everywhere else they get the last prices
+//! they saw.
+
+use std::collections::{HashMap, HashSet};
+
+use super::city_data::CityData;
+use super::strategic_map::{Caravan, Owns, Player};
+use super::turn::Turn;
+use super::warehouse::Tenure;
+use crate::network::message::PlayerId;
+use crate::prelude::*;
+
+/// Turns a city stays in view after a player's last caravan left it.
+pub const VISIT_MEMORY: u64 = 3;
+
+pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<MarketIntel>().add_systems(
+        Update,
+        refresh_intel.run_if(
+            resource_changed::<Turn>
+                .or(any_match_filter::<Changed<Caravan>>)
+                .or(any_match_filter::<Changed<CityData>>),
+        ),
+    );
+}
+
+/// A copy of a city as a player last saw it.
+#[derive(Clone, Debug)]
+pub struct Sighting {
+    pub city: CityData,
+    pub turn: u64,
+}
+
+pub enum MarketView<'a> {
+    Live,
+    Stale { sighting: &'a Sighting, age: u64 },
+    Unknown,
+}
+
+impl MarketView<'_> {
+    /// The city as the player knows it, `live` being the real one.
+    pub fn known<'a>(&'a self, live: &'a CityData) -> Option<&'a CityData> {
+        match self {
+            MarketView::Live => Some(live),
+            MarketView::Stale { sighting, .. } => Some(&sighting.city),
+            MarketView::Unknown => None,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            MarketView::Live => "Up to date".to_string(),
+            MarketView::Stale { age, .. } => format!("Last seen {age} turns ago"),
+            MarketView::Unknown => "Nobody of yours has been here".to_string(),
+        }
+    }
+}
+
+#[derive(Resource, Default, Debug)]
+pub struct MarketIntel {
+    turn: u64,
+    /// Cities each player has a caravan, building or warehouse in.
+    present: HashMap<PlayerId, HashSet<String>>,
+    /// Last turn each player was present in a city.
+    last_visit: HashMap<PlayerId, HashMap<String, u64>>,
+    sightings: HashMap<PlayerId, HashMap<String, Sighting>>,
+    /// Cities each player saw at the last refresh, whose sightings only need a
+    /// new date unless the city changed since.
+    watched: HashMap<PlayerId, HashSet<String>>,
+}
+
+impl MarketIntel {
+    pub fn is_live(&self, player: PlayerId, city_id: &str) -> bool {
+        self.present
+            .get(&player)
+            .is_some_and(|cities| cities.contains(city_id))
+            || self
+                .last_visit
+                .get(&player)
+                .and_then(|visits| visits.get(city_id))
+                .is_some_and(|turn| self.turn.saturating_sub(*turn) <= VISIT_MEMORY)
+    }
+
+    pub fn view(&self, player: PlayerId, city_id: &str) -> MarketView<'_> {
+        if self.is_live(player, city_id) {
+            return MarketView::Live;
+        }
+        match self
+            .sightings
+            .get(&player)
+            .and_then(|sightings| sightings.get(city_id))
+        {
+            Some(sighting) => MarketView::Stale {
+                sighting,
+                age: self.turn.saturating_sub(sighting.turn),
+            },
+            None => MarketView::Unknown,
+        }
+    }
+}
+
+/// Whether a player has a building or a warehouse in the city.
+fn has_property(city: &CityData, player: PlayerId) -> bool {
+    city.buildings.owned_by(player).next().is_some()
+        || city
+            .warehouse(player)
+            .is_some_and(|w| w.tenure != Tenure::None)
+}
+
+fn refresh_intel(
+    mut intel: ResMut<MarketIntel>,
+    turn: Res<Turn>,
+    players: Query<(&Player, Option<&Owns>)>,
+    caravans: Query<&Caravan>,
+    cities: Query<Ref<CityData>>,
+) {
+    let intel = &mut *intel;
+    intel.turn = **turn;
+    for (player, owned) in &players {
+        let player_id = player.player_id;
+        let mut present: HashSet<String> = owned
+            .iter()
+            .flat_map(|owned| owned.collection())
+            .filter_map(|ent| caravans.get(*ent).ok())
+            .map(|caravan| caravan.position_city_id.clone())
+            .collect();
+        present.extend(
+            cities
+                .iter()
+                .filter(|city| has_property(city, player_id))
+                .map(|city| city.id.clone()),
+        );
+
+        let visits = intel.last_visit.entry(player_id).or_default();
+        for city_id in &present {
+            visits.insert(city_id.clone(), intel.turn);
+        }
+        intel.present.insert(player_id, present);
+
+        let watched = intel.watched.remove(&player_id).unwrap_or_default();
+        let mut watching = HashSet::new();
+        for city in &cities {
+            if !intel.is_live(player_id, &city.id) {
+                continue;
+            }
+            let sightings = intel.sightings.entry(player_id).or_default();
+            match sightings.get_mut(&city.id) {
+                Some(sighting) if watched.contains(&city.id) && !city.is_changed() => {
+                    sighting.turn = intel.turn;
+                }
+                _ => {
+                    let sighting = Sighting {
+                        city: city.clone(),
+                        turn: intel.turn,
+                    };
+                    sightings.insert(city.id.clone(), sighting);
+                }
+            }
+            watching.insert(city.id.clone());
+        }
+        intel.watched.insert(player_id, watching);
+    }
+}
@@ -0,0 +1,460 @@
+//! Map descriptions read from `assets/maps`, so other maps and scenarios can be
+//! shipped without recompiling. Pick one with `--map <path>`.
+
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::PI;
+use std::fmt;
+use std::io;
+
+use petgraph::algo::tarjan_scc;
+use serde::{Deserialize, Serialize};
+
+use super::city_data::CityData;
+use super::city_graph::{CityGraph, Node};
+use super::market::BuildingType;
+use super::terrain::{Terrain, TerrainMap};
+use crate::prelude::*;
+
+pub const DEFAULT_MAP: &str = "assets/maps/default.json";
+/// Distance between the points of a road checked for water on painted maps.
+const ROAD_SAMPLE_STEP: f32 = 16.0;
+
+/// Races that have a capital and a list of city names.
+pub const PLAYABLE_RACES: [BuildingType; 4] = [
+    BuildingType::Dwarven,
+    BuildingType::Elven,
+    BuildingType::Goblin,
+    BuildingType::Human,
+];
+
+pub(super) fn plugin(app: &mut App) {
+    let path = std::env::args()
+        .skip_while(|arg| arg != "--map")
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_MAP.to_string());
+    let map = match MapDefinition::load_checked(&path) {
+        Ok(map) => map,
+        Err(e) if path != DEFAULT_MAP => {
+            error!("Couldn't use the map definition {path}: {e}, using {DEFAULT_MAP} instead");
+            MapDefinition::load_checked(DEFAULT_MAP)
+                .unwrap_or_else(|e| panic!("Couldn't use the map definition {DEFAULT_MAP}: {e}"))
+        }
+        Err(e) => panic!("Couldn't use the map definition {path}: {e}"),
+    };
+    app.insert_resource(map);
+}
+
+#[derive(Resource, Serialize, Deserialize, Clone, Debug)]
+pub struct MapDefinition {
+    /// Sprite drawn under the map, by its name in the asset config.
+    pub background: String,
+    /// Cities are kept inside a square this far from the origin on each side.
+    pub half_extent: f32,
+    pub capitals: Vec<CapitalDefinition>,
+    /// Lakes and other regions no city can be placed in, as polygons.
+    #[serde(default)]
+    pub impassable: Vec<Vec<[f32; 2]>>,
+    /// Cities spawned in each ring around a capital, innermost ring first.
+    pub rings: Vec<usize>,
+    pub tier_bands: Vec<TierBand>,
+    /// Share of the generated roads that are torn up again, between 0 and 1.
+    /// Cities never lose their last way to the rest of the map.
+    #[serde(default = "default_removal_factor")]
+    pub removal_factor: f64,
+    /// Generates terrain and capitals instead of using the background,
+    /// capitals and regions above. The map still spans `half_extent`.
+    #[serde(default)]
+    pub procedural: Option<ProceduralMap>,
+}
+
+fn default_removal_factor() -> f64 {
+    0.25
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct ProceduralMap {
+    /// Races that get a capital and a territory.
+    pub races: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CapitalDefinition {
+    pub race: BuildingType,
+    pub position: [f32; 2],
+    /// Directions in degrees the race's cities spread into, all around when
+    /// missing.
+    #[serde(default)]
+    pub arc: Option<[f32; 2]>,
+}
+
+impl CapitalDefinition {
+    pub fn position(&self) -> Vec2 {
+        Vec2::from(self.position)
+    }
+
+    /// The arc in radians.
+    pub fn arc(&self) -> (f32, f32) {
+        match self.arc {
+            Some([min, max]) => (min.to_radians(), max.to_radians()),
+            None => (-PI, PI),
+        }
+    }
+}
+
+/// Cities in the rings before `until_ring` not covered by an earlier band get
+/// `tier`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct TierBand {
+    pub until_ring: usize,
+    pub tier: u8,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MapIssue {
+    OutOfBounds(String),
+    Underwater(String),
+    Disconnected(Vec<String>),
+    DuplicateRace(BuildingType),
+    UnsupportedRace(BuildingType),
+    BadRegion(usize),
+    BadTier(u8),
+    BadRaceCount(usize),
+    BadRemovalFactor(f64),
+}
+
+impl MapIssue {
+    /// Whether no map can be generated from the definition at all.
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            MapIssue::DuplicateRace(_) | MapIssue::UnsupportedRace(_) | MapIssue::BadTier(_)
+        )
+    }
+}
+
+impl fmt::Display for MapIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapIssue::OutOfBounds(city) => write!(f, "{city} lies outside the map"),
+            MapIssue::Underwater(city) => write!(f, "{city} lies in an impassable region"),
+            MapIssue::Disconnected(cities) => write!(
+                f,
+                "{} can't be reached from the rest of the map",
+                cities.join(", ")
+            ),
+            MapIssue::DuplicateRace(race) => write!(f, "{race:?} has more than one capital"),
+            MapIssue::UnsupportedRace(race) => write!(f, "{race:?} can't have a capital"),
+            MapIssue::BadRegion(idx) => {
+                write!(f, "impassable region {idx} needs at least three corners")
+            }
+            MapIssue::BadTier(tier) => write!(f, "tier {tier} is not between 1 and 5"),
+            MapIssue::BadRaceCount(races) => write!(
+                f,
+                "{races} races don't fit, there are {} with a capital",
+                PLAYABLE_RACES.len()
+            ),
+            MapIssue::BadRemovalFactor(factor) => {
+                write!(f, "removal factor {factor} is not between 0 and 1")
+            }
+        }
+    }
+}
+
+impl MapDefinition {
+    pub fn load(path: &str) -> io::Result<Self> {
+        let f = std::fs::File::open(path)?;
+        let map: MapDefinition = serde_json::from_reader(f)?;
+        Ok(map)
+    }
+
+    /// Loads a map and checks it, warning about its smaller mistakes. Fails on
+    /// mistakes no map can be generated with.
+    pub fn load_checked(path: &str) -> Result<Self, String> {
+        let map = Self::load(path).map_err(|e| e.to_string())?;
+        let (fatal, issues): (Vec<_>, Vec<_>) =
+            map.validate().into_iter().partition(MapIssue::is_fatal);
+        for issue in issues {
+            warn!("{path}: {issue}");
+        }
+        if !fatal.is_empty() {
+            let fatal: Vec<_> = fatal.iter().map(MapIssue::to_string).collect();
+            return Err(fatal.join(", "));
+        }
+        Ok(map)
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect::from_center_half_size(Vec2::ZERO, Vec2::splat(self.half_extent))
+    }
+
+    pub fn is_underwater(&self, p: Vec2) -> bool {
+        self.impassable.iter().any(|region| contains(region, p))
+    }
+
+    /// The roughest terrain a road between `a` and `b` crosses on a painted map:
+    /// water if it runs through an impassable region, plains otherwise.
+    pub fn roughest_between(&self, a: Vec2, b: Vec2) -> Terrain {
+        let steps = (a.distance(b) / ROAD_SAMPLE_STEP).ceil().max(1.0) as usize;
+        let wet = (0..=steps).any(|i| self.is_underwater(a.lerp(b, i as f32 / steps as f32)));
+        if wet { Terrain::Water } else { Terrain::Plains }
+    }
+
+    /// Whether a city may be placed at `p`.
+    pub fn is_passable(&self, p: Vec2) -> bool {
+        self.bounds().contains(p) && !self.is_underwater(p)
+    }
+
+    /// Most cities a single race can get, capital excluded.
+    pub fn cities_per_capital(&self) -> usize {
+        self.rings.iter().sum()
+    }
+
+    pub fn tier_of_ring(&self, ring: usize) -> u8 {
+        self.tier_bands
+            .iter()
+            .find(|band| ring < band.until_ring)
+            .map_or(1, |band| band.tier)
+    }
+
+    /// Mistakes that can be found without generating the map.
+    pub fn validate(&self) -> Vec<MapIssue> {
+        let mut issues = vec![];
+        let mut races = HashSet::new();
+        for capital in &self.capitals {
+            let name = format!("The {:?} capital", capital.race);
+            if !PLAYABLE_RACES.contains(&capital.race) {
+                issues.push(MapIssue::UnsupportedRace(capital.race));
+            } else if !races.insert(capital.race) {
+                issues.push(MapIssue::DuplicateRace(capital.race));
+            }
+            if !self.bounds().contains(capital.position()) {
+                issues.push(MapIssue::OutOfBounds(name));
+            } else if self.is_underwater(capital.position()) {
+                issues.push(MapIssue::Underwater(name));
+            }
+        }
+        for (idx, region) in self.impassable.iter().enumerate() {
+            if region.len() < 3 {
+                issues.push(MapIssue::BadRegion(idx));
+            }
+        }
+        for band in &self.tier_bands {
+            if !(1..=5).contains(&band.tier) {
+                issues.push(MapIssue::BadTier(band.tier));
+            }
+        }
+        if !(0.0..=1.0).contains(&self.removal_factor) {
+            issues.push(MapIssue::BadRemovalFactor(self.removal_factor));
+        }
+        if let Some(procedural) = self.procedural
+            && !(1..=PLAYABLE_RACES.len()).contains(&procedural.races)
+        {
+            issues.push(MapIssue::BadRaceCount(procedural.races));
+        }
+        issues
+    }
+
+    /// Mistakes in a generated map: cities that ended up underwater and parts of
+    /// the road network cut off from the largest one.
+    pub fn validate_generated<'a>(
+        &self,
+        graph: &CityGraph,
+        terrain: Option<&TerrainMap>,
+        cities: impl Iterator<Item = (&'a Node, &'a CityData)>,
+    ) -> Vec<MapIssue> {
+        let mut issues = vec![];
+        let mut names = HashMap::new();
+        for (node, city) in cities {
+            let underwater = match terrain {
+                Some(terrain) => !terrain.is_passable(node.1),
+                None => self.is_underwater(node.1),
+            };
+            if !self.bounds().contains(node.1) {
+                issues.push(MapIssue::OutOfBounds(city.id.clone()));
+            } else if underwater {
+                issues.push(MapIssue::Underwater(city.id.clone()));
+            }
+            names.insert(node.0, city.id.clone());
+        }
+
+        let mut components = tarjan_scc(&graph.graph);
+        components.sort_by_key(|component| std::cmp::Reverse(component.len()));
+        for component in components.iter().skip(1) {
+            let mut cut_off: Vec<_> = component
+                .iter()
+                .filter_map(|idx| names.get(idx).cloned())
+                .collect();
+            cut_off.sort();
+            issues.push(MapIssue::Disconnected(cut_off));
+        }
+        issues
+    }
+}
+
+/// Even-odd test of whether `p` lies inside `polygon`.
+fn contains(polygon: &[[f32; 2]], p: Vec2) -> bool {
+    let mut inside = false;
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        let (a, b) = (Vec2::from(*a), Vec2::from(b));
+        if (a.y > p.y) != (b.y > p.y) && p.x < a.x + (p.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::city_graph::CityEdge;
+    use crate::game::roads::RoadType;
+
+    fn capital(race: BuildingType, position: [f32; 2]) -> CapitalDefinition {
+        CapitalDefinition {
+            race,
+            position,
+            arc: None,
+        }
+    }
+
+    /// Two capitals either side of a lake.
+    fn lake_map() -> MapDefinition {
+        MapDefinition {
+            background: "map".to_string(),
+            half_extent: 1000.0,
+            capitals: vec![
+                capital(BuildingType::Human, [-500.0, 0.0]),
+                capital(BuildingType::Elven, [500.0, 0.0]),
+            ],
+            impassable: vec![vec![[-100.0, -100.0], [100.0, -100.0], [0.0, 100.0]]],
+            rings: vec![4, 6],
+            tier_bands: vec![TierBand {
+                until_ring: 1,
+                tier: 2,
+            }],
+            removal_factor: 0.25,
+            procedural: None,
+        }
+    }
+
+    #[test]
+    fn valid_maps() {
+        assert_eq!(lake_map().validate(), vec![]);
+        let procedural = MapDefinition::load("assets/maps/procedural.json").unwrap();
+        assert_eq!(procedural.validate(), vec![]);
+    }
+
+    #[test]
+    fn capital_in_a_lake() {
+        let mut map = lake_map();
+        map.capitals[1].position = [0.0, 0.0];
+        assert_eq!(
+            map.validate(),
+            vec![MapIssue::Underwater("The Elven capital".to_string())]
+        );
+    }
+
+    #[test]
+    fn two_capitals_of_a_race() {
+        let mut map = lake_map();
+        map.capitals
+            .push(capital(BuildingType::Human, [0.0, 500.0]));
+        map.capitals
+            .push(capital(BuildingType::Unique, [0.0, -500.0]));
+        assert_eq!(
+            map.validate(),
+            vec![
+                MapIssue::DuplicateRace(BuildingType::Human),
+                MapIssue::UnsupportedRace(BuildingType::Unique),
+            ]
+        );
+        assert!(map.validate().iter().all(MapIssue::is_fatal));
+    }
+
+    #[test]
+    fn tiers_out_of_range() {
+        let mut map = lake_map();
+        map.tier_bands = [0, 3, 6]
+            .into_iter()
+            .map(|tier| TierBand {
+                until_ring: 2,
+                tier,
+            })
+            .collect();
+        assert_eq!(
+            map.validate(),
+            vec![MapIssue::BadTier(0), MapIssue::BadTier(6)]
+        );
+    }
+
+    #[test]
+    fn roads_through_a_lake_are_water() {
+        let map = lake_map();
+        let (west, east) = (vec2(-500.0, 0.0), vec2(500.0, 0.0));
+        assert_eq!(map.roughest_between(west, east), Terrain::Water);
+        let north = vec2(0.0, 500.0);
+        assert_eq!(map.roughest_between(west, north), Terrain::Plains);
+    }
+
+    /// Cities named after their index at `positions`, joined by `roads`.
+    fn generate(positions: &[[f32; 2]], roads: &[(usize, usize)]) -> Vec<MapIssue> {
+        let mut graph = CityGraph::default();
+        let mut cities = vec![];
+        for (i, pos) in positions.iter().enumerate() {
+            let pos = Vec2::from(*pos);
+            let idx = graph.add_city(Entity::PLACEHOLDER, pos);
+            let city = CityData {
+                id: format!("City {i}"),
+                ..default()
+            };
+            cities.push((Node(idx, pos, Color::WHITE), city));
+        }
+        for (a, b) in roads.iter().copied() {
+            let road = CityEdge {
+                distance: cities[a].0.1.distance(cities[b].0.1),
+                terrain: Terrain::Plains,
+                road: RoadType::Trail,
+            };
+            graph.graph.add_edge(cities[a].0.0, cities[b].0.0, road);
+        }
+        lake_map().validate_generated(&graph, None, cities.iter().map(|(n, c)| (n, c)))
+    }
+
+    #[test]
+    fn valid_generated_map() {
+        let positions = [[-500.0, 0.0], [0.0, 500.0], [500.0, 0.0]];
+        assert_eq!(generate(&positions, &[(0, 1), (1, 2)]), vec![]);
+    }
+
+    #[test]
+    fn island_city() {
+        let positions = [
+            [-500.0, 0.0],
+            [0.0, 500.0],
+            [-500.0, 500.0],
+            [500.0, 0.0],
+            [500.0, 500.0],
+        ];
+        assert_eq!(
+            generate(&positions, &[(0, 1), (1, 2), (3, 4)]),
+            vec![MapIssue::Disconnected(vec![
+                "City 3".to_string(),
+                "City 4".to_string()
+            ])]
+        );
+        assert_eq!(
+            generate(&positions, &[(0, 1), (1, 2), (2, 3)]),
+            vec![MapIssue::Disconnected(vec!["City 4".to_string()])]
+        );
+    }
+
+    #[test]
+    fn city_in_the_lake() {
+        let positions = [[-500.0, 0.0], [0.0, 0.0], [500.0, 0.0]];
+        assert_eq!(
+            generate(&positions, &[(0, 1), (1, 2)]),
+            vec![MapIssue::Underwater("City 1".to_string())]
+        );
+    }
+}
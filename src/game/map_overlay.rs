@@ -0,0 +1,228 @@
+//! Optional layers drawn over the map: a heatmap of what one resource costs in
+//! each city, as far as the active player knows, and the buildings every player
+//! holds in them, as last seen.
+
+use super::city_data::CityData;
+use super::city_graph::Node as CityNode;
+use super::city_labels::player_color;
+use super::intel::{MarketIntel, MarketView};
+use super::market::Resources;
+use super::strategic_map::{ActivePlayer, Player};
+use crate::GameState;
+use crate::prelude::*;
+
+const HEAT_RADIUS: f32 = 24.0;
+/// Space between the rings of two players around a city.
+const PRESENCE_SPACING: f32 = 6.0;
+/// Buildings beyond this don't thicken a player's ring any further.
+const PRESENCE_MAX_RINGS: usize = 4;
+/// Opacity of the rings of a city the active player no longer sees.
+const STALE_ALPHA: f32 = 0.4;
+
+pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<MapOverlay>()
+        .add_systems(OnEnter(GameState::Game), spawn_overlay_buttons)
+        .add_systems(
+            Update,
+            (
+                overlay_button,
+                update_overlay_buttons.run_if(
+                    resource_changed::<MapOverlay>.or(any_match_filter::<Added<OverlayButton>>),
+                ),
+                draw_overlay,
+            )
+                .chain()
+                .run_if(in_state(GameState::Game)),
+        );
+}
+
+#[derive(Resource, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum MapOverlay {
+    #[default]
+    Off,
+    Prices(Resources),
+    Presence,
+}
+
+impl MapOverlay {
+    fn next(&self) -> MapOverlay {
+        match self {
+            MapOverlay::Off => MapOverlay::Prices(Resources::Food),
+            MapOverlay::Prices(_) => MapOverlay::Presence,
+            MapOverlay::Presence => MapOverlay::Off,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            MapOverlay::Off => "Overlay: none".to_string(),
+            MapOverlay::Prices(_) => "Overlay: prices".to_string(),
+            MapOverlay::Presence => "Overlay: buildings".to_string(),
+        }
+    }
+}
+
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+enum OverlayButton {
+    /// Switches to the next overlay.
+    Mode,
+    /// Picks the resource of the price heatmap.
+    Resource,
+}
+
+#[derive(Component, Clone, Copy, Debug)]
+struct OverlayButtonText(OverlayButton);
+
+fn spawn_overlay_buttons(mut commands: Commands) {
+    // Below the outliner, the resource picker above the overlay switch
+    for (button, bottom) in [(OverlayButton::Mode, 0), (OverlayButton::Resource, 40)] {
+        commands.spawn((
+            ZIndex(2),
+            Button,
+            button,
+            Node {
+                position_type: PositionType::Absolute,
+                bottom: px(bottom),
+                right: px(0),
+                width: vw(20),
+                height: px(40),
+                border: UiRect::all(Val::Px(2.0)),
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            BorderColor::all(Color::BLACK),
+            DespawnOnExit(GameState::Game),
+            BackgroundColor(Srgba::new(0.2, 0.2, 0.2, 1.0).into()),
+            children![(
+                Node {
+                    width: percent(100),
+                    ..default()
+                },
+                TextLayout::new_with_justify(Justify::Center),
+                Text::new(MapOverlay::Off.describe()),
+                OverlayButtonText(button),
+            )],
+        ));
+    }
+}
+
+fn overlay_button(
+    mut interaction_query: Query<
+        (&Interaction, &OverlayButton, &mut BackgroundColor),
+        Changed<Interaction>,
+    >,
+    mut overlay: ResMut<MapOverlay>,
+) {
+    for (interaction, button, mut node_color) in &mut interaction_query {
+        match *interaction {
+            Interaction::Pressed => {
+                *overlay = match (button, *overlay) {
+                    (OverlayButton::Resource, MapOverlay::Prices(res)) => {
+                        let all = Resources::all_resources();
+                        let idx = all.iter().position(|r| *r == res).unwrap_or_default();
+                        MapOverlay::Prices(all[(idx + 1) % all.len()])
+                    }
+                    (_, overlay) => overlay.next(),
+                }
+            }
+            Interaction::Hovered => {
+                *node_color = BackgroundColor(Srgba::new(0.4, 0.4, 0.4, 1.0).into())
+            }
+            Interaction::None => {
+                *node_color = BackgroundColor(Srgba::new(0.2, 0.2, 0.2, 1.0).into())
+            }
+        }
+    }
+}
+
+fn update_overlay_buttons(
+    overlay: Res<MapOverlay>,
+    mut buttons: Query<(&OverlayButton, &mut Node)>,
+    mut texts: Query<(&OverlayButtonText, &mut Text)>,
+) {
+    for (button, mut node) in &mut buttons {
+        if *button == OverlayButton::Resource {
+            node.display = match *overlay {
+                MapOverlay::Prices(_) => Display::Flex,
+                _ => Display::None,
+            };
+        }
+    }
+    for (text_of, mut text) in &mut texts {
+        text.0 = match (text_of.0, *overlay) {
+            (OverlayButton::Resource, MapOverlay::Prices(res)) => {
+                format!("Price of {}", res.get_name())
+            }
+            (OverlayButton::Resource, _) => String::new(),
+            (OverlayButton::Mode, overlay) => overlay.describe(),
+        };
+    }
+}
+
+/// Green where the resource is cheapest, red where it is dearest.
+fn heat_color(t: f32) -> Color {
+    Color::srgb(t, 1.0 - t, 0.1)
+}
+
+fn draw_overlay(
+    mut gizmos: Gizmos,
+    overlay: Res<MapOverlay>,
+    intel: Res<MarketIntel>,
+    you: Single<&Player, With<ActivePlayer>>,
+    cities: Query<(&CityNode, &CityData)>,
+) {
+    match *overlay {
+        MapOverlay::Off => {}
+        MapOverlay::Prices(res) => {
+            let prices: Vec<_> = cities
+                .iter()
+                .map(|(node, city)| {
+                    let view = intel.view(you.player_id, &city.id);
+                    let price = view.known(city).map(|known| known.get_resource_value(&res));
+                    (node.1, price)
+                })
+                .collect();
+            let known = prices.iter().filter_map(|(_, price)| *price);
+            let min = known.clone().fold(f64::INFINITY, f64::min);
+            let max = known.fold(f64::NEG_INFINITY, f64::max);
+            for (pos, price) in prices {
+                let Some(price) = price else {
+                    // Prices nobody of yours has seen
+                    gizmos.circle_2d(pos, HEAT_RADIUS, Color::srgba(0.5, 0.5, 0.5, 0.5));
+                    continue;
+                };
+                let t = if max > min {
+                    ((price - min) / (max - min)) as f32
+                } else {
+                    0.5
+                };
+                let color = heat_color(t);
+                gizmos.circle_2d(pos, HEAT_RADIUS, color);
+                gizmos.circle_2d(pos, HEAT_RADIUS * 0.75, color);
+                gizmos.circle_2d(pos, HEAT_RADIUS * 0.5, color);
+            }
+        }
+        MapOverlay::Presence => {
+            for (node, city) in &cities {
+                let view = intel.view(you.player_id, &city.id);
+                let Some(known) = view.known(city) else {
+                    gizmos.circle_2d(node.1, HEAT_RADIUS, Color::srgba(0.5, 0.5, 0.5, 0.5));
+                    continue;
+                };
+                // Buildings as they were when last seen are drawn faded
+                let alpha = match view {
+                    MarketView::Live => 1.0,
+                    _ => STALE_ALPHA,
+                };
+                let counts = known.buildings.count_by_owner();
+                for (idx, (player_id, owned)) in counts.into_iter().enumerate() {
+                    let radius = HEAT_RADIUS + idx as f32 * PRESENCE_SPACING;
+                    let color = player_color(player_id).with_alpha(alpha);
+                    for ring in 0..owned.min(PRESENCE_MAX_RINGS) {
+                        gizmos.circle_2d(node.1, radius + ring as f32, color);
+                    }
+                }
+            }
+        }
+    }
+}
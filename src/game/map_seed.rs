@@ -0,0 +1,387 @@
+//! Keeps map generation identical on every peer. The host's seed is split into
+//! separate random streams, so a change to how one part of the map is drawn
+//! can't shift another, and every client reports a fingerprint of the map it
+//! built for the host to compare. Nobody can act on the map before the host
+//! found them all equal.
+
+use std::collections::HashMap;
+use std::hash::Hasher;
+
+use bevy::app::AppExit;
+use rand_xoshiro::Xoshiro256StarStar;
+use serde::{Deserialize, Serialize};
+
+use super::city_data::CityData;
+use super::city_graph::{CityGraph, Node as CityNode};
+use crate::network::message::{
+    ClientData, ClientMessage, NetworkMessage, PlayerId, Players, ServerMessage,
+};
+use crate::prelude::*;
+use crate::{GameState, GlobalRngSeed, NetworkState};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<FingerprintReports>()
+        .init_resource::<MapCheck>()
+        .add_systems(
+            OnEnter(GameState::Game),
+            (
+                seed_map_streams.before(NodeGenSet),
+                (
+                    fingerprint_map,
+                    client::report_fingerprint.run_if(in_state(NetworkState::Client)),
+                )
+                    .chain()
+                    .after(NodeGenSet),
+                spawn_check_overlay.run_if(not(in_state(NetworkState::SinglePlayer))),
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                server::settle_map_check.run_if(in_state(NetworkState::Host)),
+                client::receive_map_check.run_if(in_state(NetworkState::Client)),
+                update_check_overlay.run_if(resource_changed::<MapCheck>),
+                quit_button,
+            )
+                .chain()
+                .run_if(in_state(GameState::Game)),
+        );
+}
+
+/// Random streams used while generating the map, all drawn from the same seed.
+#[derive(Resource)]
+pub struct MapRng {
+    /// Terrain, capitals, city positions and which roads are kept.
+    pub positions: GlobalRng,
+    /// Buildings of the generated cities.
+    pub buildings: GlobalRng,
+    pub names: GlobalRng,
+}
+
+impl MapRng {
+    /// Every stream starts 2^128 draws after the previous one, so they never
+    /// overlap.
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Xoshiro256StarStar::seed_from_u64(seed);
+        let positions = GlobalRng(rng.clone());
+        rng.jump();
+        let buildings = GlobalRng(rng.clone());
+        rng.jump();
+        let names = GlobalRng(rng);
+        MapRng {
+            positions,
+            buildings,
+            names,
+        }
+    }
+}
+
+fn seed_map_streams(seed: Res<GlobalRngSeed>, mut streams: ResMut<MapRng>) {
+    info!("Generating the map from seed {}", seed.0);
+    *streams = MapRng::new(seed.0);
+}
+
+/// 64 bit FNV-1a, which unlike the std hashers is the same on every build.
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(FNV_OFFSET_BASIS)
+    }
+}
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+}
+
+impl Fnv {
+    fn write_name(&mut self, s: &str) {
+        self.write(s.as_bytes());
+        self.write_u8(0xff);
+    }
+
+    fn write_f32(&mut self, f: f32) {
+        self.write(&f.to_le_bytes());
+    }
+}
+
+/// Hash of everything generation decides: the cities, where they are, what they
+/// hold and the roads between them.
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MapFingerprint(pub u64);
+
+impl MapFingerprint {
+    pub fn of<'a>(
+        graph: &CityGraph,
+        cities: impl Iterator<Item = (&'a CityNode, &'a CityData)>,
+    ) -> Self {
+        let mut cities: Vec<_> = cities.collect();
+        cities.sort_by(|(_, a), (_, b)| a.id.cmp(&b.id));
+
+        let mut hasher = Fnv::default();
+        let mut names = HashMap::new();
+        for (node, city) in &cities {
+            hasher.write_name(&city.id);
+            hasher.write_name(&format!("{:?}", city.race));
+            hasher.write_f32(node.1.x);
+            hasher.write_f32(node.1.y);
+            hasher.write_u8(city.population);
+            for slot in city.buildings.iter() {
+                hasher.write_name(&slot.building_id);
+            }
+            names.insert(node.0, city.id.as_str());
+        }
+
+        let mut roads: Vec<_> = graph
+            .graph
+            .edge_indices()
+            .filter_map(|edge| {
+                let (a, b) = graph.graph.edge_endpoints(edge)?;
+                let (a, b) = (*names.get(&a)?, *names.get(&b)?);
+                Some((a.min(b), a.max(b), &graph.graph[edge]))
+            })
+            .collect();
+        roads.sort_by(|x, y| (x.0, x.1).cmp(&(y.0, y.1)));
+        for (a, b, edge) in roads {
+            hasher.write_name(a);
+            hasher.write_name(b);
+            hasher.write_f32(edge.distance);
+            hasher.write_name(&format!("{:?} {:?}", edge.terrain, edge.road));
+        }
+
+        MapFingerprint(hasher.finish())
+    }
+}
+
+fn fingerprint_map(
+    mut commands: Commands,
+    graph: Res<CityGraph>,
+    cities: Query<(&CityNode, &CityData)>,
+) {
+    let fingerprint = MapFingerprint::of(&graph, cities.iter());
+    info!("Map fingerprint {:016x}", fingerprint.0);
+    commands.insert_resource(fingerprint);
+}
+
+/// Fingerprints the clients reported for their copy of the map.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct FingerprintReports(pub HashMap<PlayerId, u64>);
+
+/// What the host made of the fingerprints, sent to every client.
+#[derive(Resource, Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum MapCheck {
+    #[default]
+    Pending,
+    Passed,
+    /// Why the players can't play on the same map.
+    Failed(String),
+}
+
+/// Whether every client built the same map as the host. The host doesn't finish
+/// a turn before this holds.
+pub fn map_verified(check: Res<MapCheck>) -> bool {
+    *check == MapCheck::Passed
+}
+
+/// Covers the map while the check is pending and tells why it failed.
+#[derive(Component)]
+struct CheckOverlay;
+
+#[derive(Component)]
+struct QuitButton;
+
+fn spawn_check_overlay(mut commands: Commands) {
+    commands.spawn((
+        ZIndex(10),
+        CheckOverlay,
+        Node {
+            position_type: PositionType::Absolute,
+            width: vw(100),
+            height: vh(100),
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::Center,
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        BackgroundColor(Srgba::new(0.05, 0.05, 0.05, 0.8).into()),
+        DespawnOnExit(GameState::Game),
+        children![Text::new("Waiting for every player to build the map")],
+    ));
+}
+
+fn update_check_overlay(
+    mut commands: Commands,
+    check: Res<MapCheck>,
+    overlay: Query<Entity, With<CheckOverlay>>,
+) {
+    for overlay in &overlay {
+        match &*check {
+            MapCheck::Pending => {}
+            MapCheck::Passed => commands.entity(overlay).despawn(),
+            MapCheck::Failed(reason) => {
+                error!("The game can't start: {reason}");
+                commands
+                    .entity(overlay)
+                    .despawn_related::<Children>()
+                    .with_children(|parent| {
+                        parent.spawn(Text::new(format!("The game can't start: {reason}")));
+                        parent.spawn((
+                            Button,
+                            QuitButton,
+                            Node {
+                                margin: UiRect::all(px(20)),
+                                padding: UiRect::axes(px(16), px(8)),
+                                ..default()
+                            },
+                            BackgroundColor(Srgba::new(0.5, 0.1, 0.1, 1.0).into()),
+                            children![Text::new("Quit")],
+                        ));
+                    });
+            }
+        }
+    }
+}
+
+fn quit_button(
+    interactions: Query<&Interaction, (Changed<Interaction>, With<QuitButton>)>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    if interactions.iter().any(|i| *i == Interaction::Pressed) {
+        exit.write(AppExit::Success);
+    }
+}
+
+mod server {
+    use bevy_renet::renet::ServerEvent;
+
+    use super::*;
+    use crate::network::server::ServerState;
+
+    /// Compares the reported fingerprints with the host's once every client
+    /// sent one. A client leaving before it reported fails the check, as the
+    /// host would wait for it forever.
+    pub fn settle_map_check(
+        mut writer: crate::network::server::Writer,
+        mut events: MessageReader<ServerEvent>,
+        server: Res<ServerState>,
+        own: Option<Res<MapFingerprint>>,
+        reports: Res<FingerprintReports>,
+        players: Res<Players>,
+        host: Res<ClientData>,
+        mut check: ResMut<MapCheck>,
+    ) {
+        if *check != MapCheck::Pending {
+            events.clear();
+            return;
+        }
+        let left = events.read().find_map(|event| match event {
+            ServerEvent::ClientDisconnected { client_id, .. } => server
+                .id_map
+                .get(client_id)
+                .filter(|player| !reports.contains_key(*player)),
+            _ => None,
+        });
+        let Some(own) = own else {
+            return;
+        };
+        let mut clients = players.0.iter().filter(|p| **p != host.player_id);
+        let result = if let Some(player) = left {
+            MapCheck::Failed(format!("player {player} left before building the map"))
+        } else if let Some(player) = clients
+            .clone()
+            .find(|p| reports.get(*p).is_some_and(|f| *f != own.0))
+        {
+            MapCheck::Failed(format!("player {player} generated a different map"))
+        } else if clients.all(|p| reports.contains_key(p)) {
+            MapCheck::Passed
+        } else {
+            return;
+        };
+
+        *check = result.clone();
+        writer.write(ServerMessage(NetworkMessage::MapChecked { check: result }));
+    }
+}
+
+mod client {
+    use super::*;
+
+    pub fn receive_map_check(
+        mut reader: crate::network::client::Reader,
+        mut check: ResMut<MapCheck>,
+    ) {
+        for msg in reader.read() {
+            if let NetworkMessage::MapChecked { check: result } = &**msg {
+                *check = result.clone();
+            }
+        }
+    }
+
+    pub fn report_fingerprint(
+        mut writer: crate::network::client::Writer,
+        fingerprint: Res<MapFingerprint>,
+        client: Res<ClientData>,
+    ) {
+        writer.write(ClientMessage(NetworkMessage::MapFingerprint {
+            player_id: client.player_id,
+            fingerprint: fingerprint.0,
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::game::city_graph::{gen_edges, remove_random_edges, setup};
+    use crate::game::map_definition::{DEFAULT_MAP, MapDefinition};
+    use crate::game::namelists::CityNameList;
+
+    /// Fingerprint of the map built from `seed` the way every peer builds it.
+    fn fingerprint(path: &str, seed: u64) -> MapFingerprint {
+        let mut world = World::new();
+        world.insert_resource(MapDefinition::load(path).unwrap());
+        world.insert_resource(MapRng::new(seed));
+        world.insert_resource(CityNameList(vec![]));
+        world.run_system_once(crate::setup_city_names).unwrap();
+        world.run_system_once(setup).unwrap();
+        world.run_system_once(gen_edges).unwrap();
+        world.run_system_once(remove_random_edges).unwrap();
+
+        let mut cities = world.query::<(&CityNode, &CityData)>();
+        MapFingerprint::of(world.resource::<CityGraph>(), cities.iter(&world))
+    }
+
+    #[test]
+    fn same_seed_same_fingerprint() {
+        for path in [DEFAULT_MAP, "assets/maps/procedural.json"] {
+            for seed in [0, 1, 42, u64::MAX] {
+                assert_eq!(
+                    fingerprint(path, seed),
+                    fingerprint(path, seed),
+                    "{path} with seed {seed}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn other_seed_other_fingerprint() {
+        for path in [DEFAULT_MAP, "assets/maps/procedural.json"] {
+            assert_ne!(fingerprint(path, 1), fingerprint(path, 2), "{path}");
+        }
+    }
+}
@@ -75,7 +75,7 @@ pub const EXOTIC_RESOURCES: [Resources; 3] = [
 pub const ILLEGAL_RESOURCES: [Resources; 3] =
     [Resources::Drugs, Resources::Slaves, Resources::Vitae];
 
-#[derive(Reflect, Debug, Clone, Copy, Eq, PartialEq, Default, Serialize, Deserialize)]
+#[derive(Reflect, Debug, Clone, Copy, Eq, PartialEq, Hash, Default, Serialize, Deserialize)]
 pub enum BuildingType {
     Human,
     Elven,
@@ -88,7 +88,10 @@ pub enum BuildingType {
 }
 
 impl Resources {
-    pub fn all_resources() -> [Self; 27] {
+    /// Number of resources, the length of a `Stockpile`.
+    pub const COUNT: usize = 27;
+
+    pub fn all_resources() -> [Self; Self::COUNT] {
         [
             Self::Artifacts,
             Self::Coal,
@@ -120,6 +123,11 @@ impl Resources {
         ]
     }
 
+    /// Illegal goods are only traded on the black market.
+    pub fn is_illegal(&self) -> bool {
+        ILLEGAL_RESOURCES.contains(self)
+    }
+
     pub fn get_base_value(&self) -> isize {
         match &self {
             Self::Food => 1,
@@ -192,6 +200,70 @@ pub struct Building {
     pub tier: usize,
     pub image_sylt_id: Option<String>,
     pub build_type: BuildingType,
+    pub construction: Construction,
+}
+
+/// Turns of output a building is worth when it changes hands.
+const BUILDING_VALUE_TURNS: isize = 20;
+
+impl Building {
+    pub fn output_value(&self) -> isize {
+        self.output
+            .iter()
+            .map(|(res, amount)| res.get_base_value() * amount)
+            .sum()
+    }
+
+    /// What a city asks for this building, larger cities ask more.
+    pub fn market_price(&self, population: u8) -> f64 {
+        (self.output_value() * BUILDING_VALUE_TURNS) as f64 * (1.0 + population as f64 / 5.0)
+    }
+}
+
+/// What it takes to put up a building.
+#[derive(Reflect, Debug, Clone, Eq, PartialEq, Default)]
+pub struct Construction {
+    pub cost: isize,
+    pub materials: HashMap<Resources, isize>,
+    pub turns: usize,
+}
+
+impl Construction {
+    /// Derives the requirements from a building's tier and how valuable its output is.
+    fn for_building(tier: usize, output: &HashMap<Resources, isize>) -> Construction {
+        let output_value: isize = output
+            .iter()
+            .map(|(res, amount)| res.get_base_value() * amount)
+            .sum();
+        let tier = tier as isize;
+
+        let mut materials = HashMap::new();
+        materials.insert(Resources::Stone, 10 * tier);
+        materials.insert(Resources::Lumber, 10 * tier);
+        if tier >= 3 {
+            materials.insert(Resources::CommonAlloys, 5 * (tier - 2));
+        }
+
+        Construction {
+            cost: 250 * tier + 5 * output_value,
+            materials,
+            turns: tier as usize + 1,
+        }
+    }
+
+    /// Requirements for raising a building from `level - 1` to `level`.
+    pub fn upgrade_to(&self, level: u8) -> Construction {
+        let factor = level.saturating_sub(1).max(1) as isize;
+        Construction {
+            cost: self.cost * factor,
+            materials: self
+                .materials
+                .iter()
+                .map(|(res, amount)| (*res, amount * factor))
+                .collect(),
+            turns: self.turns,
+        }
+    }
 }
 
 pub fn gen_building_tables() -> HashMap<String, Building> {
@@ -200,11 +272,13 @@ pub fn gen_building_tables() -> HashMap<String, Building> {
 
     macro_rules! generate_building {
         ($name:literal, $($inputname:ident x $inputamount:literal),*; $($outputname:ident x $outputamount:literal),*; $tier:literal) => {
+            let output = quick_hash(vec![$((Resources::$outputname, $outputamount)),*]);
             all_buildings.insert(
                 $name.to_string(),
                 Building {
                     input: quick_hash(vec![$((Resources::$inputname, $inputamount)),*]),
-                    output: quick_hash(vec![$((Resources::$outputname, $outputamount)),*]),
+                    construction: Construction::for_building($tier, &output),
+                    output,
                     image_sylt_id: Some($name.to_lowercase()),
                     tier: $tier,
                     build_type: current_type
@@ -213,6 +287,27 @@ pub fn gen_building_tables() -> HashMap<String, Building> {
         };
     }
 
+    // Wonders only cost money and materials, their effects live in `wonders`
+    macro_rules! generate_wonder {
+        ($name:literal, $cost:literal, $($material:ident x $amount:literal),*; $turns:literal) => {
+            all_buildings.insert(
+                $name.to_string(),
+                Building {
+                    input: HashMap::new(),
+                    output: HashMap::new(),
+                    construction: Construction {
+                        cost: $cost,
+                        materials: quick_hash(vec![$((Resources::$material, $amount)),*]),
+                        turns: $turns,
+                    },
+                    image_sylt_id: Some($name.to_lowercase()),
+                    tier: 5,
+                    build_type: BuildingType::Unique,
+                },
+            );
+        };
+    }
+
     //Generic buildings
     generate_building!("Standard Farms", Water x 15, SimpleLabour x 10; Food x 15, Plants x 15; 1);
     generate_building!("Standard Mines", ManufacturedGoods x 10, SimpleLabour x 10; CommonOre x 15, RareOre x 15, Coal x 15; 1);
@@ -302,20 +397,22 @@ pub fn gen_building_tables() -> HashMap<String, Building> {
     generate_building!("Cauldronworks of the Four Clans",  ExoticAlloys x 15, RefinedValuables x 15, Reagents x 15, Glass x 40, RareOre x 25, ComplexLabour x 50; Artifacts x 120; 5);
     generate_building!("Sunstrider Headquarters", RefinedValuables x 100, Medicines x 50; ExoticAlloys x 40, Spellwork x 40, Artifacts x 40, Military x 70; 5);
 
+    //Wonders
+    generate_wonder!("Grand Bazaar", 8000, Stone x 150, Lumber x 100, Textiles x 80, Luxuries x 100; 10);
+    generate_wonder!("Imperial Highway Office", 8000, Stone x 250, CommonAlloys x 60, Machinery x 40; 10);
+    generate_wonder!("Lantern Warden Keeps", 8000, Stone x 200, Lumber x 100, Military x 200, Glass x 60; 10);
+    generate_wonder!("Vaults of the Deep Bank", 8000, Stone x 300, CommonAlloys x 80, RefinedValuables x 60; 10);
+
     all_buildings
 }
 
-pub fn gen_random_building(
-    tier: u8,
-    rng: &mut ResMut<GlobalRng>,
-    mut race: BuildingType,
-) -> String {
-    if race == BuildingType::Illegal || race == BuildingType::Generic {
+pub fn gen_random_building(tier: u8, rng: &mut GlobalRng, mut race: BuildingType) -> String {
+    if race == BuildingType::Unique || race == BuildingType::Generic {
         panic!("generated a random building of race {:?}", race)
     }
 
     let random_choice: u32 = rng.0.random();
-    if rng.0.random_range(0..3) == 2 {
+    if race != BuildingType::Illegal && rng.0.random_range(0..3) == 2 {
         race = BuildingType::Generic;
     }
 
@@ -470,6 +567,7 @@ pub fn gen_random_building(
                 tier
             ),
         },
+        BuildingType::Illegal => illegal_building(tier as usize),
         _ => {
             panic!("fucky wucky code in gen_random_building.")
         }
@@ -478,11 +576,29 @@ pub fn gen_random_building(
     result.to_string()
 }
 
+/// The one illegal building of each tier.
+fn illegal_building(tier: usize) -> &'static str {
+    match tier {
+        1 => "Opium Plantation",
+        2 => "Hired Banditry",
+        3 => "Joy Distillery",
+        4 => "Lawless Enforcement",
+        5 => "Life Extractors",
+        _ => panic!(
+            "gen_random_building tried to generate a building of tier {:?}",
+            tier
+        ),
+    }
+}
+
 //I dont like to borrow string but its ass
 pub fn get_construction_list(race: BuildingType, tier: usize) -> Vec<&'static str> {
-    if race == BuildingType::Illegal || race == BuildingType::Generic {
+    if race == BuildingType::Unique || race == BuildingType::Generic {
         panic!("generated a random building of race {:?}", race)
     }
+    if race == BuildingType::Illegal {
+        return vec![illegal_building(tier)];
+    }
 
     let mut race_result = match race {
         BuildingType::Dwarven => match tier {
@@ -1,11 +1,27 @@
 //! The game's main screen states and transitions between them.
 
+pub mod building_slot;
 pub mod city_graph;
+pub mod city_labels;
+pub mod intel;
+pub mod map_definition;
+pub mod map_overlay;
+pub mod map_seed;
 pub mod market;
 pub mod namelists;
+pub mod relations;
+pub mod reputation;
+pub mod risk;
+pub mod roads;
+pub mod route_planner;
 pub mod scene;
 pub mod strategic_hud;
 pub mod strategic_map;
+pub mod terrain;
+pub mod trade;
+pub mod warehouse;
+pub mod wonders;
+pub mod world_events;
 use bevy::prelude::*;
 
 pub mod city_data;
@@ -16,8 +32,18 @@ pub(super) fn plugin(app: &mut App) {
     app.add_plugins((
         strategic_map::plugin,
         city_graph::plugin,
+        city_labels::plugin,
+        intel::plugin,
+        map_definition::plugin,
+        map_overlay::plugin,
+        map_seed::plugin,
+        relations::plugin,
+        reputation::plugin,
+        roads::plugin,
         strategic_hud::plugin,
         tooltip::plugin,
+        trade::plugin,
         turn::plugin,
+        world_events::plugin,
     ));
 }
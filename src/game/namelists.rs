@@ -11,7 +11,7 @@ pub struct CityNameList(pub Vec<Vec<String>>);
 
 pub fn generate_city_names(
     amount: (usize, usize, usize, usize),
-    mut rng: &mut ResMut<GlobalRng>,
+    mut rng: &mut GlobalRng,
 ) -> Vec<Vec<String>> {
     let mut names = vec![vec![], vec![], vec![], vec![]];
     let mut city_iter = |citytype: BuildingType, amount: usize, idx: usize| {
@@ -48,7 +48,7 @@ pub fn generate_city_names(
     names
 }
 
-pub fn generate_city_name(city_type: BuildingType, mut rng: &mut ResMut<GlobalRng>) -> String {
+pub fn generate_city_name(city_type: BuildingType, mut rng: &mut GlobalRng) -> String {
     match city_type {
         BuildingType::Dwarven => get_dwarven_name(&mut rng),
         BuildingType::Elven => get_elven_name(&mut rng),
@@ -58,7 +58,7 @@ pub fn generate_city_name(city_type: BuildingType, mut rng: &mut ResMut<GlobalRn
     }
 }
 
-pub fn get_dwarven_name(mut rng: &mut ResMut<GlobalRng>) -> String {
+pub fn get_dwarven_name(mut rng: &mut GlobalRng) -> String {
     let dwarven_initial_particles = vec![
         "Ka", "Kal", "Bo", "Bol", "To", "Te", "Tal", "De", "Do", "Don", "Be", "Ge", "Get",
     ];
@@ -87,7 +87,7 @@ pub fn get_dwarven_name(mut rng: &mut ResMut<GlobalRng>) -> String {
     name
 }
 
-pub fn get_elven_name(mut rng: &mut ResMut<GlobalRng>) -> String {
+pub fn get_elven_name(mut rng: &mut GlobalRng) -> String {
     let elven_initial_particle = vec![
         "Dawn", "Sun", "Gem", "Ice", "Frost", "Heart", "Sky", "Heaven", "Winter", "Lore", "Fire",
         "World", "Moon", "Forge", "Flame", "Star", "Mage", "Silver", "Storm", "Amber", "Ash",
@@ -154,7 +154,7 @@ pub fn get_elven_name(mut rng: &mut ResMut<GlobalRng>) -> String {
     name
 }
 
-pub fn get_goblin_name(mut rng: &mut ResMut<GlobalRng>) -> String {
+pub fn get_goblin_name(mut rng: &mut GlobalRng) -> String {
     let goblin_initial_particle = vec![
         "Ke", "Te", "Tre", "Kre", "Ge", "Ze", "Zhe", "Phe", "Pe", "Se",
     ];
@@ -185,7 +185,7 @@ pub fn get_goblin_name(mut rng: &mut ResMut<GlobalRng>) -> String {
     name
 }
 
-pub fn get_human_name(mut rng: &mut ResMut<GlobalRng>) -> String {
+pub fn get_human_name(mut rng: &mut GlobalRng) -> String {
     let human_initial_particle = vec![
         "Coven", "Lon", "Wake", "Shef", "Man", "Brad", "Notting", "Birming", "Stoke", "Trent",
         "Chelm", "York", "New", "Canter", "Don", "Bright", "Wolver", "Ply", "Der", "South",
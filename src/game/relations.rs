@@ -0,0 +1,266 @@
+//! How the races get along. Goods carried from one race's territory into
+//! another's pay a tariff that depends on their standing, and races at odds
+//! close their borders altogether. Standings drift back towards neutral, wars
+//! sour them and caravans trading across the border mend them.
+
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use serde::{Deserialize, Serialize};
+
+use super::city_data::CityData;
+use super::city_graph::{CityGraph, PathError, Route};
+use super::map_definition::PLAYABLE_RACES;
+use super::market::{BuildingType, Resources};
+use super::turn::{Turn, TurnEndSinglePlayer};
+use super::world_events::{WorldEventKind, WorldEvents};
+use crate::prelude::*;
+
+pub const MAX_STANDING: i32 = 100;
+const ALLIED_STANDING: i32 = 50;
+const STRAINED_STANDING: i32 = -20;
+const EMBARGO_STANDING: i32 = -60;
+/// Standing every relation moves back towards 0 each turn.
+const DRIFT: i32 = 1;
+/// Largest random change in a relation each turn.
+const JITTER: i32 = 2;
+/// Standing lost each turn between races fighting in the same war.
+const WAR_PENALTY: i32 = 4;
+/// Standing gained when a caravan brings goods across the border.
+pub const TRADE_GOODWILL: i32 = 1;
+/// Standing gained when Military from one race's territory reaches a city of
+/// another race at war.
+pub const WAR_SUPPLY_GOODWILL: i32 = 5;
+
+pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<RaceRelations>()
+        .add_observer(relations_keeper);
+}
+
+#[derive(Reflect, Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Stance {
+    /// The same race, nothing to pay.
+    Home,
+    Allied,
+    Neutral,
+    Strained,
+    Embargo,
+}
+
+impl Stance {
+    /// Share of the cargo's value paid when crossing the border.
+    pub fn tariff(&self) -> f64 {
+        match self {
+            Stance::Home | Stance::Allied => 0.0,
+            Stance::Neutral => 0.05,
+            Stance::Strained => 0.15,
+            // Nothing crosses in the first place
+            Stance::Embargo => 0.0,
+        }
+    }
+
+    pub fn describe(&self) -> &str {
+        match self {
+            Stance::Home => "Home",
+            Stance::Allied => "Allied, no tariffs",
+            Stance::Neutral => "Neutral, 5% tariffs",
+            Stance::Strained => "Strained, 15% tariffs",
+            Stance::Embargo => "Embargo, borders closed",
+        }
+    }
+}
+
+#[derive(Reflect, Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Relation {
+    pub races: [BuildingType; 2],
+    pub standing: i32,
+}
+
+/// Standing between every two playable races, from -100 to 100.
+#[derive(Resource, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct RaceRelations(pub Vec<Relation>);
+
+impl Default for RaceRelations {
+    fn default() -> Self {
+        let mut relations = vec![];
+        for (i, a) in PLAYABLE_RACES.iter().enumerate() {
+            for b in &PLAYABLE_RACES[i + 1..] {
+                relations.push(Relation {
+                    races: [*a, *b],
+                    standing: 0,
+                });
+            }
+        }
+        RaceRelations(relations)
+    }
+}
+
+impl RaceRelations {
+    fn find(&mut self, a: BuildingType, b: BuildingType) -> Option<&mut Relation> {
+        self.0
+            .iter_mut()
+            .find(|r| r.races == [a, b] || r.races == [b, a])
+    }
+
+    pub fn standing(&self, a: BuildingType, b: BuildingType) -> i32 {
+        if a == b {
+            return MAX_STANDING;
+        }
+        self.0
+            .iter()
+            .find(|r| r.races == [a, b] || r.races == [b, a])
+            .map_or(0, |r| r.standing)
+    }
+
+    pub fn stance(&self, a: BuildingType, b: BuildingType) -> Stance {
+        if a == b {
+            return Stance::Home;
+        }
+        match self.standing(a, b) {
+            s if s >= ALLIED_STANDING => Stance::Allied,
+            s if s >= STRAINED_STANDING => Stance::Neutral,
+            s if s > EMBARGO_STANDING => Stance::Strained,
+            _ => Stance::Embargo,
+        }
+    }
+
+    /// Whether `edge` crosses a border closed by an embargo, given the race of
+    /// each city.
+    pub fn closed_border(
+        &self,
+        graph: &CityGraph,
+        edge: EdgeIndex,
+        race: impl Fn(Entity) -> Option<BuildingType>,
+    ) -> bool {
+        graph
+            .graph
+            .edge_endpoints(edge)
+            .and_then(|(a, b)| Some((race(graph.graph[a])?, race(graph.graph[b])?)))
+            .is_some_and(|(a, b)| self.stance(a, b) == Stance::Embargo)
+    }
+
+    /// The races on either side of the first closed border along `path`.
+    pub fn embargo_on(
+        &self,
+        path: &[Entity],
+        race: impl Fn(Entity) -> Option<BuildingType>,
+    ) -> Option<(BuildingType, BuildingType)> {
+        path.iter()
+            .zip(path.iter().skip(1))
+            .filter_map(|(a, b)| Some((race(*a)?, race(*b)?)))
+            .find(|(a, b)| self.stance(*a, *b) == Stance::Embargo)
+    }
+
+    /// Where a caravan goes from `from` to `to`, around closed borders if the
+    /// quickest route crosses one.
+    pub fn open_route(
+        &self,
+        graph: &CityGraph,
+        from: NodeIndex,
+        to: NodeIndex,
+        race: impl Fn(Entity) -> Option<BuildingType>,
+    ) -> Result<Route, PathError> {
+        let route = graph.find_route(from, to)?;
+        let Some((a, b)) = self.embargo_on(&route.path, &race) else {
+            return Ok(route);
+        };
+        graph
+            .find_route_avoiding(from, to, |edge| self.closed_border(graph, edge, &race))
+            .map_err(|_| PathError::Embargo(a, b))
+    }
+
+    pub fn shift(&mut self, a: BuildingType, b: BuildingType, by: i32) {
+        if let Some(relation) = self.find(a, b) {
+            relation.standing = (relation.standing + by).clamp(-MAX_STANDING, MAX_STANDING);
+        }
+    }
+
+    /// Tariff on goods carried from `from`'s territory into `to`'s city.
+    pub fn tariff(
+        &self,
+        cargo: impl Iterator<Item = (Resources, usize)>,
+        from: BuildingType,
+        to: &CityData,
+    ) -> f64 {
+        let rate = self.stance(from, to.race).tariff();
+        if rate == 0.0 {
+            return 0.0;
+        }
+        let value: f64 = cargo
+            .map(|(res, amount)| to.get_resource_value(&res) * amount as f64)
+            .sum();
+        (value * rate).round()
+    }
+}
+
+/// Foreign goods a race's cities use up per tier every turn, out of what
+/// caravans import.
+pub fn foreign_demand(race: BuildingType) -> &'static [(Resources, isize)] {
+    match race {
+        BuildingType::Human => &[(Resources::Spellwork, 1), (Resources::Machinery, 1)],
+        BuildingType::Elven => &[(Resources::CommonAlloys, 1), (Resources::Glass, 1)],
+        BuildingType::Dwarven => &[(Resources::Plants, 2), (Resources::Lumber, 2)],
+        BuildingType::Goblin => &[(Resources::Luxuries, 1), (Resources::Textiles, 1)],
+        _ => &[],
+    }
+}
+
+/// Counts goods a caravan from `from`'s territory sold in `city` as imports,
+/// if its race wants them.
+pub fn record_import(city: &mut CityData, from: BuildingType, res: Resources, amount: isize) {
+    let wanted = foreign_demand(city.race).iter().any(|(r, _)| *r == res);
+    if from != city.race && wanted && amount > 0 {
+        *city.imports.entry(res).or_default() += amount;
+    }
+}
+
+/// Takes the city's share of the foreign goods it imported off the market.
+pub fn use_imports(city: &mut CityData) {
+    let population = city.population as isize;
+    for (res, per_tier) in foreign_demand(city.race) {
+        let Some(imported) = city.imports.get(res).copied() else {
+            continue;
+        };
+        let used = imported
+            .min(per_tier * population)
+            .min(city.stock(res).max(0));
+        *city.stock_mut(res) -= used;
+        // Imports bought up by someone else are gone too
+        let left = (imported - used).min(city.stock(res).max(0));
+        city.imports.insert(*res, left);
+    }
+    city.imports.retain(|_, amount| *amount > 0);
+}
+
+fn relations_keeper(
+    _ev: On<TurnEndSinglePlayer>,
+    mut relations: ResMut<RaceRelations>,
+    events: Res<WorldEvents>,
+    turn: Res<Turn>,
+    cities: Query<&CityData>,
+    mut rng: ResMut<GlobalRng>,
+) {
+    for relation in relations.0.iter_mut() {
+        relation.standing -= relation.standing.signum() * DRIFT;
+        relation.standing += rng.random_range(-JITTER..=JITTER);
+    }
+
+    for war in events
+        .active(**turn)
+        .filter(|e| e.kind == WorldEventKind::War)
+    {
+        let mut sides: Vec<BuildingType> = vec![];
+        for city in cities.iter().filter(|c| war.covers(&c.id)) {
+            if !sides.contains(&city.race) {
+                sides.push(city.race);
+            }
+        }
+        for (i, a) in sides.iter().enumerate() {
+            for b in &sides[i + 1..] {
+                relations.shift(*a, *b, -WAR_PENALTY);
+            }
+        }
+    }
+
+    for relation in relations.0.iter_mut() {
+        relation.standing = relation.standing.clamp(-MAX_STANDING, MAX_STANDING);
+    }
+}
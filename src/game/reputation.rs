@@ -0,0 +1,185 @@
+//! What each race and each city thinks of every player. Bringing a city goods it
+//! has run out of and keeping to trades wins a player goodwill, smuggling and
+//! buying markets bare lose it. Well-liked players trade on better terms, buy
+//! buildings for less and are allowed to build the most advanced buildings.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::city_data::CityData;
+use super::market::BuildingType;
+use crate::network::message::PlayerId;
+use crate::prelude::*;
+
+pub const MAX_REPUTATION: i32 = 100;
+/// Gained for selling goods to a market that has run out of them.
+pub const SUPPLY_GOODWILL: i32 = 2;
+/// Gained by the seller of a trade for every full delivery, lost for every
+/// short one.
+pub const CONTRACT_GOODWILL: i32 = 1;
+/// Lost when inspectors confiscate contraband or guards raid a smuggler's den.
+pub const SMUGGLING_PENALTY: i32 = 6;
+/// Lost for buying up the last of a market's stock.
+pub const GOUGING_PENALTY: i32 = 3;
+/// Change in market prices per point of reputation.
+const SPREAD_PER_POINT: f64 = 0.001;
+/// Change in the price of buildings per point of reputation.
+const PURCHASE_PER_POINT: f64 = 0.002;
+
+pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<Reputation>();
+}
+
+#[derive(Clone, Default, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PlayerReputation {
+    pub races: HashMap<BuildingType, i32>,
+    pub cities: HashMap<String, i32>,
+}
+
+/// Reputation of every player, from -100 to 100. Players start out at 0
+/// everywhere.
+#[derive(Resource, Clone, Default, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Reputation(pub HashMap<PlayerId, PlayerReputation>);
+
+impl Reputation {
+    pub fn of_race(&self, player: PlayerId, race: BuildingType) -> i32 {
+        self.0
+            .get(&player)
+            .and_then(|rep| rep.races.get(&race))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn of_city(&self, player: PlayerId, city_id: &str) -> i32 {
+        self.0
+            .get(&player)
+            .and_then(|rep| rep.cities.get(city_id))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// What a city makes of a player, its own opinion and its race's weighed
+    /// equally.
+    pub fn in_city(&self, player: PlayerId, city: &CityData) -> i32 {
+        (self.of_city(player, &city.id) + self.of_race(player, city.race)) / 2
+    }
+
+    /// Changes what a city thinks of a player, and what its race thinks by half
+    /// as much.
+    pub fn change(&mut self, player: PlayerId, city: &CityData, by: i32) {
+        let rep = self.0.entry(player).or_default();
+        let of_city = rep.cities.entry(city.id.clone()).or_default();
+        *of_city = (*of_city + by).clamp(-MAX_REPUTATION, MAX_REPUTATION);
+
+        let by_race = by.signum() * (by.abs() + 1) / 2;
+        let of_race = rep.races.entry(city.race).or_default();
+        *of_race = (*of_race + by_race).clamp(-MAX_REPUTATION, MAX_REPUTATION);
+    }
+
+    /// Every city with an opinion of the player, sorted by name.
+    pub fn cities(&self, player: PlayerId) -> Vec<(&String, i32)> {
+        let mut cities: Vec<_> = self
+            .0
+            .get(&player)
+            .map(|rep| rep.cities.iter().map(|(id, value)| (id, *value)).collect())
+            .unwrap_or_default();
+        cities.sort();
+        cities
+    }
+}
+
+/// Factor on what a player pays for goods from the market.
+pub fn buy_factor(reputation: i32) -> f64 {
+    1.0 - reputation as f64 * SPREAD_PER_POINT
+}
+
+/// Factor on what a player gets for goods sold to the market.
+pub fn sell_factor(reputation: i32) -> f64 {
+    1.0 + reputation as f64 * SPREAD_PER_POINT
+}
+
+/// Factor on what a player pays for a building.
+pub fn purchase_factor(reputation: i32) -> f64 {
+    1.0 - reputation as f64 * PURCHASE_PER_POINT
+}
+
+/// Reputation a player needs in a city to build at a tier.
+pub fn required_for_tier(tier: usize) -> Option<i32> {
+    match tier {
+        0..4 => None,
+        4 => Some(20),
+        _ => Some(50),
+    }
+}
+
+pub fn may_construct(reputation: i32, tier: usize) -> bool {
+    required_for_tier(tier).is_none_or(|needed| reputation >= needed)
+}
+
+pub fn describe(reputation: i32) -> &'static str {
+    match reputation {
+        50.. => "Revered",
+        20..50 => "Trusted",
+        -20..20 => "Tolerated",
+        -50..-20 => "Distrusted",
+        _ => "Despised",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn city(id: &str, race: BuildingType) -> CityData {
+        CityData {
+            id: id.to_string(),
+            race,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn race_changes_by_half() {
+        let mut reputation = Reputation::default();
+        let city = city("Ashford", BuildingType::Human);
+
+        reputation.change(1, &city, 3);
+        assert_eq!(reputation.of_city(1, "Ashford"), 3);
+        assert_eq!(reputation.of_race(1, BuildingType::Human), 2);
+
+        reputation.change(1, &city, -6);
+        assert_eq!(reputation.of_city(1, "Ashford"), -3);
+        assert_eq!(reputation.of_race(1, BuildingType::Human), -1);
+        assert_eq!(reputation.in_city(1, &city), -2);
+    }
+
+    #[test]
+    fn reputation_is_clamped() {
+        let mut reputation = Reputation::default();
+        let city = city("Ashford", BuildingType::Human);
+
+        for _ in 0..30 {
+            reputation.change(1, &city, 10);
+        }
+        assert_eq!(reputation.of_city(1, "Ashford"), MAX_REPUTATION);
+        assert_eq!(reputation.of_race(1, BuildingType::Human), MAX_REPUTATION);
+
+        reputation.change(1, &city, -500);
+        assert_eq!(reputation.of_city(1, "Ashford"), -MAX_REPUTATION);
+        assert_eq!(reputation.of_race(1, BuildingType::Human), -MAX_REPUTATION);
+    }
+
+    #[test]
+    fn reputation_is_kept_apart() {
+        let mut reputation = Reputation::default();
+        let ashford = city("Ashford", BuildingType::Human);
+        let lirith = city("Lirith", BuildingType::Elven);
+
+        reputation.change(1, &ashford, 4);
+        assert_eq!(reputation.of_city(2, "Ashford"), 0);
+        assert_eq!(reputation.of_city(1, "Lirith"), 0);
+        assert_eq!(reputation.of_race(1, BuildingType::Elven), 0);
+        assert_eq!(reputation.in_city(1, &lirith), 0);
+    }
+}
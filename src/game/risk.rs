@@ -0,0 +1,248 @@
+//! Dangers on the road: ambushes between cities and inspections at the city gates.
+
+use serde::{Deserialize, Serialize};
+
+use super::city_data::CityData;
+use super::market::{BuildingType, ILLEGAL_RESOURCES, Resources};
+use super::strategic_map::{BuildinTable, Caravan, Faction, Player, TRANSPORT_DISTANCE};
+use crate::network::message::PlayerId;
+use crate::prelude::*;
+
+const AMBUSH_CHANCE_PER_LENGTH: f64 = 0.05;
+const GOBLIN_TERRITORY_CHANCE: f64 = 0.15;
+const ESCORT_FACTOR: f64 = 0.25;
+const MAX_AMBUSH_CHANCE: f64 = 0.8;
+/// Extra inspection chance for every kind of contraband carried.
+const CONTRABAND_SUSPICION: f64 = 0.1;
+/// Incidents kept in a caravan's or city's log.
+pub const INCIDENT_LOG_LENGTH: usize = 5;
+/// Share of a city's inspection strictness that turns into a raid chance per
+/// illegal building each turn.
+const RAID_FACTOR: f64 = 0.5;
+const RAID_FINE_PER_TIER: f64 = 300.0;
+/// Strictness above which a city won't tolerate illegal buildings at all.
+const SMUGGLER_TOLERANCE: f64 = 0.35;
+
+#[derive(Clone, Reflect, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Incident {
+    Ambush {
+        from: String,
+        to: String,
+        lost: Vec<(Resources, usize)>,
+    },
+    Confiscation {
+        city: String,
+        lost: Vec<(Resources, usize)>,
+    },
+    Raid {
+        city: String,
+        building: String,
+        owner: PlayerId,
+        lost: Vec<(Resources, usize)>,
+        fine: u64,
+    },
+}
+
+impl Incident {
+    pub fn describe(&self) -> String {
+        let list = |lost: &Vec<(Resources, usize)>| {
+            lost.iter()
+                .map(|(res, amount)| format!("{amount} {}", res.get_name()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match self {
+            Incident::Ambush { from, to, lost } if lost.is_empty() => {
+                format!("Ambushed between {from} and {to}, nothing was taken")
+            }
+            Incident::Ambush { from, to, lost } => {
+                format!("Ambushed between {from} and {to}, lost {}", list(lost))
+            }
+            Incident::Confiscation { city, lost } => {
+                format!("Guards in {city} confiscated {}", list(lost))
+            }
+            Incident::Raid {
+                city,
+                building,
+                owner,
+                lost,
+                fine,
+            } if lost.is_empty() => {
+                format!("Guards raided {building} in {city}, player {owner} was fined {fine}$")
+            }
+            Incident::Raid {
+                city,
+                building,
+                owner,
+                lost,
+                fine,
+            } => format!(
+                "Guards raided {building} in {city}, player {owner} was fined {fine}$ and lost {}",
+                list(lost)
+            ),
+        }
+    }
+}
+
+/// Chance of an ambush on a road with the given cost. Large cities patrol the
+/// roads around them while goblin territory is lawless.
+pub fn ambush_chance(cost: f32, from: &CityData, to: &CityData, escorted: bool) -> f64 {
+    let length = (cost / TRANSPORT_DISTANCE) as f64;
+    let patrolled = from.population.max(to.population) as f64 / 5.0;
+    let mut chance = AMBUSH_CHANCE_PER_LENGTH * length * (1.5 - patrolled);
+    if from.race == BuildingType::Goblin || to.race == BuildingType::Goblin {
+        chance += GOBLIN_TERRITORY_CHANCE;
+    }
+    if escorted {
+        chance *= ESCORT_FACTOR;
+    }
+    chance.clamp(0.0, MAX_AMBUSH_CHANCE)
+}
+
+/// How strictly a race's cities search caravans, and which goods they forbid.
+pub fn contraband_law(race: BuildingType) -> (f64, &'static [Resources]) {
+    match race {
+        BuildingType::Human => (0.3, &[Resources::Slaves, Resources::Vitae]),
+        BuildingType::Elven => (0.4, &ILLEGAL_RESOURCES),
+        BuildingType::Dwarven => (0.3, &[Resources::Drugs, Resources::Vitae]),
+        BuildingType::Goblin => (0.0, &[]),
+        _ => (0.1, &ILLEGAL_RESOURCES),
+    }
+}
+
+/// Goods carried by the caravan, sorted so that dice rolls happen in the same
+/// order on every peer.
+fn sorted_cargo(caravan: &Caravan) -> Vec<(Resources, usize)> {
+    let mut goods: Vec<_> = caravan
+        .cargo
+        .iter()
+        .filter(|(_, amount)| **amount > 0)
+        .map(|(res, amount)| (*res, *amount))
+        .collect();
+    goods.sort();
+    goods
+}
+
+/// Rolls for an ambush on the road between two cities. Bandits take between a
+/// quarter and all of every good carried. `safety` scales the chance down for
+/// owners of road-guarding wonders.
+pub fn roll_ambush(
+    caravan: &mut Caravan,
+    cost: f32,
+    from: &CityData,
+    to: &CityData,
+    safety: f64,
+    rng: &mut ResMut<GlobalRng>,
+) -> Option<Incident> {
+    if rng.random::<f64>() >= ambush_chance(cost, from, to, caravan.escorted) * safety {
+        return None;
+    }
+    let mut lost = vec![];
+    for (res, amount) in sorted_cargo(caravan) {
+        let taken = (amount as f64 * rng.random_range(0.25..=1.0)).round() as usize;
+        if taken > 0 {
+            caravan.cargo.insert(res, amount - taken);
+            lost.push((res, taken));
+        }
+    }
+    Some(Incident::Ambush {
+        from: from.id.clone(),
+        to: to.id.clone(),
+        lost,
+    })
+}
+
+/// Rolls for an inspection when arriving in a city. Caravans carrying forbidden
+/// goods draw more attention, and inspectors confiscate all of them.
+pub fn roll_inspection(
+    caravan: &mut Caravan,
+    city: &CityData,
+    rng: &mut ResMut<GlobalRng>,
+) -> Option<Incident> {
+    let (strictness, forbidden) = contraband_law(city.race);
+    let contraband: Vec<_> = sorted_cargo(caravan)
+        .into_iter()
+        .filter(|(res, _)| forbidden.contains(res))
+        .collect();
+    if contraband.is_empty() {
+        return None;
+    }
+    let chance = strictness + CONTRABAND_SUSPICION * contraband.len() as f64;
+    if strictness == 0.0 || rng.random::<f64>() >= chance {
+        return None;
+    }
+    for (res, _) in &contraband {
+        caravan.cargo.insert(*res, 0);
+    }
+    Some(Incident::Confiscation {
+        city: city.id.clone(),
+        lost: contraband,
+    })
+}
+
+/// Small towns outside the strictest realms look the other way, so smugglers can
+/// set up shop there.
+pub fn allows_smugglers(city: &CityData) -> bool {
+    let (strictness, _) = contraband_law(city.race);
+    strictness < SMUGGLER_TOLERANCE && city.population < 5
+}
+
+/// Chance per turn that the guards raid one illegal building in the city.
+pub fn raid_chance(city: &CityData) -> f64 {
+    contraband_law(city.race).0 * RAID_FACTOR
+}
+
+/// Rolls for a raid on every illegal building owned by a player. Raided owners
+/// lose the forbidden goods in their warehouse and pay a fine by tier.
+pub fn roll_raids(
+    city: &mut CityData,
+    building_table: &Res<BuildinTable>,
+    players: &mut Query<&mut Player>,
+    rng: &mut ResMut<GlobalRng>,
+) -> Vec<Incident> {
+    let (_, forbidden) = contraband_law(city.race);
+    let chance = raid_chance(city);
+    if chance == 0.0 {
+        return vec![];
+    }
+    let mut suspects: Vec<_> = city
+        .buildings
+        .iter()
+        .filter_map(|b| match b.owner {
+            Faction::Player(owner) => Some((owner, b.building_id.clone())),
+            Faction::Neutral => None,
+        })
+        .filter_map(|(owner, id)| {
+            let building = building_table.0.get(&id)?;
+            (building.build_type == BuildingType::Illegal).then_some((owner, id, building.tier))
+        })
+        .collect();
+    suspects.sort();
+
+    let mut raids = vec![];
+    for (owner, building, tier) in suspects {
+        if rng.random::<f64>() >= chance {
+            continue;
+        }
+        let mut lost = vec![];
+        for res in forbidden {
+            let stored = city.stored(owner, *res);
+            if stored > 0 {
+                city.warehouse_mut(owner)[*res] = 0;
+                lost.push((*res, stored as usize));
+            }
+        }
+        let fine = RAID_FINE_PER_TIER * tier as f64;
+        if let Some(mut player) = players.iter_mut().find(|p| p.player_id == owner) {
+            player.money -= fine;
+        }
+        raids.push(Incident::Raid {
+            city: city.id.clone(),
+            building,
+            owner,
+            lost,
+            fine: fine as u64,
+        });
+    }
+    raids
+}
@@ -0,0 +1,169 @@
+//! Road types between cities and the upgrades players can pay for.
+
+use serde::{Deserialize, Serialize};
+
+use super::city_data::CityData;
+use super::city_graph::{CityEdge, CityGraph, Node as CityNode};
+use super::terrain::Terrain;
+use crate::NetworkState;
+use crate::network::message::{ClientMessage, NetworkMessage, ServerMessage};
+use crate::prelude::*;
+
+/// Water crossings shorter than this are rivers, longer ones sea lanes.
+const RIVER_LENGTH: f32 = 150.0;
+/// Money per unit of road length and terrain cost to upgrade a road.
+const UPGRADE_COST_PER_UNIT: f64 = 2.0;
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_observer(apply_road_upgrade)
+        .add_observer(client::send_road_upgrade)
+        .add_observer(server::send_road_upgrade);
+}
+
+#[derive(Reflect, Clone, Copy, Default, Eq, PartialEq, Debug, Hash, Serialize, Deserialize)]
+pub enum RoadType {
+    #[default]
+    Trail,
+    Road,
+    Highway,
+    River,
+    SeaLane,
+}
+
+impl RoadType {
+    /// The lowest `cost_factor` of any road.
+    pub const MIN_COST_FACTOR: f32 = 0.5;
+
+    /// The road a crossing starts out with before anyone builds on it.
+    pub fn natural(terrain: Terrain, distance: f32) -> Self {
+        match terrain {
+            Terrain::Water if distance < RIVER_LENGTH => RoadType::River,
+            Terrain::Water => RoadType::SeaLane,
+            _ => RoadType::Trail,
+        }
+    }
+
+    /// Share of the terrain's travel cost that remains on this road.
+    pub fn cost_factor(&self) -> f32 {
+        match self {
+            RoadType::Trail => 1.0,
+            RoadType::Road => 0.7,
+            RoadType::Highway => 0.5,
+            RoadType::River => 0.6,
+            RoadType::SeaLane => 0.5,
+        }
+    }
+
+    pub fn upgrade(&self) -> Option<RoadType> {
+        match self {
+            RoadType::Trail => Some(RoadType::Road),
+            RoadType::Road => Some(RoadType::Highway),
+            _ => None,
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            RoadType::Trail => Color::linear_rgb(0.55, 0.27, 0.075),
+            RoadType::Road => Color::linear_rgb(0.35, 0.2, 0.1),
+            RoadType::Highway => Color::linear_rgb(0.15, 0.15, 0.15),
+            RoadType::River => Color::linear_rgb(0.2, 0.4, 0.9),
+            RoadType::SeaLane => Color::linear_rgb(0.6, 0.8, 1.0),
+        }
+    }
+}
+
+impl CityEdge {
+    /// What upgrading this road to the next type costs, if it can be upgraded.
+    pub fn upgrade_cost(&self) -> Option<(RoadType, f64)> {
+        let next = self.road.upgrade()?;
+        let tier = if next == RoadType::Highway { 2.0 } else { 1.0 };
+        let cost =
+            self.distance as f64 * self.terrain.travel_cost() as f64 * UPGRADE_COST_PER_UNIT * tier;
+        Some((next, cost.round()))
+    }
+}
+
+/// A road between two cities, by id, was upgraded.
+#[derive(Event, Clone, Debug)]
+pub struct RoadUpgraded {
+    pub from: String,
+    pub to: String,
+    pub road: RoadType,
+}
+
+/// Sets the road type on the edge between two cities.
+pub fn upgrade_road(
+    graph: &mut CityGraph,
+    cities: &Query<(&CityNode, &CityData)>,
+    from: &str,
+    to: &str,
+    road: RoadType,
+) {
+    let find = |id: &str| {
+        cities
+            .iter()
+            .find(|(_, city)| city.id == id)
+            .map(|(node, _)| node.0)
+    };
+    let Some(edge) = find(from)
+        .zip(find(to))
+        .and_then(|(a, b)| graph.edge_between(a, b))
+    else {
+        error!("No road between {from} and {to}");
+        return;
+    };
+    graph.set_road(edge, road);
+}
+
+fn apply_road_upgrade(
+    upgraded: On<RoadUpgraded>,
+    mut graph: ResMut<CityGraph>,
+    cities: Query<(&CityNode, &CityData)>,
+) {
+    upgrade_road(
+        &mut graph,
+        &cities,
+        &upgraded.from,
+        &upgraded.to,
+        upgraded.road,
+    );
+}
+
+fn road_message(upgraded: &RoadUpgraded) -> NetworkMessage {
+    NetworkMessage::RoadUpgraded {
+        from: upgraded.from.clone(),
+        to: upgraded.to.clone(),
+        road: upgraded.road,
+    }
+}
+
+mod client {
+    use super::*;
+
+    pub fn send_road_upgrade(
+        upgraded: On<RoadUpgraded>,
+        mut writer: crate::network::client::Writer,
+        network_state: Res<State<NetworkState>>,
+    ) {
+        if *network_state != NetworkState::Client {
+            return;
+        }
+        writer.write(ClientMessage(road_message(&upgraded)));
+    }
+}
+
+mod server {
+    use super::*;
+
+    pub fn send_road_upgrade(
+        upgraded: On<RoadUpgraded>,
+        mut writer: crate::network::server::Writer,
+        network_state: Res<State<NetworkState>>,
+    ) {
+        if *network_state != NetworkState::Host {
+            return;
+        }
+        writer.write(ServerMessage(road_message(&upgraded)));
+    }
+}
@@ -0,0 +1,387 @@
+//! Dry run of a caravan's orders, used by the caravan menu to preview a full loop.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::building_slot::{InputSource, OutputDestination};
+use super::city_data::CityData;
+use super::city_graph::{CityGraph, Node as CityNode, Route, get_path};
+use super::intel::{MarketIntel, MarketView};
+use super::market::Resources;
+use super::relations::RaceRelations;
+use super::reputation::{Reputation, buy_factor, sell_factor};
+use super::risk::ambush_chance;
+use super::strategic_map::{BuildinTable, Caravan, Faction, Order};
+use super::trade::TradeLedger;
+use super::wonders::WonderBonuses;
+use crate::network::message::PlayerId;
+use crate::prelude::*;
+
+#[derive(Clone, Debug)]
+pub struct PlannedTrade {
+    pub resource: Resources,
+    pub amount: isize,
+    /// Market price paid or earned, `None` for warehouse transfers.
+    pub price: Option<f64>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PlannedStop {
+    pub city_id: String,
+    /// Number of edges travelled to get here, which is also the number of turns.
+    pub hops: usize,
+    pub distance: f32,
+    /// Transportation used on the way here, ignoring rations.
+    pub transport: usize,
+    /// Chance of at least one ambush on the way here.
+    pub ambush_risk: f64,
+    /// Paid at the borders crossed on the way here.
+    pub tariffs: f64,
+    pub trades: Vec<PlannedTrade>,
+    pub cargo: BTreeMap<Resources, usize>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RoutePlan {
+    pub stops: Vec<PlannedStop>,
+    pub cost: f64,
+    pub revenue: f64,
+}
+
+impl RoutePlan {
+    pub fn profit(&self) -> f64 {
+        self.revenue - self.cost
+    }
+
+    pub fn turns(&self) -> usize {
+        self.stops.iter().map(|stop| stop.hops).sum()
+    }
+}
+
+/// Walks through one full cycle of the caravan's orders, starting at its current
+/// order, without touching the real markets or warehouses. Markets are priced as
+/// the player last saw them, borders and reputation as they are now.
+pub fn plan_route(
+    caravan: &Caravan,
+    player_id: PlayerId,
+    graph: &Res<CityGraph>,
+    cities: &Query<(&CityNode, &CityData)>,
+    intel: &MarketIntel,
+    building_table: &Res<BuildinTable>,
+    relations: &RaceRelations,
+    reputation: &Reputation,
+) -> RoutePlan {
+    let mut plan = RoutePlan::default();
+    let bonuses = WonderBonuses::of(player_id, cities.iter().map(|(_, city)| city));
+    // Copies of the visited cities, so repeated visits see earlier trades.
+    let mut scratch: HashMap<String, (CityNode, CityData)> = HashMap::new();
+
+    let mut cargo: BTreeMap<Resources, usize> = caravan
+        .cargo
+        .iter()
+        .map(|(res, amount)| (*res, *amount))
+        .collect();
+    let mut position = caravan.position_city_id.clone();
+    let race = |e: Entity| cities.get(e).ok().map(|(_, city)| city.race);
+
+    let order_count = caravan.orders.len();
+    for i in 0..order_count {
+        let order = &caravan.orders[(caravan.order_idx + i) % order_count];
+        let mut stop = PlannedStop {
+            city_id: order.goal_city_id.clone(),
+            ..default()
+        };
+
+        let view = intel.view(player_id, &order.goal_city_id);
+        let Some((goal_node, mut city)) = lookup(&mut scratch, cities, &view, &order.goal_city_id)
+        else {
+            stop.warnings
+                .push(format!("{} does not exist", order.goal_city_id));
+            plan.stops.push(stop);
+            continue;
+        };
+
+        let departure = lookup(&mut scratch, cities, &MarketView::Live, &position);
+        let route =
+            departure.map(|(node, _)| relations.open_route(graph, node.0, goal_node.0, race));
+        match route {
+            Some(Ok(Route { cost, path })) => {
+                stop.hops = path.len().saturating_sub(1);
+                stop.distance = cost;
+                let mut safe = 1.0;
+                for leg in path.windows(2) {
+                    let Ok([(a, from), (b, to)]) = cities.get_many([leg[0], leg[1]]) else {
+                        continue;
+                    };
+                    let Some(road) = graph.edge_between(a.0, b.0) else {
+                        continue;
+                    };
+                    let cost = graph.edge_cost(road);
+                    stop.transport += bonuses.transport(Caravan::transport_needed(cost, false));
+                    safe *= 1.0
+                        - ambush_chance(cost, from, to, caravan.provisions.escort)
+                            * bonuses.ambush_factor;
+                    if from.race != to.race {
+                        let carried = cargo.iter().map(|(res, amount)| (*res, *amount));
+                        stop.tariffs += relations.tariff(carried, from.race, to);
+                    }
+                }
+                stop.ambush_risk = 1.0 - safe;
+                plan.cost += stop.tariffs;
+            }
+            Some(Err(e)) => stop.warnings.push(format!("Unreachable: {e}")),
+            None => stop.warnings.push(format!("Caravan is lost in {position}")),
+        }
+
+        match view {
+            MarketView::Live => {}
+            MarketView::Stale { .. } => stop
+                .warnings
+                .push(format!("{} in {}", view.describe(), city.id)),
+            MarketView::Unknown => stop
+                .warnings
+                .push(format!("Prices in {} are unknown", city.id)),
+        }
+        let unknown = matches!(view, MarketView::Unknown);
+        let standing = reputation.in_city(player_id, &city);
+
+        let available = city.available_commodities(building_table);
+        for (&resource, &(amount, open_market)) in &order.trade_order {
+            let in_cargo = *cargo.get(&resource).unwrap_or(&0) as isize;
+            let mut trade = PlannedTrade {
+                resource,
+                amount: 0,
+                price: None,
+            };
+
+            if open_market && unknown {
+                continue;
+            }
+            if amount > 0 && open_market {
+                if !available.contains(&resource) && !resource.is_illegal() {
+                    stop.warnings.push(format!(
+                        "{} is never sold in {}",
+                        resource.get_name(),
+                        city.id
+                    ));
+                    continue;
+                }
+                let stock = city.stock(&resource);
+                let mut bought = amount.min(stock);
+                if bought < 0 {
+                    bought = amount
+                }
+                let price =
+                    city.get_bulk_buy_price(&resource, bought as usize) * buy_factor(standing);
+                *city.stock_mut(&resource) = stock - bought;
+                plan.cost += price;
+                trade.amount = bought;
+                trade.price = Some(price);
+            } else if amount > 0 {
+                let stored = city.stored(player_id, resource);
+                let taken = amount.min(stored).max(0);
+                if taken < amount {
+                    stop.warnings.push(format!(
+                        "Warehouse only holds {} {}",
+                        stored.max(0),
+                        resource.get_name()
+                    ));
+                }
+                city.take(player_id, resource, taken);
+                trade.amount = taken;
+            } else if amount < 0 {
+                let mut moved = amount.abs().min(in_cargo);
+                if moved < amount.abs() {
+                    stop.warnings.push(format!(
+                        "Only {} {} in cargo",
+                        in_cargo,
+                        resource.get_name()
+                    ));
+                }
+                if open_market {
+                    let stock = city.stock(&resource);
+                    let price = city.get_bulk_sell_price(&resource, moved as usize)
+                        * bonuses.sale_factor
+                        * sell_factor(standing);
+                    *city.stock_mut(&resource) = stock + moved;
+                    plan.revenue += price;
+                    trade.price = Some(price);
+                } else {
+                    let stored = city.store(player_id, resource, moved);
+                    if stored < moved {
+                        stop.warnings.push(format!(
+                            "No room for {} {} in the warehouse",
+                            moved - stored,
+                            resource.get_name()
+                        ));
+                    }
+                    moved = stored;
+                }
+                trade.amount = -moved;
+            }
+
+            let new_amount = (in_cargo + trade.amount).max(0) as usize;
+            cargo.insert(resource, new_amount);
+            stop.trades.push(trade);
+        }
+
+        cargo.retain(|_, amount| *amount > 0);
+        stop.cargo = cargo.clone();
+        scratch.insert(city.id.clone(), (goal_node, city));
+        position = order.goal_city_id.clone();
+        plan.stops.push(stop);
+    }
+
+    plan
+}
+
+/// The city as planned so far, starting out as `view` shows it.
+fn lookup(
+    scratch: &mut HashMap<String, (CityNode, CityData)>,
+    cities: &Query<(&CityNode, &CityData)>,
+    view: &MarketView,
+    id: &String,
+) -> Option<(CityNode, CityData)> {
+    if !scratch.contains_key(id) {
+        let (node, data) = cities.iter().find(|(_, city)| &city.id == id)?;
+        let data = view.known(data).unwrap_or(data);
+        scratch.insert(id.clone(), (node.clone(), data.clone()));
+    }
+    scratch.get(id).cloned()
+}
+
+/// Turns of input an auto-supply caravan tries to keep in each warehouse.
+pub const AUTO_SUPPLY_TURNS: isize = 5;
+
+/// What the player's buildings in a city need from their warehouse, minus what is
+/// already stored there. Negative amounts are spare stock that can be hauled away:
+/// goods the player's buildings put into the warehouse, less what construction
+/// and open trades in the city still have to draw on.
+fn warehouse_balance(
+    city: &CityData,
+    player_id: PlayerId,
+    building_table: &Res<BuildinTable>,
+    ledger: &TradeLedger,
+) -> BTreeMap<Resources, isize> {
+    let mut balance = BTreeMap::new();
+    let mut produced = HashSet::new();
+    for slot in city.buildings.owned_by(player_id) {
+        let Some(building) = building_table.0.get(&slot.building_id) else {
+            continue;
+        };
+        if slot.output == OutputDestination::Warehouse {
+            produced.extend(building.output.keys().copied());
+        }
+        if slot.input != InputSource::Warehouse {
+            continue;
+        }
+        for (res, amount) in &building.input {
+            *balance.entry(*res).or_insert(0) += amount * AUTO_SUPPLY_TURNS;
+        }
+    }
+    for (res, amount) in city.warehouse(player_id).iter().flat_map(|w| w.iter()) {
+        *balance.entry(res).or_insert(0) -= amount;
+    }
+
+    let mut reserved: HashMap<Resources, isize> = HashMap::new();
+    let projects = city
+        .construction
+        .iter()
+        .filter(|p| p.owner == Faction::Player(player_id));
+    for (res, missing) in projects.flat_map(|p| &p.materials) {
+        *reserved.entry(*res).or_default() += (*missing).max(0);
+    }
+    let trades = ledger
+        .offers
+        .iter()
+        .filter(|o| o.seller == player_id && o.city_id == city.id);
+    for trade in trades {
+        *reserved.entry(trade.resource).or_default() += trade.amount;
+    }
+    for (res, amount) in balance.iter_mut() {
+        if *amount < 0 {
+            let spare = if produced.contains(res) {
+                -*amount - reserved.get(res).copied().unwrap_or(0)
+            } else {
+                0
+            };
+            *amount = -spare.max(0);
+        }
+    }
+    balance
+}
+
+/// Orders an auto-supply caravan follows this turn. Visits the assigned cities in
+/// a nearest-neighbour loop, picking up spare warehouse stock wherever another
+/// assigned city needs it and dropping off what each city's buildings consume.
+pub fn plan_auto_supply(
+    caravan: &Caravan,
+    player_id: PlayerId,
+    graph: &Res<CityGraph>,
+    cities: &[(&CityNode, &CityData)],
+    building_table: &Res<BuildinTable>,
+    ledger: &TradeLedger,
+) -> Vec<Order> {
+    let mut assigned: Vec<&String> = vec![];
+    for order in &caravan.orders {
+        if !assigned.contains(&&order.goal_city_id) {
+            assigned.push(&order.goal_city_id);
+        }
+    }
+    let mut stops: Vec<(&CityNode, &CityData)> = assigned
+        .iter()
+        .filter_map(|id| cities.iter().find(|(_, city)| &&city.id == id).copied())
+        .collect();
+    if stops.is_empty() {
+        return vec![];
+    }
+
+    // Order the stops into a loop, unreachable cities go last
+    let mut tour = vec![stops.remove(0)];
+    while !stops.is_empty() {
+        let (last, _) = tour[tour.len() - 1];
+        let nearest = (0..stops.len())
+            .min_by(|&a, &b| {
+                let cost = |i: usize| {
+                    get_path(graph, last.0, stops[i].0.0)
+                        .map(|(cost, _)| cost)
+                        .unwrap_or(f32::INFINITY)
+                };
+                cost(a).total_cmp(&cost(b))
+            })
+            .unwrap_or(0);
+        tour.push(stops.remove(nearest));
+    }
+
+    let balances: Vec<_> = tour
+        .iter()
+        .map(|(_, city)| warehouse_balance(city, player_id, building_table, ledger))
+        .collect();
+    let mut wanted: BTreeMap<Resources, isize> = BTreeMap::new();
+    for balance in &balances {
+        for (res, amount) in balance {
+            if *amount > 0 {
+                *wanted.entry(*res).or_insert(0) += amount;
+            }
+        }
+    }
+
+    tour.iter()
+        .zip(&balances)
+        .map(|((_, city), balance)| {
+            let mut trade_order = BTreeMap::new();
+            for (res, amount) in balance {
+                if *amount > 0 {
+                    trade_order.insert(*res, (-amount, false));
+                } else if let Some(need) = wanted.get(res) {
+                    trade_order.insert(*res, ((-amount).min(*need), false));
+                }
+            }
+            trade_order.retain(|_, (amount, _)| *amount != 0);
+            Order {
+                goal_city_id: city.id.clone(),
+                trade_order,
+            }
+        })
+        .collect()
+}
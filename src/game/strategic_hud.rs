@@ -7,11 +7,34 @@ use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
 use bevy::math::usize;
 use bevy::picking::hover::HoverMap;
 use bevy::ui::InteractionDisabled;
+use bevy_simple_text_input::{TextInput, TextInputValue};
+use petgraph::visit::EdgeRef;
 
+use super::building_slot::{
+    BuildingOffer, BuildingSlot, ConstructionProject, InputSource, OutputDestination, TIERS,
+};
 use super::city_data::CityData;
+use super::city_graph::{CityGraph, Node as CityNode};
+use super::intel::{MarketIntel, MarketView};
+use super::map_definition::PLAYABLE_RACES;
 use super::market::*;
-use super::strategic_map::{Caravan, Order, Player, SelectedCaravan, SelectedCity, StrategicState};
+use super::relations::RaceRelations;
+use super::reputation::{
+    Reputation, buy_factor, describe, may_construct, purchase_factor, required_for_tier,
+    sell_factor,
+};
+use super::risk::{allows_smugglers, raid_chance};
+use super::roads::{RoadType, RoadUpgraded};
+use super::route_planner::{RoutePlan, plan_route};
+use super::strategic_map::{
+    Caravan, CaravanMode, Order, Player, SelectedCaravan, SelectedCity, StrategicState,
+};
 use super::tooltip::Tooltips;
+use super::trade::{
+    CONTRACT_TURNS, TRADE_LOT, TradeAnswered, TradeId, TradeLedger, TradeOffer, TradeProposed,
+};
+use super::warehouse::{BUILT_CAPACITY, EXPANSION_CAPACITY, RENTED_CAPACITY, Tenure, Warehouse};
+use super::wonders::{WONDERS, is_wonder, wonder, wonder_site};
 use crate::GameState;
 use crate::NetworkState;
 use crate::game::market;
@@ -36,6 +59,10 @@ pub fn plugin(app: &mut App) {
         .add_systems(OnEnter(PopupHUD::Caravan), caravan_menu)
         .add_systems(OnEnter(PopupHUD::Wares), wares_menu)
         .add_systems(OnEnter(PopupHUD::Finance), finance_menu)
+        .add_systems(OnEnter(PopupHUD::Wonders), wonders_menu)
+        .add_systems(OnEnter(PopupHUD::Trade), trade_menu)
+        .add_systems(OnEnter(PopupHUD::Roads), roads_menu)
+        .add_systems(OnEnter(PopupHUD::Diplomacy), diplomacy_menu)
         .add_systems(
             Update,
             caravan_destination_buttons.run_if(in_state(StrategicState::DestinationPicker)),
@@ -60,6 +87,27 @@ pub fn plugin(app: &mut App) {
             Update,
             building_button.run_if(in_state(PopupHUD::Buildings)),
         )
+        .add_systems(Update, warehouse_button.run_if(in_state(PopupHUD::Wares)))
+        .add_systems(Update, trade_button.run_if(in_state(PopupHUD::Trade)))
+        .add_systems(
+            Update,
+            (kill_popup_menu, trade_menu)
+                .chain()
+                .run_if(in_state(PopupHUD::Trade).and(resource_changed::<TradeLedger>)),
+        )
+        .add_systems(Update, road_button.run_if(in_state(PopupHUD::Roads)))
+        .add_systems(
+            Update,
+            (kill_popup_menu, roads_menu)
+                .chain()
+                .run_if(in_state(PopupHUD::Roads).and(resource_changed::<CityGraph>)),
+        )
+        .add_systems(
+            Update,
+            (kill_popup_menu, update_buildings, wares_menu)
+                .chain()
+                .run_if(in_state(PopupHUD::Wares).and(resource_changed::<SelectedCity>)),
+        )
         .add_systems(
             Update,
             (kill_popup_menu, update_buildings, building_menu)
@@ -107,6 +155,10 @@ pub enum PopupHUD {
     Caravan,
     Wares,
     Finance,
+    Wonders,
+    Trade,
+    Roads,
+    Diplomacy,
 }
 
 #[derive(Resource, Deref, DerefMut)]
@@ -237,6 +289,18 @@ fn no_popup_button(
                 HudButton::FinanceAction => {
                     tab_state.set(PopupHUD::Finance);
                 }
+                HudButton::WondersAction => {
+                    tab_state.set(PopupHUD::Wonders);
+                }
+                HudButton::RoadsAction => {
+                    tab_state.set(PopupHUD::Roads);
+                }
+                HudButton::TradeAction => {
+                    tab_state.set(PopupHUD::Trade);
+                }
+                HudButton::DiplomacyAction => {
+                    tab_state.set(PopupHUD::Diplomacy);
+                }
             },
             Interaction::Hovered => {
                 if *menu_button_action != HudButton::KillHud {
@@ -359,8 +423,15 @@ fn building_menu(
     mut commands: Commands,
     city: ResMut<SelectedCity>,
     building_table: Res<BuildinTable>,
+    intel: Res<MarketIntel>,
+    you: Single<&Player, With<ActivePlayer>>,
     mut sylt: Sylt,
 ) {
+    let view = intel.view(you.player_id, &city.id);
+    if !matches!(view, MarketView::Live) {
+        distant_building_menu(&mut commands, &city, &view);
+        return;
+    }
     let window = popup_window(&mut commands, FlexDirection::Row);
     commands.entity(window).with_children(|parent| {
         parent
@@ -389,26 +460,21 @@ fn building_menu(
                         .with_children(|parent| {
                             for building_slot in 0..population - tiers {
                                 //println!("Tier {} has slot {}", tiers, building_slot);
-                                if let Some(building) = match tiers {
-                                    1 => city.buildings_t1.get(building_slot as usize),
-                                    2 => city.buildings_t2.get(building_slot as usize),
-                                    3 => city.buildings_t3.get(building_slot as usize),
-                                    4 => city.buildings_t4.get(building_slot as usize),
-                                    5 => city.buildings_t5.get(building_slot as usize),
-                                    _ => None,
-                                } {
-                                    println!("Found building {}", building.0);
+                                if let Some(building) =
+                                    city.buildings.slot(tiers as usize, building_slot as usize)
+                                {
+                                    println!("Found building {}", building.building_id);
                                     let mut production_text = "Produces: ".to_string();
                                     let mut consumption_text = "Consumes: ".to_string();
                                     let mut production = vec![];
                                     let mut consumption = vec![];
                                     for prod in &building_table
                                         .0
-                                        .get(&building.0)
+                                        .get(&building.building_id)
                                         .expect(
                                             format!(
                                                 "Tried to access invalid building {:?}",
-                                                building.0
+                                                building.building_id
                                             )
                                             .as_str(),
                                         )
@@ -418,11 +484,11 @@ fn building_menu(
                                     }
                                     for cons in &building_table
                                         .0
-                                        .get(&building.0)
+                                        .get(&building.building_id)
                                         .expect(
                                             format!(
                                                 "Tried to access invalid building {:?}",
-                                                building.0
+                                                building.building_id
                                             )
                                             .as_str(),
                                         )
@@ -508,7 +574,7 @@ fn building_menu(
                                     margin: UiRect::all(px(16)),
                                     ..default()
                                 },
-                                        ImageNode {image: sylt.get_image( match building.1 {
+                                        ImageNode {image: sylt.get_image( match building.owner {
                                             Faction::Neutral => {
                                                 "player_gray"
                                             }
@@ -524,16 +590,49 @@ fn building_menu(
                                         }
                                         }
                                         )
-                                                   ,..default()},
+                                                   , ..default()},
                                 BackgroundColor(Srgba::new(0.5, 0.2, 0.9, 1.0).into()),
                                 Button,
+                                children![(
+                                    Node {
+                                        position_type: PositionType::Absolute,
+                                        width: percent(100),
+                                        height: percent(100),
+                                        ..default()
+                                    },
+                                    ImageNode::new(sylt.get_image(&format!(
+                                        "building_level_{}",
+                                        building.level.min(MAX_LEVEL_SPRITE)
+                                    ))),
+                                    if building.level > 1 {
+                                        Visibility::Inherited
+                                    } else {
+                                        Visibility::Hidden
+                                    },
+                                ), (
+                                    Node {
+                                        position_type: PositionType::Absolute,
+                                        bottom: px(0),
+                                        right: px(2),
+                                        ..default()
+                                    },
+                                    Text::new(if building.level > 1 {
+                                        format!("Lv {}", building.level)
+                                    } else {
+                                        String::new()
+                                    }),
+                                    TextFont {
+                                        font_size: 16.0,
+                                        ..default()
+                                    },
+                                )],
                                         BuildingButton::EditBuilding(
                                             tiers as usize,
                                             building_slot as usize,
                                         ),
                                 related!(
                                     Tooltips[(
-                                        Text::new(building.0.clone()),
+                                        Text::new(building.building_id.clone()),
                                         TextShadow::default(),
                                         // Set the justification of the Text
                                         TextLayout::new_with_justify(Justify::Center),
@@ -556,9 +655,57 @@ fn building_menu(
                                         TextLayout::new_with_justify(Justify::Center),
                                         // Set the style of the Node itself.
                                         Node { ..default() }
+                                    ),
+                                    (
+                                        Text::new(building.describe_efficiency()),
+                                        TextShadow::default(),
+                                        TextLayout::new_with_justify(Justify::Center),
+                                        Node { ..default() }
                                     )]
                                 ),
                             ));
+                                } else if let Some(project) = city
+                                    .construction
+                                    .iter()
+                                                    .filter(|p| p.tier == tiers as usize && p.takes_slot())
+                                    // Projects fill the empty slots in order
+                                    .nth(
+                                        (0..building_slot as usize)
+                                            .filter(|s| {
+                                                city.buildings.slot(tiers as usize, *s).is_none()
+                                            })
+                                            .count(),
+                                    )
+                                {
+                                    parent.spawn((
+                                        Node {
+                                            width: px(64),
+                                            height: px(64),
+                                            margin: UiRect::all(px(16)),
+                                            align_items: AlignItems::Center,
+                                            justify_content: JustifyContent::Center,
+                                            ..default()
+                                        },
+                                        BackgroundColor(Srgba::new(0.8, 0.6, 0.1, 1.0).into()),
+                                        children![(
+                                            Text::new(project.turns_left.to_string()),
+                                            TextFont {
+                                                font_size: 24.0,
+                                                ..default()
+                                            },
+                                        )],
+                                        related!(
+                                            Tooltips[(
+                                                Text::new(project.describe()),
+                                                TextShadow::default(),
+                                                TextLayout::new_with_justify(Justify::Center),
+                                                Node { ..default() },
+                                                BackgroundColor(
+                                                    Srgba::new(0.05, 0.05, 0.05, 1.0).into()
+                                                ),
+                                            )]
+                                        ),
+                                    ));
                                 } else {
                                     parent.spawn((
                                         Node {
@@ -591,6 +738,95 @@ fn building_menu(
                             }
                         });
                 }
+
+                // Wonders stand apart from the tiers, on top of the city
+                let wonder_project = city
+                    .construction
+                    .iter()
+                    .find(|p| is_wonder(&p.building_id));
+                let mut row = parent.spawn((
+                    Node {
+                        width: percent(100),
+                        height: percent(15),
+                        align_items: AlignItems::Center,
+                        justify_content: JustifyContent::Center,
+                        flex_direction: FlexDirection::Row,
+                        ..default()
+                    },
+                    Text::new("Wonder"),
+                ));
+                if let Some(built) = &city.wonder {
+                    let effect = wonder(&built.building_id)
+                        .map(|w| w.effect.describe())
+                        .unwrap_or_default();
+                    row.with_child((
+                        Node {
+                            width: px(64),
+                            height: px(64),
+                            margin: UiRect::all(px(16)),
+                            ..default()
+                        },
+                        BackgroundColor(Srgba::new(1.0, 0.84, 0.0, 1.0).into()),
+                        related!(
+                            Tooltips[(
+                                Text::new(format!("{}\n{effect}", built.building_id)),
+                                TextShadow::default(),
+                                TextLayout::new_with_justify(Justify::Center),
+                                Node { ..default() },
+                                BackgroundColor(Srgba::new(0.05, 0.05, 0.05, 1.0).into()),
+                            )]
+                        ),
+                    ));
+                } else if let Some(project) = wonder_project {
+                    row.with_child((
+                        Node {
+                            width: px(64),
+                            height: px(64),
+                            margin: UiRect::all(px(16)),
+                            align_items: AlignItems::Center,
+                            justify_content: JustifyContent::Center,
+                            ..default()
+                        },
+                        BackgroundColor(Srgba::new(0.8, 0.6, 0.1, 1.0).into()),
+                        children![(
+                            Text::new(project.turns_left.to_string()),
+                            TextFont {
+                                font_size: 24.0,
+                                ..default()
+                            },
+                        )],
+                        related!(
+                            Tooltips[(
+                                Text::new(project.describe()),
+                                TextShadow::default(),
+                                TextLayout::new_with_justify(Justify::Center),
+                                Node { ..default() },
+                                BackgroundColor(Srgba::new(0.05, 0.05, 0.05, 1.0).into()),
+                            )]
+                        ),
+                    ));
+                } else if city.can_hold_wonder() {
+                    row.with_child((
+                        Node {
+                            width: px(64),
+                            height: px(64),
+                            margin: UiRect::all(px(16)),
+                            ..default()
+                        },
+                        BackgroundColor(Srgba::new(0.6, 0.5, 0.1, 1.0).into()),
+                        Button,
+                        BuildingButton::NewWonder,
+                        related!(
+                            Tooltips[(
+                                Text::new("Build a wonder"),
+                                TextShadow::default(),
+                                TextLayout::new_with_justify(Justify::Center),
+                                Node { ..default() },
+                                BackgroundColor(Srgba::new(0.05, 0.05, 0.05, 1.0).into()),
+                            )]
+                        ),
+                    ));
+                }
             });
     });
 
@@ -608,6 +844,58 @@ fn building_menu(
     });
 }
 
+/// The buildings of a city nobody of yours is in, as they were last seen.
+fn distant_building_menu(commands: &mut Commands, city: &CityData, view: &MarketView) {
+    let window = popup_window(commands, FlexDirection::Column);
+    commands.entity(window).with_children(|parent| {
+        parent.spawn(Text::new(format!("{}: {}", city.id, view.describe())));
+        let Some(known) = view.known(city) else {
+            return;
+        };
+        let mut tier = 0;
+        for (slot_tier, _, building) in known.buildings.enumerate() {
+            if slot_tier != tier {
+                tier = slot_tier;
+                parent.spawn(Text::new(format!("----Tier {tier}----")));
+            }
+            parent.spawn(Text::new(format!(
+                "{} (level {}, owned by {})",
+                building.building_id,
+                building.level,
+                describe_owner(Some(building.owner))
+            )));
+        }
+    });
+}
+
+/// Highest level with a frame of its own, higher ones reuse it.
+const MAX_LEVEL_SPRITE: u8 = 5;
+
+fn building_action(label: String, action: BuildingButton) -> impl Bundle {
+    (
+        Button,
+        action,
+        Node {
+            height: px(48),
+            margin: UiRect::all(px(4)),
+            padding: UiRect::horizontal(px(8)),
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        BackgroundColor(Srgba::new(0.1, 0.1, 0.6, 1.0).into()),
+        children![Text::new(label)],
+    )
+}
+
+fn describe_materials(materials: &HashMap<Resources, isize>) -> String {
+    let mut materials: Vec<_> = materials
+        .iter()
+        .map(|(res, amount)| format!("{amount} {}", res.get_name()))
+        .collect();
+    materials.sort();
+    materials.join(", ")
+}
+
 fn finance_menu(
     mut commands: Commands,
     other_players: Query<&Player, Without<ActivePlayer>>,
@@ -720,6 +1008,79 @@ fn finance_menu(
     });
 }
 
+/// Every wonder of the map with who built it, or who is racing for it.
+fn wonders_menu(mut commands: Commands, cities: Query<&CityData>) {
+    let window = popup_window(&mut commands, FlexDirection::Column);
+    commands.entity(window).with_children(|parent| {
+        parent.spawn((
+            Node {
+                width: percent(100),
+                height: percent(15),
+                ..default()
+            },
+            Text::new("Wonders of the world"),
+        ));
+
+        for wonder in &WONDERS {
+            let status = match wonder_site(wonder.name, cities.iter()) {
+                Some(site) => {
+                    let owner = site.wonder.as_ref().map(|w| w.owner);
+                    format!("Built in {} by {}", site.id, describe_owner(owner))
+                }
+                None => {
+                    let mut racers: Vec<_> = cities
+                        .iter()
+                        .flat_map(|city| {
+                            city.construction
+                                .iter()
+                                .filter(|p| p.building_id == wonder.name)
+                                .map(move |p| {
+                                    format!(
+                                        "{} in {} ({} turns left)",
+                                        describe_owner(Some(p.owner)),
+                                        city.id,
+                                        p.turns_left
+                                    )
+                                })
+                        })
+                        .collect();
+                    racers.sort();
+                    if racers.is_empty() {
+                        "Nobody has started building it".to_string()
+                    } else {
+                        format!("Under construction by {}", racers.join(", "))
+                    }
+                }
+            };
+            parent.spawn((
+                Node {
+                    width: percent(100),
+                    height: percent(20),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    border: UiRect::all(px(4)),
+                    flex_direction: FlexDirection::Column,
+                    ..default()
+                },
+                BackgroundColor(Srgba::new(0.6, 0.5, 0.1, 1.0).into()),
+                BorderColor::all(Color::BLACK),
+                children![
+                    (Text::new(format!("----{}----", wonder.name))),
+                    (Text::new(wonder.effect.describe())),
+                    (Text::new(status)),
+                ],
+            ));
+        }
+    });
+}
+
+fn describe_owner(owner: Option<Faction>) -> String {
+    match owner {
+        Some(Faction::Player(player_id)) => format!("player {player_id}"),
+        _ => "nobody".to_string(),
+    }
+}
+
 #[derive(Reflect, Component, Default, Clone, Debug)]
 struct BuildingBrowser;
 
@@ -727,9 +1088,31 @@ struct BuildingBrowser;
 enum BuildingButton {
     NewBuilding(usize, usize),
     EditBuilding(usize, usize),
-    EditMarketSellStatus(usize, usize, bool),
-    EditMarketBuyStatus(usize, usize, bool),
+    EditMarketSellStatus(usize, usize, OutputDestination),
+    EditMarketBuyStatus(usize, usize, InputSource),
     BuildTypeButton(String, usize, usize),
+    /// An offer on a neutral building, in percent of what the city asks.
+    BidOnBuilding(usize, usize, u32),
+    SellBuilding(usize, usize),
+    OfferBuilding(usize, usize, PlayerId),
+    AcceptOffer(usize, usize),
+    DemolishBuilding(usize, usize),
+    UpgradeBuilding(usize, usize),
+    NewWonder,
+    BuildWonder(String),
+}
+
+/// Share of a building's price the city pays when buying it back.
+const SELL_BACK_FACTOR: f64 = 0.75;
+/// Offers a player can make on a neutral building, in percent of its price.
+const BIDS: [u32; 3] = [80, 100, 120];
+/// Share of its price a city still holds out for when a building stands idle.
+const IDLE_RESERVE: f64 = 0.7;
+
+/// Least a city accepts for one of its buildings. Buildings that can't run for
+/// lack of inputs are worth less to it.
+fn reserve_price(price: f64, building: &BuildingSlot) -> f64 {
+    price * (IDLE_RESERVE + (1.0 - IDLE_RESERVE) * building.efficiency.clamp(0.0, 1.0) as f64)
 }
 
 #[derive(Reflect, Component, Default, Clone, Debug)]
@@ -745,6 +1128,9 @@ enum CaravanMenuButtons {
     ToggleTradeStockpileExclusivity(String, Resources),
     KillTrade(String, Resources),
     ChangeTradeConfirm(String, Resources, Resources),
+    ToggleRations,
+    ToggleEscort,
+    ToggleAutoSupply,
 }
 
 fn caravan_menu(mut commands: Commands) {
@@ -771,6 +1157,13 @@ fn update_caravan_menu(
     selected_caravan: ResMut<SelectedCaravan>,
     caravans: Query<&Caravan>,
     cities: Query<&CityData>,
+    city_nodes: Query<(&CityNode, &CityData)>,
+    graph: Res<CityGraph>,
+    intel: Res<MarketIntel>,
+    building_table: Res<BuildinTable>,
+    relations: Res<RaceRelations>,
+    reputation: Res<Reputation>,
+    player: Query<&Player, With<ActivePlayer>>,
     mut commands: Commands,
 ) {
     info!("updating caravan menu");
@@ -778,6 +1171,20 @@ fn update_caravan_menu(
         error!("No selected caravan to display");
         return;
     };
+    let Ok(player) = player.single() else {
+        error!("No active player to plan routes for");
+        return;
+    };
+    let plan = plan_route(
+        selected_caravan,
+        player.player_id,
+        &graph,
+        &city_nodes,
+        &intel,
+        &building_table,
+        &relations,
+        &reputation,
+    );
 
     for caravan_box in caravan_box.iter() {
         commands.entity(caravan_box).despawn_children();
@@ -788,11 +1195,70 @@ fn update_caravan_menu(
                     height: px(64),
                     ..default()
                 },
-                Text::new(format!(
-                    "Caravan in {}",
-                    selected_caravan.position_city_id.clone()
-                )),
+                Text::new(if selected_caravan.leg_duration > 0 {
+                    format!(
+                        "Caravan leaving {}, turn {} of {} on the road",
+                        selected_caravan.position_city_id,
+                        selected_caravan.time_travelled + 1,
+                        selected_caravan.leg_duration
+                    )
+                } else {
+                    format!("Caravan in {}", selected_caravan.position_city_id.clone())
+                }),
             ));
+            let provisions = &selected_caravan.provisions;
+            parent
+                .spawn(Node {
+                    width: percent(100),
+                    flex_direction: FlexDirection::Row,
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn(toggle_button(
+                        "Rations (Food)",
+                        provisions.rations,
+                        CaravanMenuButtons::ToggleRations,
+                    ));
+                    parent.spawn(toggle_button(
+                        if selected_caravan.escorted {
+                            "Escort (Military), guarded"
+                        } else {
+                            "Escort (Military)"
+                        },
+                        provisions.escort,
+                        CaravanMenuButtons::ToggleEscort,
+                    ));
+                    parent.spawn(toggle_button(
+                        "Auto-supply buildings",
+                        selected_caravan.mode == CaravanMode::AutoSupply,
+                        CaravanMenuButtons::ToggleAutoSupply,
+                    ));
+                });
+            for incident in selected_caravan.incidents.iter().rev() {
+                parent.spawn((
+                    Node {
+                        width: percent(100),
+                        ..default()
+                    },
+                    Text::new(incident.describe()),
+                    TextFont {
+                        font_size: 18.0,
+                        ..default()
+                    },
+                    TextColor(Color::Srgba(bevy::color::palettes::css::ORANGE)),
+                ));
+            }
+            if let Some(problem) = selected_caravan.status.describe() {
+                parent.spawn((
+                    Node {
+                        width: percent(100),
+                        height: px(32),
+                        ..default()
+                    },
+                    Text::new(problem),
+                    TextColor(Color::Srgba(bevy::color::palettes::css::DARK_RED)),
+                ));
+            }
             parent
                 .spawn((Node {
                     width: percent(100),
@@ -829,11 +1295,150 @@ fn update_caravan_menu(
                             TextLayout::new_with_justify(Justify::Center),
                         )],
                     ));
+                    create_route_plan(parent, &plan);
                 });
         });
     }
 }
 
+fn toggle_button(label: &str, enabled: bool, action: CaravanMenuButtons) -> impl Bundle {
+    (
+        Button,
+        action,
+        Node {
+            width: px(320),
+            height: px(48),
+            margin: UiRect::all(px(4)),
+            align_items: AlignItems::Center,
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        BackgroundColor(if enabled {
+            Srgba::new(0.1, 0.6, 0.1, 1.0).into()
+        } else {
+            Srgba::new(0.3, 0.3, 0.3, 1.0).into()
+        }),
+        children![(
+            Text::new(label),
+            TextFont {
+                font_size: 20.0,
+                ..default()
+            },
+        )],
+    )
+}
+
+fn create_route_plan(parent: &mut ChildSpawnerCommands, plan: &RoutePlan) {
+    let line = |text: String, color: Color| {
+        (
+            Node {
+                width: percent(100),
+                ..default()
+            },
+            Text::new(text),
+            TextFont {
+                font_size: 18.0,
+                ..default()
+            },
+            TextColor(color),
+        )
+    };
+    let warning_color = Color::Srgba(bevy::color::palettes::css::DARK_RED);
+
+    parent
+        .spawn((
+            Node {
+                left: percent(5),
+                width: percent(90),
+                margin: UiRect::all(px(4)),
+                padding: UiRect::all(px(8)),
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            BackgroundColor(Srgba::new(0.1, 0.1, 0.1, 1.0).into()),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new("Route plan"),
+                TextFont {
+                    font_size: 24.0,
+                    ..default()
+                },
+            ));
+            for stop in &plan.stops {
+                parent.spawn(line(
+                    format!(
+                        "{}: {} turns, distance {:.1}, {} Transportation, {:.0}% ambush risk",
+                        stop.city_id,
+                        stop.hops,
+                        stop.distance,
+                        stop.transport,
+                        stop.ambush_risk * 100.0
+                    ),
+                    Color::WHITE,
+                ));
+                if stop.tariffs > 0.0 {
+                    parent.spawn(line(
+                        format!("  Tariffs: {:.0}", stop.tariffs),
+                        Color::WHITE,
+                    ));
+                }
+                for trade in &stop.trades {
+                    let action = match (trade.amount > 0, trade.price.is_some()) {
+                        (true, true) => "Buy",
+                        (true, false) => "Take",
+                        (false, true) => "Sell",
+                        (false, false) => "Store",
+                    };
+                    let price = trade
+                        .price
+                        .map(|price| format!(" for {price:.0}"))
+                        .unwrap_or_default();
+                    parent.spawn(line(
+                        format!(
+                            "  {action} {} {}{price}",
+                            trade.amount.abs(),
+                            trade.resource.get_name()
+                        ),
+                        Color::WHITE,
+                    ));
+                }
+                let cargo = stop
+                    .cargo
+                    .iter()
+                    .map(|(res, amount)| format!("{amount} {}", res.get_name()))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                parent.spawn(line(
+                    format!(
+                        "  Cargo: {}",
+                        if cargo.is_empty() {
+                            "empty"
+                        } else {
+                            cargo.as_str()
+                        }
+                    ),
+                    Color::srgb(0.7, 0.7, 0.7),
+                ));
+                for warning in &stop.warnings {
+                    parent.spawn(line(format!("  {warning}"), warning_color));
+                }
+            }
+            parent.spawn(line(
+                format!(
+                    "Profit per cycle: {:.0} ({} turns)",
+                    plan.profit(),
+                    plan.turns()
+                ),
+                if plan.profit() >= 0.0 {
+                    Color::WHITE
+                } else {
+                    warning_color
+                },
+            ));
+        });
+}
+
 #[derive(Reflect, Component, Default, Clone, Debug)]
 struct CaravanCityUINode(String);
 
@@ -1325,6 +1930,18 @@ fn caravan_button(
                         });
                     }
                 }
+                CaravanMenuButtons::ToggleRations => {
+                    selected_caravan.provisions.rations = !selected_caravan.provisions.rations;
+                }
+                CaravanMenuButtons::ToggleEscort => {
+                    selected_caravan.provisions.escort = !selected_caravan.provisions.escort;
+                }
+                CaravanMenuButtons::ToggleAutoSupply => {
+                    selected_caravan.mode = match selected_caravan.mode {
+                        CaravanMode::Manual => CaravanMode::AutoSupply,
+                        CaravanMode::AutoSupply => CaravanMode::Manual,
+                    };
+                }
                 CaravanMenuButtons::ChangeTradeConfirm(city_id, from_res, to_res) => {
                     for (entity, _) in hud_node.iter() {
                         commands.entity(entity).despawn_children();
@@ -1411,17 +2028,28 @@ fn wares_menu(
     mut sylt: Sylt,
     town: Res<SelectedCity>,
     building_table: Res<BuildinTable>,
+    intel: Res<MarketIntel>,
     player: Single<&Player, With<ActivePlayer>>,
 ) {
+    let view = intel.view(player.player_id, &town.id);
+    let known = view.known(&town);
     let window = popup_window(&mut commands, FlexDirection::Row);
     //Basic and exotic mats
     commands.entity(window).with_children(|parent| {
+        parent.spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: px(4),
+                left: px(8),
+                ..default()
+            },
+            Text::new(view.describe()),
+        ));
         //Basic and exotic mats
         let available_resources: HashSet<Resources> = HashSet::from_iter(
-            town.available_commodities(&building_table)
-                .iter()
-                .map(|x| *x)
-                .collect::<Vec<Resources>>(),
+            known
+                .map(|city| city.available_commodities(&building_table))
+                .unwrap_or_default(),
         );
         info!(
             "{0:?}\n{1:?}",
@@ -1548,45 +2176,132 @@ fn wares_menu(
                             color_coded_basics,
                             "Basic materials".to_string(),
                             &city_data,
+                            known,
                             player.player_id,
                             &mut sylt,
                         );
                     });
-            });
 
-        //Illegals and Advanced
-        parent
-            .spawn((Node {
-                top: px(32),
-                width: percent(36),
-                height: percent(100),
-                margin: UiRect::all(px(4)),
-                justify_content: JustifyContent::FlexStart,
-                flex_direction: FlexDirection::Column,
-                ..default()
-            },))
-            .with_children(|parent| {
+                let warehouse = city_data.warehouse(player.player_id);
+                let tenure = warehouse.map(|w| w.tenure).unwrap_or_default();
                 parent
                     .spawn((
                         Node {
                             width: percent(100),
-                            height: percent(30),
+                            height: percent(15),
                             margin: UiRect::all(px(4)),
-                            justify_content: JustifyContent::FlexStart,
                             flex_direction: FlexDirection::Column,
                             ..default()
                         },
                         BackgroundColor(Srgba::new(0.05, 0.05, 0.05, 1.0).into()),
                     ))
                     .with_children(|parent| {
-                        create_resource_list(
-                            parent,
-                            color_coded_illegal,
-                            "Illegal materials".to_string(),
-                            &city_data,
-                            player.player_id,
-                            &mut sylt,
-                        );
+                        parent.spawn(Text::new(
+                            warehouse
+                                .map(|w| w.describe(city_data.population))
+                                .unwrap_or_else(|| "No warehouse".to_string()),
+                        ));
+                        let mut actions = parent.spawn(Node {
+                            flex_direction: FlexDirection::Row,
+                            ..default()
+                        });
+                        match tenure {
+                            Tenure::None => {
+                                actions.with_child(warehouse_action(
+                                    format!(
+                                        "Rent {RENTED_CAPACITY} for {:.0}$ per turn",
+                                        Warehouse::rented().upkeep(city_data.population)
+                                    ),
+                                    WarehouseButton::Rent,
+                                ));
+                                actions.with_child(warehouse_action(
+                                    format!(
+                                        "Build {BUILT_CAPACITY} for {:.0}$",
+                                        Warehouse::build_cost(BUILT_CAPACITY)
+                                    ),
+                                    WarehouseButton::Build,
+                                ));
+                            }
+                            Tenure::Rented => {
+                                actions.with_child(warehouse_action(
+                                    format!(
+                                        "Build own for {:.0}$",
+                                        Warehouse::build_cost(BUILT_CAPACITY)
+                                    ),
+                                    WarehouseButton::Build,
+                                ));
+                                actions.with_child(warehouse_action(
+                                    "Stop renting, stock is lost".to_string(),
+                                    WarehouseButton::Release,
+                                ));
+                            }
+                            Tenure::Owned => {
+                                actions.with_child(warehouse_action(
+                                    format!(
+                                        "Expand by {EXPANSION_CAPACITY} for {:.0}$",
+                                        Warehouse::build_cost(EXPANSION_CAPACITY)
+                                    ),
+                                    WarehouseButton::Expand,
+                                ));
+                            }
+                        }
+                    });
+            });
+
+        //Illegals and Advanced
+        parent
+            .spawn((Node {
+                top: px(32),
+                width: percent(36),
+                height: percent(100),
+                margin: UiRect::all(px(4)),
+                justify_content: JustifyContent::FlexStart,
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },))
+            .with_children(|parent| {
+                parent
+                    .spawn((
+                        Node {
+                            width: percent(100),
+                            height: percent(30),
+                            margin: UiRect::all(px(4)),
+                            justify_content: JustifyContent::FlexStart,
+                            flex_direction: FlexDirection::Column,
+                            ..default()
+                        },
+                        BackgroundColor(Srgba::new(0.05, 0.05, 0.05, 1.0).into()),
+                    ))
+                    .with_children(|parent| {
+                        create_resource_list(
+                            parent,
+                            color_coded_illegal,
+                            match (&view, known) {
+                                (MarketView::Live, Some(city)) => format!(
+                                    "Black market, {:.0}% raid chance",
+                                    raid_chance(city) * 100.0
+                                ),
+                                (_, Some(city)) => format!(
+                                    "Black market, {:.0}% raid chance when last seen",
+                                    raid_chance(city) * 100.0
+                                ),
+                                (_, None) => "Black market, raid chance unknown".to_string(),
+                            },
+                            &city_data,
+                            known,
+                            player.player_id,
+                            &mut sylt,
+                        );
+                        for raid in known.iter().flat_map(|city| city.raids.iter().rev()) {
+                            parent.spawn((
+                                Text::new(raid.describe()),
+                                TextFont {
+                                    font_size: 12.0,
+                                    ..default()
+                                },
+                                TextColor(Color::Srgba(bevy::color::palettes::css::ORANGE)),
+                            ));
+                        }
                     });
 
                 parent
@@ -1607,6 +2322,7 @@ fn wares_menu(
                             color_coded_advanced,
                             "Advanced materials".to_string(),
                             &city_data,
+                            known,
                             player.player_id,
                             &mut sylt,
                         );
@@ -1643,6 +2359,7 @@ fn wares_menu(
                             color_coded_service,
                             "Services".to_string(),
                             &city_data,
+                            known,
                             player.player_id,
                             &mut sylt,
                         );
@@ -1666,6 +2383,7 @@ fn wares_menu(
                             color_coded_exotics,
                             "Exotic materials".to_string(),
                             &city_data,
+                            known,
                             player.player_id,
                             &mut sylt,
                         );
@@ -1674,6 +2392,449 @@ fn wares_menu(
     });
 }
 
+#[derive(Reflect, Component, Clone, Copy, Debug)]
+enum WarehouseButton {
+    Rent,
+    Build,
+    Expand,
+    Release,
+}
+
+fn warehouse_action(label: String, action: WarehouseButton) -> impl Bundle {
+    (
+        Button,
+        action,
+        Node {
+            margin: UiRect::all(px(4)),
+            padding: UiRect::horizontal(px(8)),
+            ..default()
+        },
+        BackgroundColor(Srgba::new(0.1, 0.1, 0.6, 1.0).into()),
+        children![(
+            Text::new(label),
+            TextFont {
+                font_size: 14.0,
+                ..default()
+            },
+        )],
+    )
+}
+
+fn warehouse_button(
+    interaction_query: Query<
+        (&Interaction, &WarehouseButton),
+        (Changed<Interaction>, With<Button>),
+    >,
+    mut selected_city: ResMut<SelectedCity>,
+    mut you: Single<&mut Player, With<ActivePlayer>>,
+) {
+    for (interaction, action) in &interaction_query {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let player_id = you.player_id;
+        let warehouse = selected_city.warehouse_mut(player_id);
+        match (action, warehouse.tenure) {
+            (WarehouseButton::Rent, Tenure::None) => {
+                warehouse.capacity = RENTED_CAPACITY;
+                warehouse.tenure = Tenure::Rented;
+            }
+            (WarehouseButton::Build, Tenure::None | Tenure::Rented) => {
+                you.money -= Warehouse::build_cost(BUILT_CAPACITY);
+                warehouse.capacity = BUILT_CAPACITY;
+                warehouse.tenure = Tenure::Owned;
+            }
+            (WarehouseButton::Expand, Tenure::Owned) => {
+                you.money -= Warehouse::build_cost(EXPANSION_CAPACITY);
+                warehouse.capacity += EXPANSION_CAPACITY;
+            }
+            (WarehouseButton::Release, Tenure::Rented) => {
+                selected_city.warehouses.remove(&player_id);
+            }
+            (action, tenure) => error!("Can't {action:?} a warehouse held as {tenure:?}"),
+        }
+    }
+}
+
+/// Offers to and from the other players, and goods in your warehouse here that
+/// can be sold to them.
+fn trade_menu(
+    mut commands: Commands,
+    town: Res<SelectedCity>,
+    ledger: Res<TradeLedger>,
+    you: Single<&Player, With<ActivePlayer>>,
+    others: Query<&Player, Without<ActivePlayer>>,
+) {
+    let player_id = you.player_id;
+    let mut buyers: Vec<PlayerId> = others.iter().map(|p| p.player_id).collect();
+    buyers.sort();
+    let goods: Vec<(Resources, isize)> = town
+        .warehouse(player_id)
+        .iter()
+        .flat_map(|w| w.iter())
+        .filter(|(_, amount)| *amount > 0)
+        .collect();
+
+    let window = popup_window(&mut commands, FlexDirection::Column);
+    commands.entity(window).with_children(|parent| {
+        parent.spawn(Text::new(format!(
+            "Trade in {}, offers lapse at the end of the turn",
+            town.id
+        )));
+
+        parent.spawn(Text::new("----Offers to you----"));
+        for offer in ledger.incoming(player_id) {
+            let mut row = parent.spawn(Node {
+                flex_direction: FlexDirection::Row,
+                align_items: AlignItems::Center,
+                ..default()
+            });
+            row.with_child(Text::new(offer.describe()));
+            row.with_child(action_button(
+                "Accept".to_string(),
+                TradeButton::Answer(offer.id, true),
+            ));
+            row.with_child(action_button(
+                "Decline".to_string(),
+                TradeButton::Answer(offer.id, false),
+            ));
+        }
+
+        parent.spawn(Text::new("----Your trades----"));
+        for offer in ledger.standing(player_id) {
+            let status = if offer.accepted {
+                "agreed"
+            } else {
+                "waiting for an answer"
+            };
+            parent.spawn(Text::new(format!("{}, {status}", offer.describe())));
+        }
+
+        parent.spawn(Text::new("----Sell from your warehouse here----"));
+        if buyers.is_empty() {
+            parent.spawn(Text::new("There is nobody to trade with"));
+        } else if goods.is_empty() {
+            parent.spawn(Text::new("Your warehouse here is empty"));
+        }
+        if buyers.is_empty() {
+            return;
+        }
+        let mut terms = parent.spawn(Node {
+            flex_direction: FlexDirection::Row,
+            align_items: AlignItems::Center,
+            ..default()
+        });
+        terms.with_children(|row| {
+            row.spawn(Text::new("Amount per delivery"));
+            row.spawn((
+                TradeAmountField,
+                TextInput,
+                TextInputValue(TRADE_LOT.to_string()),
+                trade_field(),
+            ));
+            row.spawn(Text::new("Price per unit, blank for market value"));
+            row.spawn((TradePriceField, TextInput, trade_field()));
+        });
+        for (res, amount) in goods {
+            let price = town.get_resource_value(&res);
+            let mut row = parent.spawn(Node {
+                flex_direction: FlexDirection::Row,
+                align_items: AlignItems::Center,
+                ..default()
+            });
+            row.with_child(Text::new(format!(
+                "{} ({amount} stored, {price:.1}$ each)",
+                res.get_name()
+            )));
+            for buyer in &buyers {
+                row.with_child(action_button(
+                    format!("Once to player {buyer}"),
+                    TradeButton::Propose {
+                        buyer: *buyer,
+                        resource: res,
+                        turns: 1,
+                    },
+                ));
+                row.with_child(action_button(
+                    format!("{CONTRACT_TURNS} turns to player {buyer}"),
+                    TradeButton::Propose {
+                        buyer: *buyer,
+                        resource: res,
+                        turns: CONTRACT_TURNS,
+                    },
+                ));
+            }
+        }
+    });
+}
+
+#[derive(Reflect, Component, Clone, Copy, Debug)]
+enum TradeButton {
+    Propose {
+        buyer: PlayerId,
+        resource: Resources,
+        turns: u32,
+    },
+    Answer(TradeId, bool),
+}
+
+/// How much goes in each delivery of a proposed trade.
+#[derive(Component)]
+struct TradeAmountField;
+
+/// What the buyer pays per unit, market value when left blank.
+#[derive(Component)]
+struct TradePriceField;
+
+fn trade_field() -> impl Bundle {
+    (
+        Node {
+            width: px(80),
+            margin: UiRect::all(px(4)),
+            padding: UiRect::all(px(4)),
+            border: UiRect::all(px(2)),
+            ..default()
+        },
+        BorderColor::all(Color::BLACK),
+    )
+}
+
+fn action_button(label: String, action: impl Component) -> impl Bundle {
+    (
+        Button,
+        action,
+        Node {
+            margin: UiRect::all(px(4)),
+            padding: UiRect::horizontal(px(8)),
+            ..default()
+        },
+        BackgroundColor(Srgba::new(0.1, 0.5, 0.3, 1.0).into()),
+        children![(
+            Text::new(label),
+            TextFont {
+                font_size: 14.0,
+                ..default()
+            },
+        )],
+    )
+}
+
+fn trade_button(
+    mut commands: Commands,
+    interaction_query: Query<(&Interaction, &TradeButton), (Changed<Interaction>, With<Button>)>,
+    town: Res<SelectedCity>,
+    mut ledger: ResMut<TradeLedger>,
+    you: Single<&Player, With<ActivePlayer>>,
+    amount_field: Option<Single<&TextInputValue, With<TradeAmountField>>>,
+    price_field: Option<Single<&TextInputValue, With<TradePriceField>>>,
+) {
+    for (interaction, action) in &interaction_query {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match *action {
+            TradeButton::Propose {
+                buyer,
+                resource,
+                turns,
+            } => {
+                let amount = match amount_field.as_ref() {
+                    Some(field) => match field.0.trim().parse::<isize>() {
+                        Ok(amount) if amount > 0 => amount,
+                        _ => {
+                            warn!("{} is not an amount to trade", field.0);
+                            continue;
+                        }
+                    },
+                    None => TRADE_LOT,
+                };
+                let stored = town.warehouse(you.player_id).map_or(0, |w| w[resource]);
+                if turns == 1 && amount > stored {
+                    warn!("Only {stored} {} stored here", resource.get_name());
+                    continue;
+                }
+                let price = match price_field.as_ref().map(|field| field.0.trim()) {
+                    Some(text) if !text.is_empty() => match text.parse::<f64>() {
+                        Ok(each) if each >= 0.0 => each * amount as f64,
+                        _ => {
+                            warn!("{text} is not a price");
+                            continue;
+                        }
+                    },
+                    _ => TradeOffer::market_price(&town, resource, amount),
+                };
+                let id = ledger.next_id(you.player_id);
+                let offer = TradeOffer::new(id, you.player_id, buyer, &town, resource, turns)
+                    .with_terms(amount, price);
+                commands.trigger(TradeProposed(offer));
+            }
+            TradeButton::Answer(id, accepted) => {
+                commands.trigger(TradeAnswered { id, accepted });
+            }
+        }
+    }
+}
+
+/// Roads leaving the selected city and what upgrading them costs.
+fn roads_menu(
+    mut commands: Commands,
+    town: Res<SelectedCity>,
+    graph: Res<CityGraph>,
+    cities: Query<(&CityNode, &CityData)>,
+    you: Single<&Player, With<ActivePlayer>>,
+) {
+    let Some((node, _)) = cities.iter().find(|(_, city)| city.id == town.id) else {
+        error!("{} is not on the map", town.id);
+        return;
+    };
+    let here = node.0;
+
+    let window = popup_window(&mut commands, FlexDirection::Column);
+    commands.entity(window).with_children(|parent| {
+        parent.spawn(Text::new(format!(
+            "Roads from {}, you have {:.0}$",
+            town.id, you.money
+        )));
+
+        for edge in graph.graph.edges(here) {
+            let other = if edge.source() == here {
+                edge.target()
+            } else {
+                edge.source()
+            };
+            let Ok((_, neighbour)) = cities.get(graph.graph[other]) else {
+                continue;
+            };
+            let route = edge.weight();
+            let mut row = parent.spawn(Node {
+                flex_direction: FlexDirection::Row,
+                align_items: AlignItems::Center,
+                ..default()
+            });
+            row.with_child(Text::new(format!(
+                "{:?} to {}: {:.0} long through {:?}, costs {:.0} to travel",
+                route.road,
+                neighbour.id,
+                route.distance,
+                route.terrain,
+                route.cost()
+            )));
+            if let Some((next, price)) = route.upgrade_cost() {
+                row.with_child(action_button(
+                    format!("Build a {next:?} for {price:.0}$"),
+                    RoadButton {
+                        to_city: neighbour.id.clone(),
+                        road: next,
+                        price,
+                    },
+                ));
+            }
+        }
+    });
+}
+
+#[derive(Reflect, Component, Clone, Debug)]
+struct RoadButton {
+    to_city: String,
+    road: RoadType,
+    price: f64,
+}
+
+fn road_button(
+    mut commands: Commands,
+    interaction_query: Query<(&Interaction, &RoadButton), (Changed<Interaction>, With<Button>)>,
+    town: Res<SelectedCity>,
+    mut you: Single<&mut Player, With<ActivePlayer>>,
+) {
+    for (interaction, action) in &interaction_query {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        if you.money < action.price {
+            warn!("Can't afford a {:?} to {}", action.road, action.to_city);
+            continue;
+        }
+        you.money -= action.price;
+        commands.trigger(RoadUpgraded {
+            from: town.id.clone(),
+            to: action.to_city.clone(),
+            road: action.road,
+        });
+    }
+}
+
+fn diplomacy_menu(
+    mut commands: Commands,
+    town: Res<SelectedCity>,
+    reputation: Res<Reputation>,
+    relations: Res<RaceRelations>,
+    you: Single<&Player, With<ActivePlayer>>,
+) {
+    let player_id = you.player_id;
+    let standing = reputation.in_city(player_id, &town);
+    let window = popup_window(&mut commands, FlexDirection::Column);
+    commands.entity(window).with_children(|parent| {
+        parent.spawn(Text::new(format!(
+            "{} and the {:?} think of you as {} ({standing})",
+            town.id,
+            town.race,
+            describe(standing)
+        )));
+        parent.spawn(Text::new(format!(
+            "{}: {}, {:?} race: {}",
+            town.id,
+            reputation.of_city(player_id, &town.id),
+            town.race,
+            reputation.of_race(player_id, town.race)
+        )));
+        parent.spawn(Text::new(format!(
+            "Market goods {:+.1}% to buy, {:+.1}% to sell, buildings {:+.1}%",
+            (buy_factor(standing) - 1.0) * 100.0,
+            (sell_factor(standing) - 1.0) * 100.0,
+            (purchase_factor(standing) - 1.0) * 100.0
+        )));
+        for tier in 1..=TIERS {
+            if let Some(needed) = required_for_tier(tier) {
+                let access = if may_construct(standing, tier) {
+                    "allowed"
+                } else {
+                    "closed"
+                };
+                parent.spawn(Text::new(format!(
+                    "Tier {tier} construction needs {needed}: {access}"
+                )));
+            }
+        }
+
+        parent.spawn(Text::new("Your reputation with the races"));
+        for race in PLAYABLE_RACES {
+            let value = reputation.of_race(player_id, race);
+            parent.spawn(Text::new(format!(
+                "{race:?}: {value} ({})",
+                describe(value)
+            )));
+        }
+
+        parent.spawn(Text::new("Your reputation in the cities"));
+        for (city_id, value) in reputation.cities(player_id) {
+            parent.spawn(Text::new(format!(
+                "{city_id}: {value} ({})",
+                describe(value)
+            )));
+        }
+
+        parent.spawn(Text::new("Relations between the races"));
+        for relation in &relations.0 {
+            let [a, b] = relation.races;
+            parent.spawn(Text::new(format!(
+                "{a:?} and {b:?}: {}, {}",
+                relation.standing,
+                relations.stance(a, b).describe()
+            )));
+        }
+    });
+}
+
 #[derive(Reflect, Component, PartialEq)]
 enum HudButton {
     KillHud,
@@ -1681,6 +2842,10 @@ enum HudButton {
     BuldingTabAction,
     OperationAction,
     FinanceAction,
+    WondersAction,
+    TradeAction,
+    RoadsAction,
+    DiplomacyAction,
 }
 
 #[derive(Reflect, Component)]
@@ -1694,6 +2859,7 @@ fn create_resource_list(
     resources: Vec<(Resources, TextColor)>,
     box_name: String,
     town: &CityData,
+    prices: Option<&CityData>,
     player_id: u64,
     mut sylt: &mut Sylt,
 ) {
@@ -1707,8 +2873,8 @@ fn create_resource_list(
         Text::new(box_name.clone()),
     ));
     for resource in resources {
-        let warehouse_store = if let Some(player_warehouse) = town.warehouses.get(&player_id) {
-            player_warehouse.get(&resource.0)
+        let warehouse_store = if let Some(player_warehouse) = town.warehouse(player_id) {
+            Some(&player_warehouse[resource.0])
         } else {
             info!("No warehouse for player {} in city {}", player_id, town.id);
             None
@@ -1718,7 +2884,7 @@ fn create_resource_list(
             parent,
             resource,
             warehouse_store,
-            town.get_resource_value(&resource.0),
+            prices.map(|city| city.get_resource_value(&resource.0)),
             &mut sylt,
         );
     }
@@ -1728,7 +2894,7 @@ fn create_resource_icon(
     parent: &mut ChildSpawnerCommands,
     resource: (Resources, TextColor),
     player_warehouse_amount: Option<&isize>,
-    cost: f64,
+    cost: Option<f64>,
     //    amount: usize,
     sylt: &mut Sylt,
 ) {
@@ -1834,7 +3000,10 @@ fn create_resource_icon(
                     ..default()
                 },
             ),
-            (Text::new(format!("{:.2}$", cost)),)
+            (Text::new(match cost {
+                Some(cost) => format!("{cost:.2}$"),
+                None => "?".to_string(),
+            }),)
         ],
     ));
 }
@@ -1883,7 +3052,7 @@ pub fn city_hud_setup(
             Button,
             button_functionality,
             Node {
-                width: vw(23),
+                width: vw(10.5),
                 height: percent(50),
                 margin: UiRect::all(vw(1)),
                 ..default()
@@ -1948,6 +3117,10 @@ pub fn city_hud_setup(
                     big_button_spawn("Check wares", HudButton::EconomyTabAction),
                     big_button_spawn("Send a new caravan", HudButton::OperationAction),
                     big_button_spawn("Finances", HudButton::FinanceAction),
+                    big_button_spawn("Wonders", HudButton::WondersAction),
+                    big_button_spawn("Trade with players", HudButton::TradeAction),
+                    big_button_spawn("Build roads", HudButton::RoadsAction),
+                    big_button_spawn("Diplomacy", HudButton::DiplomacyAction),
                 ]
             ),
         ],
@@ -1961,8 +3134,22 @@ fn update_button_networking(
     hud_node: Query<Entity, With<BuildingBrowser>>,
     mut selected_city: ResMut<SelectedCity>,
     mut you: Single<&mut Player, With<ActivePlayer>>,
+    other_players: Query<&Player, Without<ActivePlayer>>,
+    building_table: Res<BuildinTable>,
+    cities: Query<&CityData>,
+    reputation: Res<Reputation>,
 ) {
-    building_button(commands, interaction_query, hud_node, selected_city, you);
+    building_button(
+        commands,
+        interaction_query,
+        hud_node,
+        selected_city,
+        you,
+        other_players,
+        building_table,
+        cities,
+        reputation,
+    );
 }
 
 fn building_button(
@@ -1971,7 +3158,12 @@ fn building_button(
     hud_node: Query<Entity, With<BuildingBrowser>>,
     mut selected_city: ResMut<SelectedCity>,
     mut you: Single<&mut Player, With<ActivePlayer>>,
+    other_players: Query<&Player, Without<ActivePlayer>>,
+    building_table: Res<BuildinTable>,
+    cities: Query<&CityData>,
+    reputation: Res<Reputation>,
 ) {
+    let standing = reputation.in_city(you.player_id, &selected_city);
     for (interaction, menu_button_action) in &interaction_query {
         if *interaction == Interaction::Pressed {
             match menu_button_action {
@@ -1980,8 +3172,32 @@ fn building_button(
                     for hud_node in hud_node.iter() {
                         commands.entity(hud_node).despawn_children();
                         commands.entity(hud_node).with_children(|parent| {
-                            for building_choice in get_construction_list(selected_city.race, *tier)
-                            {
+                            if !may_construct(standing, *tier) {
+                                parent.spawn((
+                                    Text::new(format!(
+                                        "{} only lets those with {} reputation build tier {tier}, you have {standing}",
+                                        selected_city.id,
+                                        required_for_tier(*tier).unwrap_or_default(),
+                                    )),
+                                    BackgroundColor(Srgba::new(0.0, 0.0, 0.0, 0.7).into()),
+                                ));
+                                return;
+                            }
+                            let mut choices = get_construction_list(selected_city.race, *tier);
+                            if allows_smugglers(&selected_city) {
+                                choices.extend(get_construction_list(BuildingType::Illegal, *tier));
+                            }
+                            for building_choice in choices {
+                                let legality = if building_table.0[building_choice].build_type
+                                    == BuildingType::Illegal
+                                {
+                                    format!(
+                                        ", illegal with {:.0}% raid chance",
+                                        raid_chance(&selected_city) * 100.0
+                                    )
+                                } else {
+                                    String::new()
+                                };
                                 parent.spawn((
                                     Button,
                                     BuildingButton::BuildTypeButton(
@@ -1996,9 +3212,16 @@ fn building_button(
                                     BackgroundColor(Srgba::new(0.0, 0.0, 0.0, 0.7).into()),
                                     children![
                                         (Text::new(format!(
-                                            "{} costing: {}$",
+                                            "{} costing: {}$, {}, {} turns{}",
                                             building_choice,
-                                            500 * (tier * tier + tier)
+                                            building_table.0[building_choice].construction.cost,
+                                            describe_materials(
+                                                &building_table.0[building_choice]
+                                                    .construction
+                                                    .materials
+                                            ),
+                                            building_table.0[building_choice].construction.turns,
+                                            legality,
                                         )))
                                     ],
                                 ));
@@ -2011,34 +3234,56 @@ fn building_button(
                     for hud_node in hud_node.iter() {
                         commands.entity(hud_node).despawn_children();
                         commands.entity(hud_node).with_children(|parent| {
-                            let inpected_building = match tier {
-                                1 => &selected_city.buildings_t1[*slot],
-                                2 => &selected_city.buildings_t2[*slot],
-                                3 => &selected_city.buildings_t3[*slot],
-                                4 => &selected_city.buildings_t4[*slot],
-                                5 => &selected_city.buildings_t5[*slot],
-                                _ => {
-                                    error!("Wrong tier given!");
-                                    return;
-                                }
+                            let Some(inpected_building) =
+                                selected_city.buildings.slot(*tier, *slot)
+                            else {
+                                error!("Wrong tier given!");
+                                return;
                             };
+                            let price = building_table
+                                .0
+                                .get(&inpected_building.building_id)
+                                .map(|b| b.market_price(selected_city.population))
+                                .unwrap_or_default();
+                            let stores_output =
+                                inpected_building.output == OutputDestination::Warehouse;
+                            let buys_from_market = inpected_building.input == InputSource::Market;
 
-                            match inpected_building.1 {
+                            parent.spawn((
+                                Text::new(inpected_building.describe_efficiency()),
+                                BackgroundColor(Srgba::new(0.0, 0.0, 0.0, 0.7).into()),
+                            ));
+
+                            match inpected_building.owner {
                                 Faction::Neutral => {
                                     parent.spawn((
                                         Text::new(format!(
                                             "Neutral building: {}",
-                                            inpected_building.0
+                                            inpected_building.building_id
+                                        )),
+                                        BackgroundColor(Srgba::new(0.0, 0.0, 0.0, 0.7).into()),
+                                    ));
+                                    let asking = price * purchase_factor(standing);
+                                    parent.spawn((
+                                        Text::new(format!(
+                                            "{} asks {asking:.0}$ for it",
+                                            selected_city.id
                                         )),
                                         BackgroundColor(Srgba::new(0.0, 0.0, 0.0, 0.7).into()),
                                     ));
+                                    for share in BIDS {
+                                        parent.spawn(building_action(
+                                            format!("Offer {:.0}$", asking * share as f64 / 100.0),
+                                            BuildingButton::BidOnBuilding(*tier, *slot, share),
+                                        ));
+                                    }
                                 }
                                 Faction::Player(owner_id) => {
                                     if owner_id == you.player_id {
                                         parent.spawn((
                                             Text::new(format!(
                                                 "Your building: {}",
-                                                inpected_building.0
+                                                inpected_building.building_id
                                             )),
                                             BackgroundColor(Srgba::new(0.0, 0.0, 0.0, 0.7).into()),
                                         ));
@@ -2051,7 +3296,7 @@ fn building_button(
                                                 ..default()
                                             },
                                             children![
-                                                (match !inpected_building.2.1 {
+                                                (match !stores_output {
                                                     true => Text::new("Sells output to the market"),
                                                     false =>
                                                         Text::new("Stores output in warehouses"),
@@ -2070,7 +3315,11 @@ fn building_button(
                                                     BuildingButton::EditMarketSellStatus(
                                                         *tier,
                                                         *slot,
-                                                        !inpected_building.2.1,
+                                                        if stores_output {
+                                                            OutputDestination::Market
+                                                        } else {
+                                                            OutputDestination::Warehouse
+                                                        },
                                                     ),
                                                     children![(
                                                         Node {
@@ -2079,7 +3328,7 @@ fn building_button(
                                                             margin: UiRect::all(px(5)),
                                                             ..default()
                                                         },
-                                                        if inpected_building.2.1 {
+                                                        if stores_output {
                                                             BackgroundColor(
                                                                 Srgba::new(0.1, 0.8, 0.1, 0.0)
                                                                     .into(),
@@ -2104,7 +3353,7 @@ fn building_button(
                                                 ..default()
                                             },
                                             children![
-                                                (match !inpected_building.2.0 {
+                                                (match buys_from_market {
                                                     true => Text::new("Buys from the market"),
                                                     false =>
                                                         Text::new("Does not buy raw from market"),
@@ -2123,7 +3372,11 @@ fn building_button(
                                                     BuildingButton::EditMarketBuyStatus(
                                                         *tier,
                                                         *slot,
-                                                        !inpected_building.2.0,
+                                                        if buys_from_market {
+                                                            InputSource::Warehouse
+                                                        } else {
+                                                            InputSource::Market
+                                                        },
                                                     ),
                                                     children![(
                                                         Node {
@@ -2132,7 +3385,7 @@ fn building_button(
                                                             margin: UiRect::all(px(5)),
                                                             ..default()
                                                         },
-                                                        if inpected_building.2.0 {
+                                                        if !buys_from_market {
                                                             BackgroundColor(
                                                                 Srgba::new(0.1, 0.8, 0.1, 0.0)
                                                                     .into(),
@@ -2148,6 +3401,78 @@ fn building_button(
                                                 )
                                             ],
                                         ));
+
+                                        if selected_city.upgrade_queued(*tier, *slot) {
+                                            parent.spawn((
+                                                Text::new("Upgrade under construction"),
+                                                BackgroundColor(
+                                                    Srgba::new(0.0, 0.0, 0.0, 0.7).into(),
+                                                ),
+                                            ));
+                                        } else if inpected_building.level
+                                            >= selected_city.max_level()
+                                        {
+                                            parent.spawn((
+                                                Text::new(format!(
+                                                    "Level {}, the highest this city allows",
+                                                    inpected_building.level
+                                                )),
+                                                BackgroundColor(
+                                                    Srgba::new(0.0, 0.0, 0.0, 0.7).into(),
+                                                ),
+                                            ));
+                                        } else if let Some(definition) =
+                                            building_table.0.get(&inpected_building.building_id)
+                                        {
+                                            let upgrade = definition
+                                                .construction
+                                                .upgrade_to(inpected_building.level + 1);
+                                            parent.spawn(building_action(
+                                                format!(
+                                                    "Upgrade to level {} for {}$, {}, {} turns",
+                                                    inpected_building.level + 1,
+                                                    upgrade.cost,
+                                                    describe_materials(&upgrade.materials),
+                                                    upgrade.turns
+                                                ),
+                                                BuildingButton::UpgradeBuilding(*tier, *slot),
+                                            ));
+                                        }
+                                        parent.spawn(building_action(
+                                            format!(
+                                                "Sell to the city for {:.0}$",
+                                                price * SELL_BACK_FACTOR
+                                            ),
+                                            BuildingButton::SellBuilding(*tier, *slot),
+                                        ));
+                                        for other in other_players.iter() {
+                                            parent.spawn(building_action(
+                                                format!(
+                                                    "Offer to player {} for {price:.0}$",
+                                                    other.player_id
+                                                ),
+                                                BuildingButton::OfferBuilding(
+                                                    *tier,
+                                                    *slot,
+                                                    other.player_id,
+                                                ),
+                                            ));
+                                        }
+                                        if let Some(offer) = inpected_building.offer {
+                                            parent.spawn((
+                                                Text::new(format!(
+                                                    "Offered to player {} for {:.0}$",
+                                                    offer.buyer, offer.price
+                                                )),
+                                                BackgroundColor(
+                                                    Srgba::new(0.0, 0.0, 0.0, 0.7).into(),
+                                                ),
+                                            ));
+                                        }
+                                        parent.spawn(building_action(
+                                            "Demolish".to_string(),
+                                            BuildingButton::DemolishBuilding(*tier, *slot),
+                                        ));
                                     }
                                     //Someone else owns this building
                                     else {
@@ -2155,6 +3480,14 @@ fn building_button(
                                             Text::new(format!("Building owned by: {}", owner_id)),
                                             BackgroundColor(Srgba::new(0.0, 0.0, 0.0, 0.7).into()),
                                         ));
+                                        if let Some(offer) = inpected_building.offer
+                                            && offer.buyer == you.player_id
+                                        {
+                                            parent.spawn(building_action(
+                                                format!("Accept offer for {:.0}$", offer.price),
+                                                BuildingButton::AcceptOffer(*tier, *slot),
+                                            ));
+                                        }
                                     }
                                 }
                             }
@@ -2167,69 +3500,220 @@ fn building_button(
                     for hud_node in hud_node.iter() {
                         commands.entity(hud_node).despawn_children();
                     }
-                    you.money -= (500 * (tier * tier + tier)) as f64;
-                    match tier {
-                        1 => selected_city.buildings_t1.push((
-                            building.clone(),
-                            Faction::Player(you.player_id),
-                            (false, false),
-                        )),
-                        2 => selected_city.buildings_t2.push((
-                            building.clone(),
-                            Faction::Player(you.player_id),
-                            (false, false),
-                        )),
-                        3 => selected_city.buildings_t3.push((
-                            building.clone(),
-                            Faction::Player(you.player_id),
-                            (false, false),
-                        )),
-                        4 => selected_city.buildings_t4.push((
-                            building.clone(),
-                            Faction::Player(you.player_id),
-                            (false, false),
-                        )),
-                        5 => selected_city.buildings_t5.push((
-                            building.clone(),
-                            Faction::Player(you.player_id),
-                            (false, false),
-                        )),
-                        _ => {
-                            error!("Wrong tier given!");
-                        }
+                    let Some(definition) = building_table.0.get(building) else {
+                        error!("Tried to construct unknown building {building}");
+                        return;
+                    };
+                    if selected_city.free_slots(*tier) == 0 {
+                        error!("No free slot at tier {tier}");
+                        return;
+                    }
+                    if definition.build_type == BuildingType::Illegal
+                        && !allows_smugglers(&selected_city)
+                    {
+                        error!("{} won't tolerate {building}", selected_city.id);
+                        return;
+                    }
+                    if !may_construct(standing, *tier) {
+                        error!(
+                            "Not trusted enough to build at tier {tier} in {}",
+                            selected_city.id
+                        );
+                        return;
                     }
+                    you.money -= definition.construction.cost as f64;
+                    selected_city.construction.push(ConstructionProject::new(
+                        building.clone(),
+                        Faction::Player(you.player_id),
+                        definition,
+                    ));
                 }
-                BuildingButton::EditMarketSellStatus(tier, slot, set_sell) => {
-                    println!("Toggled sell status to: {}", set_sell);
-                    match tier {
-                        1 => &mut selected_city.buildings_t1[*slot],
-                        2 => &mut selected_city.buildings_t2[*slot],
-                        3 => &mut selected_city.buildings_t3[*slot],
-                        4 => &mut selected_city.buildings_t4[*slot],
-                        5 => &mut selected_city.buildings_t5[*slot],
+                BuildingButton::BidOnBuilding(tier, slot, _)
+                | BuildingButton::SellBuilding(tier, slot)
+                | BuildingButton::OfferBuilding(tier, slot, _)
+                | BuildingButton::AcceptOffer(tier, slot)
+                | BuildingButton::DemolishBuilding(tier, slot) => {
+                    for hud_node in hud_node.iter() {
+                        commands.entity(hud_node).despawn_children();
+                    }
+                    let population = selected_city.population;
+                    let city = &mut selected_city.0;
+                    let Some(building) = city.buildings.slot_mut(*tier, *slot) else {
+                        error!("Wrong tier given!");
+                        return;
+                    };
+                    let price = building_table
+                        .0
+                        .get(&building.building_id)
+                        .map(|b| b.market_price(population))
+                        .unwrap_or_default();
+                    let owner = building.owner;
+                    let owned = building.is_owned_by(you.player_id);
+
+                    match menu_button_action {
+                        BuildingButton::BidOnBuilding(_, _, share)
+                            if building.owner == Faction::Neutral =>
+                        {
+                            let asking = price * purchase_factor(standing);
+                            let bid = asking * *share as f64 / 100.0;
+                            if bid < reserve_price(asking, building) {
+                                info!(
+                                    "{} turned down {bid:.0}$ for {}",
+                                    city.id, building.building_id
+                                );
+                                for hud_node in hud_node.iter() {
+                                    commands.entity(hud_node).with_child((
+                                        Text::new(format!(
+                                            "{} turned down your offer of {bid:.0}$",
+                                            city.id
+                                        )),
+                                        BackgroundColor(Srgba::new(0.0, 0.0, 0.0, 0.7).into()),
+                                    ));
+                                }
+                                return;
+                            }
+                            you.money -= bid;
+                            building.transfer(Faction::Player(you.player_id));
+                        }
+                        BuildingButton::SellBuilding(..) if owned => {
+                            you.money += price * SELL_BACK_FACTOR;
+                            building.transfer(Faction::Neutral);
+                        }
+                        BuildingButton::OfferBuilding(_, _, buyer) if owned => {
+                            building.offer = Some(BuildingOffer {
+                                buyer: *buyer,
+                                price,
+                            });
+                        }
+                        BuildingButton::AcceptOffer(..) => {
+                            let (Some(offer), Faction::Player(seller)) =
+                                (building.offer, building.owner)
+                            else {
+                                return;
+                            };
+                            if offer.buyer != you.player_id {
+                                return;
+                            }
+                            you.money -= offer.price;
+                            building.transfer(Faction::Player(you.player_id));
+                            city.payouts.push((seller, offer.price));
+                        }
+                        BuildingButton::DemolishBuilding(..) if owned => {
+                            city.demolish(*tier, *slot);
+                        }
                         _ => {
-                            error!("Wrong tier given!");
+                            error!("Not allowed to {menu_button_action:?}");
                             return;
                         }
                     }
-                    .2
-                    .1 = *set_sell;
+                    // An upgrade is refunded to whoever ordered it once the building changes hands
+                    if city
+                        .buildings
+                        .slot(*tier, *slot)
+                        .is_none_or(|b| b.owner != owner)
+                    {
+                        city.cancel_upgrade(*tier, *slot);
+                    }
                 }
-                BuildingButton::EditMarketBuyStatus(tier, slot, set_buy) => {
-                    println!("Toggled buy status to: {}", set_buy);
-                    match tier {
-                        1 => &mut selected_city.buildings_t1[*slot],
-                        2 => &mut selected_city.buildings_t2[*slot],
-                        3 => &mut selected_city.buildings_t3[*slot],
-                        4 => &mut selected_city.buildings_t4[*slot],
-                        5 => &mut selected_city.buildings_t5[*slot],
-                        _ => {
-                            error!("Wrong tier given!");
-                            return;
-                        }
+                BuildingButton::NewWonder => {
+                    for hud_node in hud_node.iter() {
+                        commands.entity(hud_node).despawn_children();
+                        commands.entity(hud_node).with_children(|parent| {
+                            for wonder in &WONDERS {
+                                let Some(definition) = building_table.0.get(wonder.name) else {
+                                    continue;
+                                };
+                                if let Some(site) = wonder_site(wonder.name, cities.iter()) {
+                                    parent.spawn((
+                                        Text::new(format!(
+                                            "{} already stands in {}",
+                                            wonder.name, site.id
+                                        )),
+                                        BackgroundColor(Srgba::new(0.0, 0.0, 0.0, 0.7).into()),
+                                    ));
+                                    continue;
+                                }
+                                parent.spawn(building_action(
+                                    format!(
+                                        "{}: {}. Costing {}$, {}, {} turns",
+                                        wonder.name,
+                                        wonder.effect.describe(),
+                                        definition.construction.cost,
+                                        describe_materials(&definition.construction.materials),
+                                        definition.construction.turns
+                                    ),
+                                    BuildingButton::BuildWonder(wonder.name.to_string()),
+                                ));
+                            }
+                        });
                     }
-                    .2
-                    .0 = *set_buy;
+                }
+                BuildingButton::BuildWonder(name) => {
+                    for hud_node in hud_node.iter() {
+                        commands.entity(hud_node).despawn_children();
+                    }
+                    let Some(definition) = building_table.0.get(name) else {
+                        error!("Tried to construct unknown wonder {name}");
+                        return;
+                    };
+                    let owner = Faction::Player(you.player_id);
+                    let already_racing = cities.iter().any(|city| {
+                        city.construction
+                            .iter()
+                            .any(|p| &p.building_id == name && p.owner == owner)
+                    });
+                    if !selected_city.can_hold_wonder()
+                        || already_racing
+                        || wonder_site(name, cities.iter()).is_some()
+                    {
+                        error!("Can't build {name} in {}", selected_city.id);
+                        return;
+                    }
+                    you.money -= definition.construction.cost as f64;
+                    selected_city.construction.push(ConstructionProject::new(
+                        name.clone(),
+                        owner,
+                        definition,
+                    ));
+                }
+                BuildingButton::UpgradeBuilding(tier, slot) => {
+                    for hud_node in hud_node.iter() {
+                        commands.entity(hud_node).despawn_children();
+                    }
+                    let Some(building) = selected_city.buildings.slot(*tier, *slot) else {
+                        error!("Wrong tier given!");
+                        return;
+                    };
+                    let Some(definition) = building_table.0.get(&building.building_id) else {
+                        error!("Tried to upgrade unknown building {}", building.building_id);
+                        return;
+                    };
+                    if !building.is_owned_by(you.player_id)
+                        || building.level >= selected_city.max_level()
+                        || selected_city.upgrade_queued(*tier, *slot)
+                    {
+                        error!("Can't upgrade {}", building.building_id);
+                        return;
+                    }
+                    let project = ConstructionProject::upgrade(*slot, building, definition);
+                    you.money -= definition.construction.upgrade_to(building.level + 1).cost as f64;
+                    selected_city.construction.push(project);
+                }
+                BuildingButton::EditMarketSellStatus(tier, slot, output) => {
+                    println!("Toggled output to: {:?}", output);
+                    let Some(building) = selected_city.buildings.slot_mut(*tier, *slot) else {
+                        error!("Wrong tier given!");
+                        return;
+                    };
+                    building.output = *output;
+                }
+                BuildingButton::EditMarketBuyStatus(tier, slot, input) => {
+                    println!("Toggled input to: {:?}", input);
+                    let Some(building) = selected_city.buildings.slot_mut(*tier, *slot) else {
+                        error!("Wrong tier given!");
+                        return;
+                    };
+                    building.input = *input;
                 }
             }
         }
@@ -1,7 +1,19 @@
 use super::city_data::*;
+use super::city_labels::CityLabel;
+use super::map_definition::MapDefinition;
+use super::relations::{record_import, RaceRelations, TRADE_GOODWILL, WAR_SUPPLY_GOODWILL};
+use super::reputation::{
+    buy_factor, sell_factor, Reputation, GOUGING_PENALTY, SMUGGLING_PENALTY, SUPPLY_GOODWILL,
+};
+use super::risk::{roll_ambush, roll_inspection, Incident, INCIDENT_LOG_LENGTH};
+use super::route_planner::plan_auto_supply;
 use super::strategic_hud::{LockedCities, PopupHUD};
-use super::turn::TurnEndSinglePlayer;
-use crate::game::city_graph::{get_path, CityGraph, Node as CityNode};
+use super::terrain::TerrainMap;
+use super::trade::TradeLedger;
+use super::turn::{Turn, TurnEndSinglePlayer};
+use super::wonders::WonderBonuses;
+use super::world_events::{WorldEventKind, WorldEvents};
+use crate::game::city_graph::{CityGraph, Node as CityNode, PathError};
 use crate::game::turn::TurnEnd;
 use crate::network::message::NetworkMessage;
 use crate::network::message::{ClientMessage, PlayerId, ServerMessage};
@@ -44,13 +56,89 @@ pub struct Owns(Vec<Entity>);
 #[derive(Reflect, Resource, Deref, DerefMut)]
 pub struct SelectedCaravan(pub Entity);
 
-#[derive(Reflect, Component, Clone, Default, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[derive(Reflect, Component, Clone, Default, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Caravan {
     pub orders: Vec<Order>,
     pub order_idx: usize,
     pub time_travelled: usize,
     pub position_city_id: String,
     pub cargo: HashMap<Resources, usize>,
+    #[serde(default)]
+    pub status: CaravanStatus,
+    #[serde(default)]
+    pub provisions: Provisions,
+    /// Turns the current leg takes, 0 while the caravan waits in a city.
+    #[serde(default)]
+    pub leg_duration: usize,
+    /// City the current leg leads to, fixed when the caravan sets out.
+    #[serde(default)]
+    pub leg_to: Option<String>,
+    /// Cost of the road the current leg follows, as it was when the caravan set out.
+    #[serde(default)]
+    pub leg_cost: f32,
+    /// Whether an escort was supplied for the current leg.
+    #[serde(default)]
+    pub escorted: bool,
+    #[serde(default)]
+    pub incidents: Vec<Incident>,
+    #[serde(default)]
+    pub mode: CaravanMode,
+    /// Race of the city each good in the cargo was loaded in.
+    #[serde(default)]
+    pub origins: HashMap<Resources, BuildingType>,
+}
+
+#[derive(Clone, Copy, Reflect, Default, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum CaravanMode {
+    /// Follows the orders written by the player.
+    #[default]
+    Manual,
+    /// Rewrites its orders every turn to haul warehouse stock between the player's
+    /// buildings in the cities of its stops.
+    AutoSupply,
+}
+
+/// Optional supplies a caravan takes on every leg besides Transportation.
+#[derive(Clone, Reflect, Default, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Provisions {
+    /// Food for the crew, halves the Transportation needed per leg.
+    pub rations: bool,
+    /// Military guarding the cargo on the road.
+    pub escort: bool,
+}
+
+/// Road length one unit of Transportation carries a caravan.
+pub const TRANSPORT_DISTANCE: f32 = 100.0;
+
+/// Whether the caravan could follow its orders during the last turn.
+#[derive(Clone, Reflect, Default, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum CaravanStatus {
+    #[default]
+    Ok,
+    NoRoute {
+        from: String,
+        to: String,
+    },
+    UnknownCity(String),
+    NoTransport(String),
+    Embargo {
+        from: BuildingType,
+        to: BuildingType,
+    },
+}
+
+impl CaravanStatus {
+    pub fn describe(&self) -> Option<String> {
+        match self {
+            CaravanStatus::Ok => None,
+            CaravanStatus::NoRoute { from, to } => Some(format!("No route from {from} to {to}")),
+            CaravanStatus::UnknownCity(id) => Some(format!("Unknown destination {id}")),
+            CaravanStatus::NoTransport(id) => Some(format!("No Transportation available in {id}")),
+            CaravanStatus::Embargo { from, to } => Some(format!(
+                "The {to:?} have closed their border to the {from:?}"
+            )),
+        }
+    }
 }
 
 #[derive(Clone, Reflect, Default, Eq, PartialEq, Debug, Serialize, Deserialize)]
@@ -60,6 +148,50 @@ pub struct Order {
 }
 
 impl Caravan {
+    /// Transportation needed to travel a road with the given cost.
+    pub fn transport_needed(cost: f32, fed: bool) -> usize {
+        let needed = (cost / TRANSPORT_DISTANCE).ceil().max(1.0) as usize;
+        if fed {
+            needed.div_ceil(2)
+        } else {
+            needed
+        }
+    }
+
+    fn has_supplies(&self, resource: Resources, city: &CityData) -> bool {
+        self.cargo.get(&resource).is_some_and(|amount| *amount > 0) || city.market[&resource] > 0
+    }
+
+    /// Takes up to `needed` of a resource from the cargo, buying the rest from the
+    /// city's market. Returns how much was found.
+    fn draw_supplies(
+        &mut self,
+        resource: Resources,
+        needed: usize,
+        city: &mut CityData,
+        player: &mut Player,
+    ) -> usize {
+        let carried = self.cargo.get(&resource).copied().unwrap_or(0);
+        let from_cargo = carried.min(needed);
+        self.cargo.insert(resource, carried - from_cargo);
+
+        let in_stock = city.market[&resource].max(0) as usize;
+        let bought = (needed - from_cargo).min(in_stock);
+        if bought > 0 {
+            player.money -= city.get_bulk_buy_price(&resource, bought);
+            city.market.insert(resource, (in_stock - bought) as isize);
+        }
+        from_cargo + bought
+    }
+
+    pub fn log_incident(&mut self, incident: Incident) {
+        info!("{}", incident.describe());
+        self.incidents.push(incident);
+        if self.incidents.len() > INCIDENT_LOG_LENGTH {
+            self.incidents.remove(0);
+        }
+    }
+
     pub fn update_orders(
         _: On<TurnEndSinglePlayer>,
         players: Query<(&mut Player, &Owns)>,
@@ -67,61 +199,211 @@ impl Caravan {
         city: Res<CityGraph>,
         mut nodes: Query<(&CityNode, &mut CityData)>,
         building_table: Res<BuildinTable>,
+        mut rng: ResMut<GlobalRng>,
+        mut relations: ResMut<RaceRelations>,
+        mut reputation: ResMut<Reputation>,
+        events: Res<WorldEvents>,
+        turn: Res<Turn>,
+        ledger: Res<TradeLedger>,
     ) {
         for (mut player, owned_entities) in players {
             for ent in owned_entities.collection() {
                 let Ok(mut caravan) = caravans.get_mut(*ent) else {
                     continue;
                 };
-                if caravan.orders.len() == 0 {
-                    return;
-                }
-                let city_by_id = |id| {
-                    nodes
+                if caravan.mode == CaravanMode::AutoSupply {
+                    let cities: Vec<_> = nodes.iter().collect();
+                    let orders = plan_auto_supply(
+                        &caravan,
+                        player.player_id,
+                        &city,
+                        &cities,
+                        &building_table,
+                        &ledger,
+                    );
+                    let same_stops = orders
                         .iter()
-                        .find(|(_, city)| &city.id == id)
-                        .unwrap_or_else(|| panic!("Attempted to get nonexistent city {id}"))
+                        .map(|order| &order.goal_city_id)
+                        .eq(caravan.orders.iter().map(|order| &order.goal_city_id));
+                    if !same_stops {
+                        caravan.order_idx = 0;
+                    }
+                    caravan.orders = orders;
+                }
+                let bonuses = WonderBonuses::of(player.player_id, nodes.iter().map(|(_, c)| c));
+                let city_by_id = |id: &String| nodes.iter().find(|(_, city)| &city.id == id);
+                let Some((current_node, _)) = city_by_id(&caravan.position_city_id) else {
+                    error!(
+                        "Caravan is positioned in nonexistent city {}",
+                        caravan.position_city_id
+                    );
+                    continue;
+                };
+                let current_node = current_node.0;
+                let race = |e: Entity| nodes.get(e).ok().map(|(_, c)| c.race);
+                let leg_to = caravan.leg_to.as_ref().and_then(city_by_id);
+                // A leg under way is finished whatever the orders say by now, only then
+                // is the next one planned
+                let path = match leg_to {
+                    Some((leg_to, _)) if caravan.leg_duration > 0 => {
+                        vec![city.graph[current_node], city.graph[leg_to.0]]
+                    }
+                    _ => {
+                        // A leg to a city that is gone is called off
+                        caravan.leg_duration = 0;
+                        caravan.leg_to = None;
+                        let goal = caravan.orders.get(caravan.order_idx);
+                        let Some(goal_city_id) = goal.map(|order| order.goal_city_id.clone())
+                        else {
+                            continue;
+                        };
+                        let Some((next_node, _)) = city_by_id(&goal_city_id) else {
+                            caravan.status = CaravanStatus::UnknownCity(goal_city_id);
+                            continue;
+                        };
+                        // Go around borders closed by an embargo before setting out, and
+                        // wait if there is no way around
+                        match relations.open_route(&city, current_node, next_node.0, race) {
+                            Ok(route) => route.path,
+                            Err(PathError::Embargo(from, to)) => {
+                                caravan.status = CaravanStatus::Embargo { from, to };
+                                continue;
+                            }
+                            Err(e) => {
+                                warn!("Caravan can't reach {goal_city_id}: {e}");
+                                caravan.status = CaravanStatus::NoRoute {
+                                    from: caravan.position_city_id.clone(),
+                                    to: goal_city_id,
+                                };
+                                continue;
+                            }
+                        }
+                    }
                 };
-                let current_node = city_by_id(&caravan.position_city_id);
-                let next_node = city_by_id(&caravan.orders[caravan.order_idx].goal_city_id);
-                let (_, path) = get_path(&city, current_node.0 .0, next_node.0 .0);
+                caravan.status = CaravanStatus::Ok;
                 info!(
                     "Nodes next to current node: {0:?}",
                     city.graph
-                        .neighbors(current_node.0 .0)
-                        .map(|n| nodes.get(city.graph[n]).unwrap())
-                        .map(|(_, data)| data.id.clone())
-                        .collect::<Vec<String>>()
-                );
-                info!(
-                    "Nodes next to next node: {0:?}",
-                    city.graph
-                        .neighbors(next_node.0 .0)
-                        .map(|n| nodes.get(city.graph[n]).unwrap())
+                        .neighbors(current_node)
+                        .filter_map(|n| nodes.get(city.graph[n]).ok())
                         .map(|(_, data)| data.id.clone())
                         .collect::<Vec<String>>()
                 );
 
                 let paths_mapped: Vec<String> = path
                     .iter()
-                    .map(|n| nodes.get(*n).unwrap())
+                    .filter_map(|n| nodes.get(*n).ok())
                     .map(|(_, data)| data.id.clone())
                     .collect();
 
                 //info!("astar path: {:?}", paths_mapped);
 
-                let mut current_city = nodes
-                    .get_mut(path[0])
-                    .expect("failed to get a path in order updater????");
+                if path.len() > 1 && caravan.leg_duration == 0 {
+                    let Some(road) = nodes
+                        .get(path[1])
+                        .ok()
+                        .and_then(|(node, _)| city.edge_between(current_node, node.0))
+                    else {
+                        error!("Caravan path {paths_mapped:?} skips a road");
+                        continue;
+                    };
+                    let Ok((_, mut departure)) = nodes.get_mut(path[0]) else {
+                        continue;
+                    };
+                    let fed = caravan.provisions.rations
+                        && caravan.has_supplies(Resources::Food, &departure);
+                    let cost = city.edge_cost(road);
+                    let needed = bonuses.transport(Caravan::transport_needed(cost, fed));
+                    let found = caravan.draw_supplies(
+                        Resources::Transportation,
+                        needed,
+                        &mut departure,
+                        &mut player,
+                    );
+                    if found == 0 {
+                        caravan.status = CaravanStatus::NoTransport(departure.id.clone());
+                        continue;
+                    }
+                    if fed {
+                        caravan.draw_supplies(Resources::Food, 1, &mut departure, &mut player);
+                    }
+                    caravan.escorted = caravan.provisions.escort
+                        && caravan.draw_supplies(
+                            Resources::Military,
+                            1,
+                            &mut departure,
+                            &mut player,
+                        ) > 0;
+                    caravan.leg_duration = needed.div_ceil(found);
+                    caravan.time_travelled = 0;
+                    caravan.leg_to = nodes.get(path[1]).ok().map(|(_, to)| to.id.clone());
+                    caravan.leg_cost = cost;
+                }
+                if path.len() > 1 {
+                    caravan.time_travelled += 1;
+                    if caravan.time_travelled < caravan.leg_duration {
+                        continue;
+                    }
+                    caravan.leg_duration = 0;
+                    caravan.time_travelled = 0;
+                    caravan.leg_to = None;
+
+                    if let Ok([(_, from), (_, to)]) = nodes.get_many([path[0], path[1]]) {
+                        let cost = caravan.leg_cost;
+                        if let Some(incident) = roll_ambush(
+                            &mut caravan,
+                            cost,
+                            from,
+                            to,
+                            bonuses.ambush_factor,
+                            &mut rng,
+                        ) {
+                            caravan.log_incident(incident);
+                        }
+                    }
+                }
+
+                let next_stop = if path.len() > 1 { path[1] } else { path[0] };
+                let from_race = nodes.get(path[0]).ok().map(|(_, city)| city.race);
+                let Ok(mut current_city) = nodes.get_mut(next_stop) else {
+                    error!("Caravan path {paths_mapped:?} leads through a missing city");
+                    continue;
+                };
                 if path.len() > 1 {
-                    current_city = nodes
-                        .get_mut(path[1])
-                        .expect("Caravan travelling to a city that doesnt exist");
                     caravan.position_city_id = current_city.1.id.to_string();
+                    if let Some(incident) = roll_inspection(&mut caravan, &current_city.1, &mut rng)
+                    {
+                        if let Incident::Confiscation { .. } = incident {
+                            reputation.change(
+                                player.player_id,
+                                &current_city.1,
+                                -SMUGGLING_PENALTY,
+                            );
+                        }
+                        caravan.log_incident(incident);
+                    }
+                    if let Some(from_race) = from_race
+                        && from_race != current_city.1.race
+                    {
+                        let to_race = current_city.1.race;
+                        let cargo = caravan.cargo.iter().map(|(res, amount)| (*res, *amount));
+                        let tariff = relations.tariff(cargo, from_race, &current_city.1);
+                        if tariff > 0.0 {
+                            info!(
+                                "Caravan paid {tariff}$ in tariffs entering {}",
+                                current_city.1.id
+                            );
+                            player.money -= tariff;
+                        }
+                        if caravan.cargo.values().any(|amount| *amount > 0) {
+                            relations.shift(from_race, to_race, TRADE_GOODWILL);
+                        }
+                    }
                     //info!("Caravan travels to {0:?}", current_city.1.id.to_string());
                 }
                 //info!(  "Caravan wants to get to {0:?}",caravan.orders[caravan.order_idx].goal_city_id);
-                if caravan.orders[caravan.order_idx].goal_city_id == current_city.1.id {
+                let at_goal = caravan.orders.get(caravan.order_idx);
+                if at_goal.is_some_and(|order| order.goal_city_id == current_city.1.id) {
                     let available_commodies = current_city.1.available_commodities(&building_table);
                     let cargo_access = caravan.cargo.clone();
                     info!("Caravan currently has {:?} stored", cargo_access);
@@ -131,15 +413,18 @@ impl Caravan {
                         println!("open market is set to: {interacts_with_warehouse}");
                         //Buy from market
                         if amount > 0 && interacts_with_warehouse {
-                            if available_commodies.contains(&trade) {
-                                let amount_available = current_city.1.market[&trade];
+                            if available_commodies.contains(&trade) || trade.is_illegal() {
+                                let amount_available = current_city.1.stock(&trade);
                                 let mut amount_bought = amount.abs().min(amount_available);
                                 if amount_bought < 0 {
                                     amount_bought = amount
                                 }
+                                let standing =
+                                    reputation.in_city(player.player_id, &current_city.1);
                                 let price = current_city
                                     .1
-                                    .get_bulk_buy_price(&trade, amount_bought as usize);
+                                    .get_bulk_buy_price(&trade, amount_bought as usize)
+                                    * buy_factor(standing);
                                 info!(
                                     "Caravan paid {0} for {2} {1}",
                                     price,
@@ -151,86 +436,97 @@ impl Caravan {
                                     trade,
                                     cargo_access.get(&trade).unwrap_or(&0) + amount_bought as usize,
                                 );
+                                caravan.origins.insert(trade, current_city.1.race);
                                 info!(
                                     "Caravan now has {0} {1}",
                                     caravan.cargo.get(&trade).unwrap_or(&0),
                                     &trade.get_name()
                                 );
-                                current_city
-                                    .1
-                                    .market
-                                    .insert(trade, amount_available - amount_bought);
+                                *current_city.1.stock_mut(&trade) =
+                                    amount_available - amount_bought;
+                                // Buying a market bare drives its prices up
+                                if amount_bought > 0 && amount_available - amount_bought <= 0 {
+                                    reputation.change(
+                                        player.player_id,
+                                        &current_city.1,
+                                        -GOUGING_PENALTY,
+                                    );
+                                }
                             } else {
                                 error!("Could not buy commodety");
                                 continue;
                             }
                         // Take from warehouse
                         } else if amount > 0 {
-                            let city_id = current_city.1.id.clone();
-                            let Some(mut warehouse) =
-                                current_city.1.warehouses.get_mut(&player.player_id)
-                            else {
-                                error!(
-                                    "no warehouse of playerid {0} in {1}",
-                                    player.player_id, current_city.1.id
-                                );
-                                continue;
-                            };
-                            let amount_available = warehouse
-                                .get(&trade)
-                                .expect(&format!("malformed warehouse in {0}", city_id))
-                                .clone();
-
-                            let amount_taken = amount.min(amount_available);
+                            let amount_taken = current_city.1.take(player.player_id, trade, amount);
 
                             caravan.cargo.insert(
                                 trade,
                                 cargo_access.get(&trade).unwrap_or(&0) + amount_taken as usize,
                             );
-                            warehouse.insert(trade, amount_available - amount_taken);
+                            if amount_taken > 0 {
+                                caravan.origins.insert(trade, current_city.1.race);
+                            }
                         }
                         //Sell to market
                         if amount < 0 && interacts_with_warehouse {
-                            let amount_available = current_city.1.market[&trade];
+                            let amount_available = current_city.1.stock(&trade);
                             let amount_sold = amount
                                 .abs()
                                 .min(*cargo_access.get(&trade).unwrap_or(&0) as isize);
+                            let standing = reputation.in_city(player.player_id, &current_city.1);
                             let price = current_city
                                 .1
-                                .get_bulk_sell_price(&trade, amount_sold as usize);
+                                .get_bulk_sell_price(&trade, amount_sold as usize)
+                                * bonuses.sale_factor
+                                * sell_factor(standing);
                             player.money += price;
                             caravan.cargo.insert(
                                 trade,
                                 cargo_access.get(&trade).unwrap_or(&0) - amount_sold as usize,
                             );
                             //info!("Caravan sold {1} for {0}", price, trade.get_name());
-                            current_city
-                                .1
-                                .market
-                                .insert(trade, amount_available + amount_sold);
+                            *current_city.1.stock_mut(&trade) = amount_available + amount_sold;
+                            // Only goods loaded in another race's city count as imports
+                            let origin = caravan
+                                .origins
+                                .get(&trade)
+                                .copied()
+                                .filter(|race| *race != current_city.1.race);
+                            if let Some(origin) = origin {
+                                record_import(&mut current_city.1, origin, trade, amount_sold);
+                            }
+                            if amount_sold > 0 && amount_available <= 0 {
+                                reputation.change(
+                                    player.player_id,
+                                    &current_city.1,
+                                    SUPPLY_GOODWILL,
+                                );
+                            }
+
+                            // Arms for a city at war win its race over
+                            let at_war = events.active(**turn).any(|e| {
+                                e.kind == WorldEventKind::War && e.covers(&current_city.1.id)
+                            });
+                            if trade == Resources::Military
+                                && amount_sold > 0
+                                && at_war
+                                && let Some(origin) = origin
+                            {
+                                relations.shift(origin, current_city.1.race, WAR_SUPPLY_GOODWILL);
+                            }
                         }
                         //Put into warehouse
                         else if amount < 0 {
                             let city_id = current_city.1.id.clone();
-                            let Some(mut warehouse) =
-                                current_city.1.warehouses.get_mut(&player.player_id)
-                            else {
-                                error!(
-                                    "no warehouse of playerid {0} in {1}",
-                                    player.player_id, current_city.1.id
-                                );
-                                continue;
-                            };
-                            let amount_available = warehouse
-                                .get(&trade)
-                                .expect(&format!("malformed warehouse in {0}", city_id))
-                                .clone();
-
-                            //info!("found {0} {1} in city", amount_available, &trade.get_name());
-
-                            let amount_deposited = amount
+                            let carried = amount
                                 .abs()
                                 .min(*cargo_access.get(&trade).unwrap_or(&0) as isize);
+                            let amount_deposited =
+                                current_city.1.store(player.player_id, trade, carried);
+                            if amount_deposited < carried {
+                                warn!("Warehouse in {city_id} is full");
+                            }
 
                             //info!("want to deposit {0} {1} in city", amount_deposited, &trade.get_name());
 
@@ -239,8 +535,6 @@ impl Caravan {
                                 cargo_access.get(&trade).unwrap_or(&0) - amount_deposited as usize,
                             );
                             //info!("removed {0} {1} from caravan inventory", cargo_access.get(&trade).unwrap_or(&0) - amount_deposited as usize, &trade.get_name());
-                            warehouse.insert(trade, amount_available + amount_deposited);
-                            //info!("warehouse now has {0:?} {1}", warehouse.get(&trade), &trade.get_name());
                         }
                     }
 
@@ -261,7 +555,7 @@ pub fn plugin(app: &mut App) {
             OnEnter(GameState::Game),
             (
                 crate::kill_music,
-                spawn_map_sprite,
+                spawn_map_sprite.after(NodeGenSet),
                 spawn_city_ui_nodes.after(NodeGenSet),
                 spawn_game_ost,
             ),
@@ -401,14 +695,22 @@ fn spawn_map_sprite(
     mut commands: Commands,
     mut sylt: Sylt,
     network_state: Res<State<NetworkState>>,
+    map: Res<MapDefinition>,
+    terrain: Option<Res<TerrainMap>>,
+    mut images: ResMut<Assets<Image>>,
 ) {
-    commands.spawn((
-        Sprite {
-            image: sylt.get_sprite("map").image,
+    let sprite = match terrain {
+        Some(terrain) => Sprite {
+            image: images.add(terrain.render()),
+            custom_size: Some(Vec2::splat(2.0 * terrain.half_extent)),
             ..default()
         },
-        DespawnOnExit(GameState::Game),
-    ));
+        None => Sprite {
+            image: sylt.get_sprite(&map.background).image,
+            ..default()
+        },
+    };
+    commands.spawn((sprite, DespawnOnExit(GameState::Game)));
 
     //Next turn button
     commands.spawn((
@@ -514,7 +816,10 @@ fn update_caravan_outliner(
                         ..default()
                     },
                     BackgroundColor(Srgba::new(0.8, 0.1, 0.1, 1.0).into()),
-                    Text::new(caravan.position_city_id.clone()),
+                    Text::new(match caravan.status {
+                        CaravanStatus::Ok => caravan.position_city_id.clone(),
+                        _ => format!("{} (stuck)", caravan.position_city_id),
+                    }),
                 ));
             }
         });
@@ -543,28 +848,11 @@ fn spawn_city_ui_nodes(
         ];
         let mut image = ImageNode::new(sylt.get_image("town_ui_icon"));
         let mut background = BackgroundColor(Srgba::new(1.0, 0.1, 0.1, 0.3).into());
-        let city_descriptor = match city_data.population {
-            0..3 => format!("{:?} town", city_data.race),
-            3..6 => format!("{:?} city", city_data.race),
-            _ => format!(
-                "GREAT AREA OF {:?} (error in tooltip code btw)",
-                city_data.race
-            ),
-        };
         if capitals.contains(&city_data.id.as_str()) {
             image.color.set_alpha(0.0);
             background.0.set_alpha(0.0);
         }
 
-        let text_node = |text: String| {
-            (
-                Text::new(text),
-                TextLayout::new_with_justify(Justify::Center),
-                Node { ..default() },
-                BackgroundColor(Srgba::new(0.05, 0.05, 0.05, 1.0).into()),
-            )
-        };
-
         let city_ui_node = (
             Button,
             Node {
@@ -612,18 +900,33 @@ fn spawn_city_ui_nodes(
                 ..default()
             },
             BackgroundColor(Srgba::new(0.2, 0.2, 0.2, 0.5).into()),
-            related!(
-                Tooltips[
-                    text_node(city_data.id.clone()),
-                    text_node(format!("Tier: {}", city_data.population)),
-                    text_node(city_descriptor),
-                ]
-            ),
+            // Filled in by `refresh_city_markers`
+            related!(Tooltips[Text::new(city_data.id.clone())]),
+        );
+
+        let city_label = (
+            AnchorUiConfig {
+                anchorpoint: AnchorPoint::middle(),
+                offset: Some(Vec3::new(0.0, -24.0, 0.0)),
+                ..default()
+            },
+            CityLabel::default(),
+            Node {
+                flex_direction: FlexDirection::Row,
+                align_items: AlignItems::Center,
+                column_gap: px(4.0),
+                padding: UiRect::horizontal(px(4.0)),
+                border: UiRect::all(px(2.0)),
+                ..default()
+            },
+            BorderColor::all(Color::BLACK),
+            BackgroundColor(Srgba::new(0.05, 0.05, 0.05, 0.7).into()),
+            Pickable::IGNORE,
         );
 
-        commands
-            .entity(ent)
-            .insert(related!(AnchoredUiNodes[miku_slot, city_ui_node, clickable_node]));
+        commands.entity(ent).insert(related!(
+            AnchoredUiNodes[miku_slot, city_ui_node, clickable_node, city_label]
+        ));
     }
 }
 
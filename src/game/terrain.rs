@@ -0,0 +1,230 @@
+//! Procedurally generated terrain for maps that don't use a painted
+//! background, and the race territories placed on it.
+
+use bevy::asset::RenderAssetUsages;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use serde::{Deserialize, Serialize};
+
+use super::map_definition::{CapitalDefinition, PLAYABLE_RACES};
+use crate::prelude::*;
+
+/// World units covered by a single terrain cell and texture pixel.
+const CELL_SIZE: f32 = 16.0;
+const OCTAVES: usize = 4;
+/// Lattice points along each side of the coarsest noise octave.
+const BASE_LATTICE: usize = 4;
+const SEA_LEVEL: f32 = 0.35;
+const TREE_LINE: f32 = 0.7;
+const FOREST_MOISTURE: f32 = 0.55;
+/// Capitals keep this share of the map size away from the map's edge.
+const CAPITAL_MARGIN: f32 = 0.15;
+const CAPITAL_ATTEMPTS: usize = 200;
+
+#[derive(Reflect, Clone, Copy, Default, Eq, PartialEq, Debug, Hash, Serialize, Deserialize)]
+pub enum Terrain {
+    #[default]
+    Plains,
+    Forest,
+    Mountains,
+    Water,
+}
+
+impl Terrain {
+    /// How much more a road through this terrain costs than one over plains.
+    pub fn travel_cost(&self) -> f32 {
+        match self {
+            Terrain::Plains => 1.0,
+            Terrain::Forest => 1.5,
+            Terrain::Mountains => 2.5,
+            Terrain::Water => 4.0,
+        }
+    }
+
+    pub fn is_passable(&self) -> bool {
+        *self != Terrain::Water
+    }
+
+    fn color(&self) -> [u8; 4] {
+        match self {
+            Terrain::Plains => [170, 190, 110, 255],
+            Terrain::Forest => [60, 120, 60, 255],
+            Terrain::Mountains => [130, 120, 110, 255],
+            Terrain::Water => [60, 100, 170, 255],
+        }
+    }
+}
+
+/// Smoothly interpolated random values on a square lattice.
+struct ValueNoise {
+    lattice: Vec<f32>,
+    size: usize,
+}
+
+impl ValueNoise {
+    fn new(size: usize, rng: &mut GlobalRng) -> Self {
+        let lattice = (0..size * size).map(|_| rng.random::<f32>()).collect();
+        ValueNoise { lattice, size }
+    }
+
+    /// Samples the noise at `(u, v)`, both between 0 and 1.
+    fn sample(&self, u: f32, v: f32) -> f32 {
+        let max = (self.size - 1) as f32;
+        let (x, y) = (u.clamp(0.0, 1.0) * max, v.clamp(0.0, 1.0) * max);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(self.size - 1), (y0 + 1).min(self.size - 1));
+        let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+        let (tx, ty) = (smooth(x.fract()), smooth(y.fract()));
+        let at = |x: usize, y: usize| self.lattice[y * self.size + x];
+        let top = at(x0, y0) + (at(x1, y0) - at(x0, y0)) * tx;
+        let bottom = at(x0, y1) + (at(x1, y1) - at(x0, y1)) * tx;
+        top + (bottom - top) * ty
+    }
+}
+
+/// Several octaves of value noise, normalised to lie between 0 and 1.
+fn fractal_noise(rng: &mut GlobalRng) -> impl Fn(f32, f32) -> f32 + use<> {
+    let octaves: Vec<_> = (0..OCTAVES)
+        .map(|i| ValueNoise::new((BASE_LATTICE << i) + 1, rng))
+        .collect();
+    let total: f32 = (0..OCTAVES).map(|i| 0.5f32.powi(i as i32)).sum();
+    move |u, v| {
+        octaves
+            .iter()
+            .enumerate()
+            .map(|(i, noise)| noise.sample(u, v) * 0.5f32.powi(i as i32))
+            .sum::<f32>()
+            / total
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct TerrainMap {
+    pub half_extent: f32,
+    /// Cells along each side.
+    pub resolution: usize,
+    cells: Vec<Terrain>,
+}
+
+impl TerrainMap {
+    pub fn generate(half_extent: f32, rng: &mut GlobalRng) -> Self {
+        let resolution = ((2.0 * half_extent / CELL_SIZE).ceil() as usize).max(1);
+        let height = fractal_noise(rng);
+        let moisture = fractal_noise(rng);
+
+        let mut cells = Vec::with_capacity(resolution * resolution);
+        for y in 0..resolution {
+            for x in 0..resolution {
+                let u = (x as f32 + 0.5) / resolution as f32;
+                let v = (y as f32 + 0.5) / resolution as f32;
+                let h = height(u, v);
+                cells.push(if h < SEA_LEVEL {
+                    Terrain::Water
+                } else if h > TREE_LINE {
+                    Terrain::Mountains
+                } else if moisture(u, v) > FOREST_MOISTURE {
+                    Terrain::Forest
+                } else {
+                    Terrain::Plains
+                });
+            }
+        }
+
+        TerrainMap {
+            half_extent,
+            resolution,
+            cells,
+        }
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect::from_center_half_size(Vec2::ZERO, Vec2::splat(self.half_extent))
+    }
+
+    /// Terrain under a world position, water outside the map.
+    pub fn terrain_at(&self, pos: Vec2) -> Terrain {
+        if !self.bounds().contains(pos) {
+            return Terrain::Water;
+        }
+        let cell =
+            |c: f32| (((c + self.half_extent) / CELL_SIZE) as usize).min(self.resolution - 1);
+        // Row 0 is the top of the texture, the world's y axis points up
+        self.cells[cell(-pos.y) * self.resolution + cell(pos.x)]
+    }
+
+    pub fn is_passable(&self, pos: Vec2) -> bool {
+        self.terrain_at(pos).is_passable()
+    }
+
+    /// The most expensive terrain a road between `a` and `b` has to cross.
+    pub fn roughest_between(&self, a: Vec2, b: Vec2) -> Terrain {
+        let steps = (a.distance(b) / CELL_SIZE).ceil().max(1.0) as usize;
+        (0..=steps)
+            .map(|i| self.terrain_at(a.lerp(b, i as f32 / steps as f32)))
+            .max_by(|x, y| x.travel_cost().total_cmp(&y.travel_cost()))
+            .unwrap_or_default()
+    }
+
+    /// Spreads `count` capitals over dry land by Poisson-disk sampling, relaxing
+    /// the spacing whenever no more room is found.
+    pub fn place_capitals(&self, count: usize, rng: &mut GlobalRng) -> Vec<CapitalDefinition> {
+        let reach = self.half_extent * (1.0 - CAPITAL_MARGIN);
+        let mut spacing = self.half_extent;
+        let mut sites: Vec<Vec2> = vec![];
+        while sites.len() < count.min(PLAYABLE_RACES.len()) && spacing > CELL_SIZE {
+            let found = (0..CAPITAL_ATTEMPTS).find_map(|_| {
+                let pos = vec2(
+                    rng.random_range(-reach..reach),
+                    rng.random_range(-reach..reach),
+                );
+                (self.is_passable(pos) && sites.iter().all(|s| s.distance(pos) >= spacing))
+                    .then_some(pos)
+            });
+            match found {
+                Some(pos) => sites.push(pos),
+                None => spacing *= 0.9,
+            }
+        }
+        if sites.len() < count {
+            warn!("Only found room for {} of {count} capitals", sites.len());
+        }
+
+        sites
+            .into_iter()
+            .zip(PLAYABLE_RACES)
+            .map(|(pos, race)| CapitalDefinition {
+                race,
+                position: pos.to_array(),
+                arc: None,
+            })
+            .collect()
+    }
+
+    /// The map as a texture, one pixel per cell.
+    pub fn render(&self) -> Image {
+        let data = self.cells.iter().flat_map(|t| t.color()).collect();
+        Image::new(
+            Extent3d {
+                width: self.resolution as u32,
+                height: self.resolution as u32,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::RENDER_WORLD,
+        )
+    }
+}
+
+/// Index of the capital whose Voronoi cell `pos` lies in.
+pub fn territory_of(pos: Vec2, capitals: &[CapitalDefinition]) -> Option<usize> {
+    capitals
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| {
+            a.position()
+                .distance_squared(pos)
+                .total_cmp(&b.position().distance_squared(pos))
+        })
+        .map(|(idx, _)| idx)
+}
@@ -0,0 +1,318 @@
+//! Goods sold from one player's warehouse to another's in the same city, either
+//! once or as a standing supply contract over several turns.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use super::city_data::CityData;
+use super::market::Resources;
+use super::reputation::{CONTRACT_GOODWILL, Reputation};
+use super::strategic_map::{Player, UpdatedCity};
+use super::turn::TurnEndSinglePlayer;
+use crate::NetworkState;
+use crate::network::message::{ClientMessage, NetworkMessage, PlayerId, ServerMessage};
+use crate::prelude::*;
+
+/// Goods in a delivery unless the seller asks for another amount.
+pub const TRADE_LOT: isize = 10;
+/// Deliveries a standing contract runs for.
+pub const CONTRACT_TURNS: u32 = 5;
+/// Share of a delivery's price the seller owes when nothing is delivered.
+pub const PENALTY_SHARE: f64 = 0.5;
+
+pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<TradeLedger>()
+        .add_observer(record_proposal)
+        .add_observer(record_answer)
+        .add_observer(trade_settler)
+        .add_observer(client::send_proposal)
+        .add_observer(client::send_answer)
+        .add_observer(server::send_proposal)
+        .add_observer(server::send_answer);
+}
+
+/// Unique across all peers, as every player counts their own offers.
+#[derive(Reflect, Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct TradeId {
+    pub proposer: PlayerId,
+    pub number: u64,
+}
+
+#[derive(Reflect, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct TradeOffer {
+    pub id: TradeId,
+    pub seller: PlayerId,
+    pub buyer: PlayerId,
+    pub city_id: String,
+    pub resource: Resources,
+    /// Goods in every delivery.
+    pub amount: isize,
+    /// Paid by the buyer for every full delivery.
+    pub price: f64,
+    /// Deliveries left, a one-off trade has a single one.
+    pub turns: u32,
+    /// Owed by the seller for a delivery that falls entirely short.
+    pub penalty: f64,
+    pub accepted: bool,
+}
+
+impl TradeOffer {
+    /// An offer of a lot of the resource at its market value.
+    pub fn new(
+        id: TradeId,
+        seller: PlayerId,
+        buyer: PlayerId,
+        city: &CityData,
+        resource: Resources,
+        turns: u32,
+    ) -> Self {
+        let price = Self::market_price(city, resource, TRADE_LOT);
+        TradeOffer {
+            id,
+            seller,
+            buyer,
+            city_id: city.id.clone(),
+            resource,
+            amount: TRADE_LOT,
+            price,
+            turns,
+            penalty: (price * PENALTY_SHARE).round(),
+            accepted: false,
+        }
+    }
+
+    /// What `amount` of a resource is worth on the city's market.
+    pub fn market_price(city: &CityData, resource: Resources, amount: isize) -> f64 {
+        (city.get_resource_value(&resource) * amount as f64).round()
+    }
+
+    /// Delivers `amount` every time for the price the seller asks.
+    pub fn with_terms(mut self, amount: isize, price: f64) -> Self {
+        self.amount = amount;
+        self.price = price.round();
+        self.penalty = (self.price * PENALTY_SHARE).round();
+        self
+    }
+
+    pub fn is_contract(&self) -> bool {
+        self.turns > 1
+    }
+
+    pub fn describe(&self) -> String {
+        let terms = if self.is_contract() {
+            format!(
+                "every turn for {} more turns, {:.0}$ penalty when short",
+                self.turns, self.penalty
+            )
+        } else {
+            "once".to_string()
+        };
+        format!(
+            "Player {} sells {} {} to player {} in {} for {:.0}$ {}",
+            self.seller,
+            self.amount,
+            self.resource.get_name(),
+            self.buyer,
+            self.city_id,
+            self.price,
+            terms
+        )
+    }
+
+    /// Moves one delivery between the warehouses of `city`. Returns how much
+    /// arrived and how much the seller did not have.
+    fn deliver(&self, city: &mut CityData) -> (isize, isize) {
+        let in_stock = city
+            .stored(self.seller, self.resource)
+            .clamp(0, self.amount);
+        let delivered = city.store(self.buyer, self.resource, in_stock);
+        city.take(self.seller, self.resource, delivered);
+        (delivered, self.amount - in_stock)
+    }
+}
+
+/// Every offer still waiting for an answer and every accepted trade that has
+/// deliveries left. Unanswered offers lapse at the end of the turn.
+#[derive(Resource, Default, Clone, Debug)]
+pub struct TradeLedger {
+    pub offers: Vec<TradeOffer>,
+    next_number: u64,
+}
+
+impl TradeLedger {
+    pub fn next_id(&mut self, proposer: PlayerId) -> TradeId {
+        let number = self.next_number;
+        self.next_number += 1;
+        TradeId { proposer, number }
+    }
+
+    pub fn propose(&mut self, offer: TradeOffer) {
+        if self.offers.iter().any(|o| o.id == offer.id) {
+            return;
+        }
+        self.offers.push(offer);
+    }
+
+    pub fn answer(&mut self, id: TradeId, accepted: bool) {
+        if accepted {
+            if let Some(offer) = self.offers.iter_mut().find(|o| o.id == id) {
+                offer.accepted = true;
+            }
+        } else {
+            self.offers.retain(|o| o.id != id);
+        }
+    }
+
+    /// Offers waiting for `player` to accept or decline them.
+    pub fn incoming(&self, player: PlayerId) -> impl Iterator<Item = &TradeOffer> {
+        self.offers
+            .iter()
+            .filter(move |o| !o.accepted && o.buyer == player)
+    }
+
+    /// Everything else `player` takes part in.
+    pub fn standing(&self, player: PlayerId) -> impl Iterator<Item = &TradeOffer> {
+        self.offers.iter().filter(move |o| {
+            (o.seller == player || o.buyer == player) && (o.accepted || o.buyer != player)
+        })
+    }
+}
+
+#[derive(Event, Deref)]
+pub struct TradeProposed(pub TradeOffer);
+
+#[derive(Event)]
+pub struct TradeAnswered {
+    pub id: TradeId,
+    pub accepted: bool,
+}
+
+fn record_proposal(proposed: On<TradeProposed>, mut ledger: ResMut<TradeLedger>) {
+    ledger.propose(proposed.0.clone());
+}
+
+fn record_answer(answer: On<TradeAnswered>, mut ledger: ResMut<TradeLedger>) {
+    ledger.answer(answer.id, answer.accepted);
+}
+
+/// Delivers every accepted trade, moves the money and charges penalties for
+/// whatever the seller could not deliver. Sellers gain reputation in the city
+/// for full deliveries and lose it for short ones.
+pub fn trade_settler(
+    _ev: On<TurnEndSinglePlayer>,
+    mut ledger: ResMut<TradeLedger>,
+    mut reputation: ResMut<Reputation>,
+    mut nodes: Query<&mut CityData>,
+    mut players: Query<&mut Player>,
+    mut commands: Commands,
+) {
+    let mut touched = HashSet::new();
+    for offer in ledger.offers.iter_mut() {
+        if !offer.accepted {
+            offer.turns = 0;
+            continue;
+        }
+        let Some(mut city) = nodes.iter_mut().find(|c| c.id == offer.city_id) else {
+            error!("No city {} to trade in", offer.city_id);
+            offer.turns = 0;
+            continue;
+        };
+
+        let (delivered, shortfall) = offer.deliver(&mut city);
+        let paid = offer.price * delivered as f64 / offer.amount as f64;
+        let penalty = offer.penalty * shortfall as f64 / offer.amount as f64;
+        for mut player in players.iter_mut() {
+            if player.player_id == offer.buyer {
+                player.money += penalty - paid;
+            } else if player.player_id == offer.seller {
+                player.money += paid - penalty;
+            }
+        }
+        let goodwill = if shortfall > 0 {
+            -CONTRACT_GOODWILL
+        } else {
+            CONTRACT_GOODWILL
+        };
+        reputation.change(offer.seller, &city, goodwill);
+        if shortfall > 0 {
+            warn!(
+                "Player {} was {shortfall} {} short, paying {penalty:.0}$",
+                offer.seller,
+                offer.resource.get_name()
+            );
+        }
+
+        offer.turns -= 1;
+        touched.insert(city.id.clone());
+    }
+    ledger.offers.retain(|o| o.turns > 0);
+
+    for node in &nodes {
+        if touched.contains(&node.id) {
+            commands.trigger(UpdatedCity(node.clone()));
+        }
+    }
+}
+
+mod client {
+    use super::*;
+
+    pub fn send_proposal(
+        proposed: On<TradeProposed>,
+        mut writer: crate::network::client::Writer,
+        network_state: Res<State<NetworkState>>,
+    ) {
+        if *network_state != NetworkState::Client {
+            return;
+        }
+        writer.write(ClientMessage(NetworkMessage::TradeProposed {
+            offer: proposed.0.clone(),
+        }));
+    }
+
+    pub fn send_answer(
+        answer: On<TradeAnswered>,
+        mut writer: crate::network::client::Writer,
+        network_state: Res<State<NetworkState>>,
+    ) {
+        if *network_state != NetworkState::Client {
+            return;
+        }
+        writer.write(ClientMessage(NetworkMessage::TradeAnswered {
+            trade_id: answer.id,
+            accepted: answer.accepted,
+        }));
+    }
+}
+
+mod server {
+    use super::*;
+
+    pub fn send_proposal(
+        proposed: On<TradeProposed>,
+        mut writer: crate::network::server::Writer,
+        network_state: Res<State<NetworkState>>,
+    ) {
+        if *network_state != NetworkState::Host {
+            return;
+        }
+        writer.write(ServerMessage(NetworkMessage::TradeProposed {
+            offer: proposed.0.clone(),
+        }));
+    }
+
+    pub fn send_answer(
+        answer: On<TradeAnswered>,
+        mut writer: crate::network::server::Writer,
+        network_state: Res<State<NetworkState>>,
+    ) {
+        if *network_state != NetworkState::Host {
+            return;
+        }
+        writer.write(ServerMessage(NetworkMessage::TradeAnswered {
+            trade_id: answer.id,
+            accepted: answer.accepted,
+        }));
+    }
+}
@@ -1,16 +1,24 @@
 use std::collections::HashMap;
 
 use super::city_data::CityData;
+use super::map_seed::map_verified;
+use super::relations::{RaceRelations, use_imports};
+use super::reputation::{Reputation, SMUGGLING_PENALTY};
+use super::risk::{INCIDENT_LOG_LENGTH, Incident, roll_raids};
+use super::trade::TradeLedger;
+use super::wonders::WonderBonuses;
+use super::world_events::WorldEvents;
 use crate::NetworkState;
 use crate::game::strategic_hud::LockedCities;
 use crate::game::strategic_map::{
-    ActivePlayer, BuildinTable, Caravan, CaravanId, HostFixedTurnEnd, Player,
+    ActivePlayer, BuildinTable, Caravan, CaravanId, HostFixedTurnEnd, Player, UpdatedCity,
 };
 use crate::network::message::{PlayerId, ServerMessage};
 use crate::prelude::*;
 
+/// Turns played so far. Clients count the turns the host finishes.
 #[derive(Resource, Default, Deref, DerefMut)]
-struct Turn(u64);
+pub struct Turn(u64);
 
 #[derive(Event)]
 pub struct TurnEndSinglePlayer;
@@ -25,16 +33,21 @@ pub(super) fn plugin(app: &mut App) {
     app.init_resource::<Turn>()
         .add_systems(
             Update,
-            every_turn_ended.run_if(in_state(NetworkState::Host)),
+            every_turn_ended.run_if(in_state(NetworkState::Host).and(map_verified)),
         )
         .add_systems(
             PreUpdate,
             send_turn_update.run_if(in_state(NetworkState::Host).and(resource_changed::<Turn>)),
         )
         .add_observer(market_updater)
+        .add_observer(construction_updater)
+        .add_observer(building_sale_settler)
+        .add_observer(raid_roller)
+        .add_observer(warehouse_keeper)
         .add_observer(debt_collector)
         .add_observer(update_turnend)
         .add_observer(|_: On<TurnEndSinglePlayer>, mut turn: ResMut<Turn>| **turn += 1)
+        .add_observer(client::count_turn)
         .add_observer(client::update_turnend)
         .add_observer(server::update_turnend);
 }
@@ -44,6 +57,10 @@ fn send_turn_update(
     mut writer: crate::network::server::Writer,
     caravans: Query<(&CaravanId, &Caravan)>,
     players: Query<(Entity, &Player)>,
+    ledger: Res<TradeLedger>,
+    relations: Res<RaceRelations>,
+    reputation: Res<Reputation>,
+    events: Res<WorldEvents>,
     mut locked_cities: ResMut<LockedCities>,
 ) {
     locked_cities.clear();
@@ -58,7 +75,14 @@ fn send_turn_update(
     }
 
     writer.write(ServerMessage(
-        crate::network::message::NetworkMessage::TurnFinished { caravans, economy },
+        crate::network::message::NetworkMessage::TurnFinished {
+            caravans,
+            economy,
+            trades: ledger.offers.clone(),
+            relations: relations.clone(),
+            reputation: reputation.clone(),
+            events: events.clone(),
+        },
     ));
 
     commands.trigger(HostFixedTurnEnd);
@@ -89,6 +113,17 @@ mod client {
 
     use super::*;
 
+    pub fn count_turn(
+        _: On<HostFixedTurnEnd>,
+        mut turn: ResMut<Turn>,
+        network_state: Res<State<NetworkState>>,
+    ) {
+        if *network_state != NetworkState::Client {
+            return;
+        }
+        **turn += 1;
+    }
+
     pub fn update_turnend(
         player: On<TurnEnd>,
         mut writer: crate::network::client::Writer,
@@ -146,10 +181,97 @@ pub fn market_updater(
     nodes: Query<&mut CityData>,
     building_table: Res<BuildinTable>,
     mut players: Query<&mut Player>,
+    events: Res<WorldEvents>,
+    turn: Res<Turn>,
 ) {
     println!("we ended the turn!!!!");
     for mut node in nodes {
-        node.update_market(&building_table, &mut players);
+        let effects = events.effects(&node, **turn);
+        node.update_market(&building_table, &mut players, &effects);
+        use_imports(&mut node);
+    }
+}
+
+pub fn construction_updater(
+    _ev: On<TurnEndSinglePlayer>,
+    mut nodes: Query<&mut CityData>,
+    mut players: Query<&mut Player>,
+    mut commands: Commands,
+) {
+    let mut built_wonders: Vec<String> = nodes
+        .iter()
+        .filter_map(|node| node.wonder.as_ref())
+        .map(|wonder| wonder.building_id.clone())
+        .collect();
+    for mut node in &mut nodes {
+        if node.advance_construction(&mut players, &mut built_wonders) {
+            commands.trigger(UpdatedCity(node.clone()));
+        }
+    }
+}
+
+pub fn building_sale_settler(
+    _ev: On<TurnEndSinglePlayer>,
+    nodes: Query<&mut CityData>,
+    mut players: Query<&mut Player>,
+    mut commands: Commands,
+) {
+    for mut node in nodes {
+        if node.payouts.is_empty() {
+            continue;
+        }
+        for (player_id, amount) in node.payouts.drain(..) {
+            if let Some(mut player) = players.iter_mut().find(|p| p.player_id == player_id) {
+                player.money += amount;
+            }
+        }
+        commands.trigger(UpdatedCity(node.clone()));
+    }
+}
+
+pub fn raid_roller(
+    _ev: On<TurnEndSinglePlayer>,
+    nodes: Query<&mut CityData>,
+    building_table: Res<BuildinTable>,
+    mut players: Query<&mut Player>,
+    mut reputation: ResMut<Reputation>,
+    mut rng: ResMut<GlobalRng>,
+    mut commands: Commands,
+) {
+    for mut node in nodes {
+        let raids = roll_raids(&mut node, &building_table, &mut players, &mut rng);
+        if raids.is_empty() {
+            continue;
+        }
+        for raid in raids {
+            info!("{}", raid.describe());
+            if let Incident::Raid { owner, .. } = &raid {
+                reputation.change(*owner, &node, -SMUGGLING_PENALTY);
+            }
+            node.raids.push(raid);
+        }
+        let excess = node.raids.len().saturating_sub(INCIDENT_LOG_LENGTH);
+        node.raids.drain(..excess);
+        commands.trigger(UpdatedCity(node.clone()));
+    }
+}
+
+/// Charges rent and upkeep for every warehouse, lets perishable goods rot and
+/// refreshes the capacity granted by wonders.
+pub fn warehouse_keeper(
+    _ev: On<TurnEndSinglePlayer>,
+    mut nodes: Query<&mut CityData>,
+    mut players: Query<&mut Player>,
+    mut commands: Commands,
+) {
+    let bonuses: HashMap<PlayerId, WonderBonuses> = players
+        .iter()
+        .map(|p| (p.player_id, WonderBonuses::of(p.player_id, nodes.iter())))
+        .collect();
+    for mut node in &mut nodes {
+        if node.keep_warehouses(&mut players, &bonuses) {
+            commands.trigger(UpdatedCity(node.clone()));
+        }
     }
 }
 
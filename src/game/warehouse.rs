@@ -0,0 +1,270 @@
+//! Storage a player rents or builds in a city.
+
+use std::collections::HashMap;
+use std::ops::{Index, IndexMut};
+
+use serde::{Deserialize, Serialize};
+
+use super::market::Resources;
+use crate::prelude::*;
+
+pub const RENTED_CAPACITY: isize = 200;
+pub const BUILT_CAPACITY: isize = 400;
+/// Capacity added by every expansion of an owned warehouse.
+pub const EXPANSION_CAPACITY: isize = 200;
+/// Rent per unit of capacity and turn in a city of population 1.
+const RENT_PER_UNIT: f64 = 0.1;
+const UPKEEP_PER_UNIT: f64 = 0.02;
+const BUILD_COST_PER_UNIT: f64 = 5.0;
+/// Share of perishable goods that rots away every turn.
+const SPOILAGE: f64 = 0.1;
+
+pub const PERISHABLE_RESOURCES: [Resources; 3] =
+    [Resources::Food, Resources::Plants, Resources::Medicines];
+
+#[derive(Reflect, Clone, Copy, Default, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Tenure {
+    /// No storage at all.
+    #[default]
+    None,
+    Rented,
+    Owned,
+}
+
+/// Amount held of every resource, indexed by the resource itself. Sent and
+/// saved as a map so older data still loads.
+#[derive(Reflect, Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(from = "HashMap<Resources, isize>", into = "HashMap<Resources, isize>")]
+pub struct Stockpile([isize; Resources::COUNT]);
+
+impl Default for Stockpile {
+    fn default() -> Self {
+        Stockpile([0; Resources::COUNT])
+    }
+}
+
+impl Index<Resources> for Stockpile {
+    type Output = isize;
+
+    fn index(&self, res: Resources) -> &isize {
+        &self.0[res as usize]
+    }
+}
+
+impl IndexMut<Resources> for Stockpile {
+    fn index_mut(&mut self, res: Resources) -> &mut isize {
+        &mut self.0[res as usize]
+    }
+}
+
+impl Stockpile {
+    /// Every resource with a non-zero amount.
+    pub fn iter(&self) -> impl Iterator<Item = (Resources, isize)> + '_ {
+        Resources::all_resources()
+            .into_iter()
+            .map(|res| (res, self[res]))
+            .filter(|(_, amount)| *amount != 0)
+    }
+}
+
+impl From<HashMap<Resources, isize>> for Stockpile {
+    fn from(map: HashMap<Resources, isize>) -> Self {
+        let mut stock = Stockpile::default();
+        for (res, amount) in map {
+            stock[res] = amount;
+        }
+        stock
+    }
+}
+
+impl From<Stockpile> for HashMap<Resources, isize> {
+    fn from(stock: Stockpile) -> Self {
+        stock.iter().collect()
+    }
+}
+
+/// A player's storage in a city. Players without one get an empty warehouse
+/// without capacity, see `CityData::warehouse_mut`.
+#[derive(Reflect, Clone, Default, PartialEq, Debug, Serialize, Deserialize, Deref, DerefMut)]
+pub struct Warehouse {
+    #[deref]
+    pub stock: Stockpile,
+    pub capacity: isize,
+    /// Extra capacity granted by wonders, refreshed every turn.
+    #[serde(default)]
+    pub bonus_capacity: isize,
+    pub tenure: Tenure,
+}
+
+impl Warehouse {
+    pub fn rented() -> Self {
+        Warehouse {
+            capacity: RENTED_CAPACITY,
+            tenure: Tenure::Rented,
+            ..default()
+        }
+    }
+
+    pub fn built() -> Self {
+        Warehouse {
+            capacity: BUILT_CAPACITY,
+            tenure: Tenure::Owned,
+            ..default()
+        }
+    }
+
+    /// What putting up or expanding a warehouse by `capacity` costs.
+    pub fn build_cost(capacity: isize) -> f64 {
+        capacity as f64 * BUILD_COST_PER_UNIT
+    }
+
+    pub fn total_capacity(&self) -> isize {
+        self.capacity + self.bonus_capacity
+    }
+
+    pub fn used(&self) -> isize {
+        self.stock.iter().map(|(_, amount)| amount.max(0)).sum()
+    }
+
+    pub fn free_space(&self) -> isize {
+        (self.total_capacity() - self.used()).max(0)
+    }
+
+    /// Puts up to `amount` into storage and returns how much fit.
+    pub fn store(&mut self, res: Resources, amount: isize) -> isize {
+        let stored = amount.clamp(0, self.free_space());
+        self.stock[res] += stored;
+        stored
+    }
+
+    /// Money owed every turn, rent grows with the city's population.
+    pub fn upkeep(&self, population: u8) -> f64 {
+        match self.tenure {
+            Tenure::None => 0.0,
+            Tenure::Rented => self.capacity as f64 * RENT_PER_UNIT * population as f64,
+            Tenure::Owned => self.capacity as f64 * UPKEEP_PER_UNIT,
+        }
+    }
+
+    /// Rots a share of the perishable goods and throws out whatever no longer
+    /// fits, cheapest goods first. Returns what was lost.
+    pub fn spoil(&mut self) -> Vec<(Resources, isize)> {
+        let mut lost = vec![];
+        for res in PERISHABLE_RESOURCES {
+            let rotten = (self.stock[res] as f64 * SPOILAGE).ceil() as isize;
+            if rotten > 0 {
+                self.stock[res] -= rotten;
+                lost.push((res, rotten));
+            }
+        }
+
+        let mut excess = self.used() - self.total_capacity();
+        let mut goods: Vec<_> = self
+            .stock
+            .iter()
+            .filter(|(_, amount)| *amount > 0)
+            .map(|(res, _)| res)
+            .collect();
+        goods.sort_by_key(|res| (res.get_base_value(), *res));
+        for res in goods {
+            if excess <= 0 {
+                break;
+            }
+            let dropped = excess.min(self.stock[res]);
+            self.stock[res] -= dropped;
+            excess -= dropped;
+            lost.push((res, dropped));
+        }
+        lost
+    }
+
+    pub fn describe(&self, population: u8) -> String {
+        match self.tenure {
+            Tenure::None => "No warehouse".to_string(),
+            Tenure::Rented => format!(
+                "Rented warehouse: {}/{} stored, {:.0}$ rent per turn",
+                self.used(),
+                self.total_capacity(),
+                self.upkeep(population)
+            ),
+            Tenure::Owned => format!(
+                "Own warehouse: {}/{} stored, {:.0}$ upkeep per turn",
+                self.used(),
+                self.total_capacity(),
+                self.upkeep(population)
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn perishables_rot() {
+        let mut warehouse = Warehouse::rented();
+        warehouse[Resources::Food] = 100;
+        warehouse[Resources::Medicines] = 5;
+        warehouse[Resources::Stone] = 50;
+
+        let lost = warehouse.spoil();
+
+        assert_eq!(lost, vec![(Resources::Food, 10), (Resources::Medicines, 1)]);
+        assert_eq!(warehouse[Resources::Food], 90);
+        assert_eq!(warehouse[Resources::Medicines], 4);
+        assert_eq!(warehouse[Resources::Stone], 50);
+    }
+
+    #[test]
+    fn excess_goes_cheapest_first() {
+        let mut warehouse = Warehouse::rented();
+        warehouse[Resources::Textiles] = 200;
+        warehouse[Resources::Stone] = 30;
+        warehouse[Resources::Lumber] = 20;
+
+        let lost = warehouse.spoil();
+
+        assert_eq!(lost, vec![(Resources::Lumber, 20), (Resources::Stone, 30)]);
+        assert_eq!(warehouse.used(), warehouse.total_capacity());
+        assert_eq!(warehouse[Resources::Textiles], 200);
+    }
+
+    #[test]
+    fn bonus_capacity_keeps_goods() {
+        let mut warehouse = Warehouse::rented();
+        warehouse.bonus_capacity = 100;
+        warehouse[Resources::Stone] = 300;
+
+        assert!(warehouse.spoil().is_empty());
+        assert_eq!(warehouse[Resources::Stone], 300);
+    }
+
+    #[test]
+    fn stockpile_lists_only_held_goods() {
+        let mut stock = Stockpile::default();
+        stock[Resources::Glass] = 7;
+        stock[Resources::Coal] = -3;
+        stock[Resources::Coal] += 3;
+
+        assert_eq!(
+            stock.iter().collect::<Vec<_>>(),
+            vec![(Resources::Glass, 7)]
+        );
+    }
+
+    #[test]
+    fn stockpile_round_trips_as_a_map() {
+        let mut stock = Stockpile::default();
+        stock[Resources::Food] = 12;
+        stock[Resources::Artifacts] = 1;
+
+        let json = serde_json::to_string(&stock).unwrap();
+        let map: HashMap<Resources, isize> = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            map,
+            HashMap::from([(Resources::Food, 12), (Resources::Artifacts, 1)])
+        );
+        assert_eq!(serde_json::from_str::<Stockpile>(&json).unwrap(), stock);
+    }
+}
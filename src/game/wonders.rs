@@ -0,0 +1,140 @@
+//! World wonders: unique buildings that can stand only once on the whole map and
+//! help their owner everywhere.
+
+use super::city_data::CityData;
+use super::strategic_map::Faction;
+use crate::network::message::PlayerId;
+
+/// Smallest city population that can hold a wonder.
+pub const WONDER_POPULATION: u8 = 4;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WonderEffect {
+    /// Share of extra money earned when selling on a market.
+    SaleBonus(f64),
+    /// Share of Transportation saved on every leg.
+    TransportDiscount(f32),
+    /// Share of the ambush chance that remains.
+    RoadSafety(f64),
+    /// Share of extra capacity in every warehouse.
+    WarehouseSpace(f32),
+}
+
+impl WonderEffect {
+    pub fn describe(&self) -> String {
+        match self {
+            WonderEffect::SaleBonus(bonus) => {
+                format!("Your caravans sell for {:.0}% more", bonus * 100.0)
+            }
+            WonderEffect::TransportDiscount(discount) => format!(
+                "Your caravans need {:.0}% less Transportation",
+                discount * 100.0
+            ),
+            WonderEffect::RoadSafety(factor) => format!(
+                "Your caravans are ambushed {:.0}% less often",
+                (1.0 - factor) * 100.0
+            ),
+            WonderEffect::WarehouseSpace(bonus) => format!(
+                "Your warehouses hold {:.0}% more",
+                bonus * 100.0
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Wonder {
+    /// Key into the `BuildinTable`.
+    pub name: &'static str,
+    pub effect: WonderEffect,
+}
+
+pub const WONDERS: [Wonder; 4] = [
+    Wonder {
+        name: "Grand Bazaar",
+        effect: WonderEffect::SaleBonus(0.1),
+    },
+    Wonder {
+        name: "Imperial Highway Office",
+        effect: WonderEffect::TransportDiscount(0.25),
+    },
+    Wonder {
+        name: "Lantern Warden Keeps",
+        effect: WonderEffect::RoadSafety(0.5),
+    },
+    Wonder {
+        name: "Vaults of the Deep Bank",
+        effect: WonderEffect::WarehouseSpace(0.5),
+    },
+];
+
+pub fn wonder(name: &str) -> Option<&'static Wonder> {
+    WONDERS.iter().find(|wonder| wonder.name == name)
+}
+
+pub fn is_wonder(name: &str) -> bool {
+    wonder(name).is_some()
+}
+
+/// The city a wonder stands in, if it has been finished.
+pub fn wonder_site<'a>(
+    name: &str,
+    mut cities: impl Iterator<Item = &'a CityData>,
+) -> Option<&'a CityData> {
+    cities.find(|city| {
+        city.wonder
+            .as_ref()
+            .is_some_and(|built| built.building_id == name)
+    })
+}
+
+/// Everything the wonders owned by a player add up to.
+#[derive(Clone, Copy, Debug)]
+pub struct WonderBonuses {
+    pub sale_factor: f64,
+    pub transport_factor: f32,
+    pub ambush_factor: f64,
+    pub warehouse_factor: f32,
+}
+
+impl Default for WonderBonuses {
+    fn default() -> Self {
+        WonderBonuses {
+            sale_factor: 1.0,
+            transport_factor: 1.0,
+            ambush_factor: 1.0,
+            warehouse_factor: 1.0,
+        }
+    }
+}
+
+impl WonderBonuses {
+    pub fn of<'a>(player_id: PlayerId, cities: impl Iterator<Item = &'a CityData>) -> Self {
+        let mut bonuses = WonderBonuses::default();
+        for built in cities.filter_map(|city| city.wonder.as_ref()) {
+            if built.owner != Faction::Player(player_id) {
+                continue;
+            }
+            match wonder(&built.building_id).map(|wonder| wonder.effect) {
+                Some(WonderEffect::SaleBonus(bonus)) => bonuses.sale_factor += bonus,
+                Some(WonderEffect::TransportDiscount(discount)) => {
+                    bonuses.transport_factor *= 1.0 - discount
+                }
+                Some(WonderEffect::RoadSafety(factor)) => bonuses.ambush_factor *= factor,
+                Some(WonderEffect::WarehouseSpace(bonus)) => bonuses.warehouse_factor += bonus,
+                None => {}
+            }
+        }
+        bonuses
+    }
+
+    /// Capacity a warehouse gains on top of its own.
+    pub fn extra_capacity(&self, capacity: isize) -> isize {
+        (capacity as f32 * (self.warehouse_factor - 1.0)) as isize
+    }
+
+    /// Transportation needed for a leg once the discount is applied.
+    pub fn transport(&self, needed: usize) -> usize {
+        ((needed as f32 * self.transport_factor).ceil() as usize).max(1)
+    }
+}
@@ -0,0 +1,346 @@
+//! Droughts, plagues, wars and other events that hit a region of the map for a
+//! few turns, cutting what its buildings make or raising what its cities use up.
+//! Wars make the roads between their cities dearer to travel and droughts dry
+//! up the rivers.
+//! Only the host rolls them, everyone else hears about them over the network.
+
+use std::collections::{HashMap, HashSet};
+
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+
+use super::city_data::CityData;
+use super::city_graph::{CityGraph, Node as CityNode};
+use super::market::Resources;
+use super::roads::RoadType;
+use super::turn::{Turn, TurnEndSinglePlayer};
+use crate::network::message::{NetworkMessage, ServerMessage};
+use crate::prelude::*;
+use crate::{GameState, NetworkState};
+
+/// Chance that a new event breaks out at the end of a turn.
+const EVENT_CHANCE: f64 = 0.2;
+const MAX_ACTIVE_EVENTS: usize = 3;
+const MIN_EVENT_TURNS: u64 = 3;
+const MAX_EVENT_TURNS: u64 = 6;
+/// How much dearer a road is to travel while both its ends are at war.
+const WAR_ROUTE_COST: f32 = 1.5;
+
+pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<WorldEvents>()
+        .add_observer(event_roller)
+        .add_observer(record_event)
+        .add_observer(server::send_event)
+        .add_systems(OnEnter(GameState::Game), spawn_event_banner)
+        .add_systems(
+            Update,
+            (
+                update_event_banner,
+                close_roads.run_if(resource_exists::<CityGraph>),
+            )
+                .run_if(
+                    in_state(GameState::Game)
+                        .and(resource_changed::<WorldEvents>.or(resource_changed::<Turn>)),
+                ),
+        );
+}
+
+#[derive(Reflect, Clone, Copy, Eq, PartialEq, Debug, Hash, Serialize, Deserialize)]
+pub enum WorldEventKind {
+    Drought,
+    Plague,
+    MineCollapse,
+    Festival,
+    War,
+}
+
+impl WorldEventKind {
+    pub const ALL: [WorldEventKind; 5] = [
+        WorldEventKind::Drought,
+        WorldEventKind::Plague,
+        WorldEventKind::MineCollapse,
+        WorldEventKind::Festival,
+        WorldEventKind::War,
+    ];
+
+    /// Roads away from the city it breaks out in that the event spreads.
+    pub fn reach(&self) -> usize {
+        match self {
+            WorldEventKind::War => 2,
+            _ => 1,
+        }
+    }
+
+    /// Resources the region's buildings make less of, and the share they still
+    /// make.
+    fn output_cut(&self) -> (&'static [Resources], f32) {
+        match self {
+            WorldEventKind::Drought => (&[Resources::Water, Resources::Plants], 0.4),
+            WorldEventKind::MineCollapse => (
+                &[Resources::CommonOre, Resources::RareOre, Resources::Coal],
+                0.3,
+            ),
+            _ => (&[], 1.0),
+        }
+    }
+
+    /// Goods every city in the region uses up per tier each turn.
+    fn demand(&self) -> Option<(Resources, isize)> {
+        match self {
+            WorldEventKind::Plague => Some((Resources::Medicines, 5)),
+            WorldEventKind::Festival => Some((Resources::Luxuries, 6)),
+            WorldEventKind::War => Some((Resources::Military, 8)),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            WorldEventKind::Drought => "Drought",
+            WorldEventKind::Plague => "Plague",
+            WorldEventKind::MineCollapse => "Mine collapse",
+            WorldEventKind::Festival => "Festival",
+            WorldEventKind::War => "War",
+        }
+    }
+}
+
+#[derive(Reflect, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct WorldEvent {
+    pub kind: WorldEventKind,
+    /// City the event broke out in.
+    pub origin: String,
+    /// Every city the event covers, the origin included.
+    pub cities: Vec<String>,
+    /// Turn the event broke out in.
+    pub started: u64,
+    pub turns: u64,
+}
+
+impl WorldEvent {
+    pub fn turns_left(&self, turn: u64) -> u64 {
+        (self.started + self.turns).saturating_sub(turn)
+    }
+
+    pub fn covers(&self, city_id: &str) -> bool {
+        self.cities.iter().any(|c| c == city_id)
+    }
+
+    pub fn describe(&self, turn: u64) -> String {
+        format!(
+            "{} around {} ({} cities), {} turns left",
+            self.kind.name(),
+            self.origin,
+            self.cities.len(),
+            self.turns_left(turn)
+        )
+    }
+}
+
+/// What the events under way in a city do to its market.
+#[derive(Default, Debug)]
+pub struct EventEffects {
+    output: HashMap<Resources, f32>,
+    demand: HashMap<Resources, isize>,
+}
+
+impl EventEffects {
+    /// Share of their usual output the city's buildings make of a resource.
+    pub fn output_factor(&self, res: &Resources) -> f32 {
+        self.output.get(res).copied().unwrap_or(1.0)
+    }
+
+    /// Extra goods the city uses up this turn.
+    pub fn demand(&self) -> impl Iterator<Item = (&Resources, &isize)> {
+        self.demand.iter()
+    }
+}
+
+#[derive(Resource, Clone, Default, Debug, Serialize, Deserialize)]
+pub struct WorldEvents {
+    pub events: Vec<WorldEvent>,
+}
+
+impl WorldEvents {
+    pub fn active(&self, turn: u64) -> impl Iterator<Item = &WorldEvent> {
+        self.events.iter().filter(move |e| e.turns_left(turn) > 0)
+    }
+
+    pub fn effects(&self, city: &CityData, turn: u64) -> EventEffects {
+        let mut effects = EventEffects::default();
+        for event in self.active(turn).filter(|e| e.covers(&city.id)) {
+            let (resources, factor) = event.kind.output_cut();
+            for res in resources {
+                *effects.output.entry(*res).or_insert(1.0) *= factor;
+            }
+            if let Some((res, per_tier)) = event.kind.demand() {
+                *effects.demand.entry(res).or_default() += per_tier * city.population as isize;
+            }
+        }
+        effects
+    }
+}
+
+/// A new event broke out, announced by the host.
+#[derive(Event, Clone, Debug)]
+pub struct WorldEventStarted(pub WorldEvent);
+
+/// Cities at most `reach` roads away from `origin`.
+fn region(graph: &CityGraph, origin: &CityNode, reach: usize) -> HashSet<NodeIndex> {
+    let mut seen = HashSet::from([origin.0]);
+    let mut frontier = vec![origin.0];
+    for _ in 0..reach {
+        frontier = frontier
+            .iter()
+            .flat_map(|idx| graph.graph.neighbors(*idx))
+            .filter(|idx| seen.insert(*idx))
+            .collect();
+    }
+    seen
+}
+
+fn event_roller(
+    _ev: On<TurnEndSinglePlayer>,
+    events: Res<WorldEvents>,
+    turn: Res<Turn>,
+    graph: Res<CityGraph>,
+    cities: Query<(&CityNode, &CityData)>,
+    mut rng: ResMut<GlobalRng>,
+    mut commands: Commands,
+) {
+    let turn = **turn;
+    if events.active(turn).count() >= MAX_ACTIVE_EVENTS || rng.random::<f64>() >= EVENT_CHANCE {
+        return;
+    }
+
+    // Sorted so that the dice land the same for the same seed
+    let mut cities: Vec<_> = cities.iter().collect();
+    cities.sort_by(|(_, a), (_, b)| a.id.cmp(&b.id));
+    let Some((origin, origin_city)) = cities.get(rng.random_range(0..cities.len().max(1))) else {
+        return;
+    };
+    let kind = WorldEventKind::ALL[rng.random_range(0..WorldEventKind::ALL.len())];
+    let region = region(&graph, origin, kind.reach());
+    let event = WorldEvent {
+        kind,
+        origin: origin_city.id.clone(),
+        cities: cities
+            .iter()
+            .filter(|(node, _)| region.contains(&node.0))
+            .map(|(_, city)| city.id.clone())
+            .collect(),
+        started: turn,
+        turns: rng.random_range(MIN_EVENT_TURNS..=MAX_EVENT_TURNS),
+    };
+    commands.trigger(WorldEventStarted(event));
+}
+
+fn record_event(started: On<WorldEventStarted>, mut events: ResMut<WorldEvents>) {
+    info!(
+        "{} broke out around {}",
+        started.0.kind.name(),
+        started.0.origin
+    );
+    let turn = started.0.started;
+    events.events.retain(|e| e.turns_left(turn) > 0);
+    events.events.push(started.0.clone());
+}
+
+/// Dries up the rivers in droughts and slows the roads between cities at war,
+/// opening them again once the events are over.
+fn close_roads(
+    events: Res<WorldEvents>,
+    turn: Res<Turn>,
+    mut graph: ResMut<CityGraph>,
+    cities: Query<(&CityNode, &CityData)>,
+) {
+    let covered = |kind: WorldEventKind| -> HashSet<NodeIndex> {
+        cities
+            .iter()
+            .filter(|(_, city)| {
+                events
+                    .active(**turn)
+                    .any(|e| e.kind == kind && e.covers(&city.id))
+            })
+            .map(|(node, _)| node.0)
+            .collect()
+    };
+    let drought = covered(WorldEventKind::Drought);
+    let war = covered(WorldEventKind::War);
+
+    let edges: Vec<_> = graph.graph.edge_indices().collect();
+    for edge in edges {
+        let Some((a, b)) = graph.graph.edge_endpoints(edge) else {
+            continue;
+        };
+        let dry = graph.graph[edge].road == RoadType::River
+            && drought.contains(&a)
+            && drought.contains(&b);
+        graph.set_blockade(edge, dry);
+        let cost = if war.contains(&a) && war.contains(&b) {
+            WAR_ROUTE_COST
+        } else {
+            1.0
+        };
+        graph.set_route_cost(edge, cost);
+    }
+}
+
+/// Lists the events under way at the top of the map.
+#[derive(Component, Default, Clone, Debug)]
+struct EventBanner;
+
+fn spawn_event_banner(mut commands: Commands) {
+    commands.spawn((
+        ZIndex(2),
+        EventBanner,
+        Node {
+            position_type: PositionType::Absolute,
+            top: px(0),
+            left: vw(30),
+            width: vw(40),
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        Pickable::IGNORE,
+        DespawnOnExit(GameState::Game),
+    ));
+}
+
+fn update_event_banner(
+    mut commands: Commands,
+    events: Res<WorldEvents>,
+    turn: Res<Turn>,
+    banner: Single<Entity, With<EventBanner>>,
+) {
+    commands
+        .entity(*banner)
+        .despawn_related::<Children>()
+        .with_children(|banner| {
+            for event in events.active(**turn) {
+                banner.spawn((
+                    Text::new(event.describe(**turn)),
+                    TextFont::from_font_size(16.0),
+                    BackgroundColor(Srgba::new(0.05, 0.05, 0.05, 0.8).into()),
+                ));
+            }
+        });
+}
+
+mod server {
+    use super::*;
+
+    pub fn send_event(
+        started: On<WorldEventStarted>,
+        mut writer: crate::network::server::Writer,
+        network_state: Res<State<NetworkState>>,
+    ) {
+        if *network_state != NetworkState::Host {
+            return;
+        }
+        writer.write(ServerMessage(NetworkMessage::WorldEventStarted {
+            event: started.0.clone(),
+        }));
+    }
+}
@@ -6,7 +6,8 @@ use std::arch::global_asm;
 use std::time::SystemTime;
 
 use crate::game::city_data::CityData;
-use crate::game::city_graph::CITY_COUNTS;
+use crate::game::map_definition::MapDefinition;
+use crate::game::map_seed::MapRng;
 use crate::game::namelists::*;
 use crate::game::strategic_map::{CityImageMarker, CityNodeMarker};
 use bevy::feathers::FeathersPlugins;
@@ -156,6 +157,11 @@ struct DefaultUiCameraMarker;
 struct GlobalRngSeed(u64);
 
 fn main() {
+    // The host's seed, clients replace it with the one in the `Map` message
+    let seed = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("Error in system time.")
+        .as_secs();
     App::new()
         .add_plugins((
             DefaultPlugins.set(ImagePlugin::default_nearest()),
@@ -172,14 +178,10 @@ fn main() {
         ))
         // Insert as resource the initial value for the settings resources
         .insert_resource(DisplayQuality::Medium)
-        .insert_resource(GlobalRngSeed(0))
+        .insert_resource(GlobalRngSeed(seed))
         //.insert_resource(GlobalRng(StdRng::from_seed([0; 32])))
-        .insert_resource(GlobalRng(Xoshiro256StarStar::seed_from_u64(
-            SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .expect("Error in system time.")
-                .as_secs(),
-        )))
+        .insert_resource(GlobalRng(Xoshiro256StarStar::seed_from_u64(seed)))
+        .insert_resource(MapRng::new(seed))
         .insert_resource(Volume(7))
         .insert_resource(CityNameList(vec![]))
         // Declare the game state, whose starting value is determined by the `Default` trait
@@ -205,8 +207,12 @@ fn update_rng(seed: Res<GlobalRngSeed>, mut rng: ResMut<GlobalRng>) {
     *rng = GlobalRng(Xoshiro256StarStar::seed_from_u64(seed.0));
 }
 
-fn setup_city_names(mut rng: ResMut<GlobalRng>, mut namelist: ResMut<CityNameList>) {
-    let total_cities_per_faction = CITY_COUNTS.iter().fold(0, |acc, x| acc + x);
+fn setup_city_names(
+    mut streams: ResMut<MapRng>,
+    mut namelist: ResMut<CityNameList>,
+    map: Res<MapDefinition>,
+) {
+    let total_cities_per_faction = map.cities_per_capital();
     let namelists = generate_city_names(
         (
             total_cities_per_faction,
@@ -214,7 +220,7 @@ fn setup_city_names(mut rng: ResMut<GlobalRng>, mut namelist: ResMut<CityNameLis
             total_cities_per_faction,
             total_cities_per_faction,
         ),
-        &mut rng,
+        &mut streams.names,
     );
     namelist.0 = namelists;
 }
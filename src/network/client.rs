@@ -8,12 +8,19 @@ use crate::{
     GlobalRngSeed, NetworkState,
     game::{
         city_data::CityData,
+        city_graph::{CityGraph, Node as CityNode},
+        map_definition::MapDefinition,
         namelists::CityNameList,
+        relations::RaceRelations,
+        reputation::Reputation,
+        roads::upgrade_road,
         strategic_hud::LockedCities,
         strategic_map::{
             ActivePlayer, BelongsTo, Caravan, CaravanId, HostFixedTurnEnd, Player, SelectedCity,
         },
+        trade::TradeLedger,
         turn::TurnEnded,
+        world_events::{WorldEventStarted, WorldEvents},
     },
     network::{
         message::{ClientData, ClientMessage, NetworkMessage, Players, ServerMessage},
@@ -98,6 +105,9 @@ pub fn plugin(app: &mut App) {
                 receive_host_finished_turn,
                 update_caravan_edits,
                 update_turnend,
+                receive_trades,
+                receive_roads,
+                receive_world_events,
                 spawn_caravans,
             )
                 .chain()
@@ -174,11 +184,18 @@ fn await_map(
     mut state: ResMut<NextState<ClientNetworkState>>,
     mut rng: ResMut<GlobalRngSeed>,
     mut my_city_names: ResMut<CityNameList>,
+    mut my_map: ResMut<MapDefinition>,
 ) {
     for message in messages.read() {
-        if let NetworkMessage::Map { seed, city_names } = &**message {
+        if let NetworkMessage::Map {
+            seed,
+            city_names,
+            map,
+        } = &**message
+        {
             info!("Received seed from host, set seed to {seed}");
             my_city_names.0 = city_names.clone();
+            *my_map = map.clone();
             rng.0 = *seed;
             state.set(ClientNetworkState::AwaitingStart);
         }
@@ -364,12 +381,29 @@ fn receive_host_finished_turn(
     mut players: Query<(Entity, &mut Player), With<TurnEnded>>,
     mut caravans_query: Query<(&mut Caravan, &CaravanId)>,
     mut locked_cities: ResMut<LockedCities>,
+    mut ledger: ResMut<TradeLedger>,
+    mut race_relations: ResMut<RaceRelations>,
+    mut player_reputation: ResMut<Reputation>,
+    mut world_events: ResMut<WorldEvents>,
 ) {
     for msg in reader.read() {
-        let NetworkMessage::TurnFinished { caravans, economy } = &**msg else {
+        let NetworkMessage::TurnFinished {
+            caravans,
+            economy,
+            trades,
+            relations,
+            reputation,
+            events,
+        } = &**msg
+        else {
             continue;
         };
 
+        ledger.offers = trades.clone();
+        *race_relations = relations.clone();
+        *player_reputation = reputation.clone();
+        *world_events = events.clone();
+
         for (caravan_id, caravan) in caravans {
             let Some((mut c, _)) = caravans_query
                 .iter_mut()
@@ -391,3 +425,39 @@ fn receive_host_finished_turn(
         locked_cities.clear();
     }
 }
+
+fn receive_trades(mut reader: Reader, mut ledger: ResMut<TradeLedger>) {
+    for msg in reader.read() {
+        match &**msg {
+            NetworkMessage::TradeProposed { offer } => ledger.propose(offer.clone()),
+            NetworkMessage::TradeAnswered { trade_id, accepted } => {
+                ledger.answer(*trade_id, *accepted)
+            }
+            _ => {}
+        }
+    }
+}
+
+fn receive_roads(
+    mut reader: Reader,
+    mut graph: ResMut<CityGraph>,
+    cities: Query<(&CityNode, &CityData)>,
+) {
+    for msg in reader.read() {
+        let NetworkMessage::RoadUpgraded { from, to, road } = &**msg else {
+            continue;
+        };
+
+        upgrade_road(&mut graph, &cities, from, to, *road);
+    }
+}
+
+fn receive_world_events(mut reader: Reader, mut commands: Commands) {
+    for msg in reader.read() {
+        let NetworkMessage::WorldEventStarted { event } = &**msg else {
+            continue;
+        };
+
+        commands.trigger(WorldEventStarted(event.clone()));
+    }
+}
@@ -3,7 +3,14 @@ use std::collections::HashMap;
 use crate::{
     game::{
         city_data::CityData,
+        map_definition::MapDefinition,
+        map_seed::MapCheck,
+        relations::RaceRelations,
+        reputation::Reputation,
+        roads::RoadType,
         strategic_map::{Caravan, CaravanId},
+        trade::{TradeId, TradeOffer},
+        world_events::{WorldEvent, WorldEvents},
     },
     prelude::*,
 };
@@ -24,6 +31,8 @@ pub enum NetworkMessage {
     Map {
         seed: u64,
         city_names: Vec<Vec<String>>,
+        /// The host's map, which clients play instead of their own copy.
+        map: MapDefinition,
     },
     GameStart,
     TurnEnded {
@@ -49,6 +58,14 @@ pub enum NetworkMessage {
     TurnFinished {
         caravans: Vec<(CaravanId, Caravan)>,
         economy: HashMap<PlayerId, f64>,
+        #[serde(default)]
+        trades: Vec<TradeOffer>,
+        #[serde(default)]
+        relations: RaceRelations,
+        #[serde(default)]
+        reputation: Reputation,
+        #[serde(default)]
+        events: WorldEvents,
     },
     CityViewing {
         player_id: PlayerId,
@@ -58,6 +75,28 @@ pub enum NetworkMessage {
         player_id: PlayerId,
         city_id: String,
     },
+    TradeProposed {
+        offer: TradeOffer,
+    },
+    TradeAnswered {
+        trade_id: TradeId,
+        accepted: bool,
+    },
+    RoadUpgraded {
+        from: String,
+        to: String,
+        road: RoadType,
+    },
+    MapFingerprint {
+        player_id: PlayerId,
+        fingerprint: u64,
+    },
+    MapChecked {
+        check: MapCheck,
+    },
+    WorldEventStarted {
+        event: WorldEvent,
+    },
 }
 
 #[derive(Resource)]
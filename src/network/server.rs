@@ -13,9 +13,14 @@ use crate::{
     GlobalRngSeed, NetworkState,
     game::{
         city_data::CityData,
+        city_graph::{CityGraph, Node as CityNode},
+        map_definition::MapDefinition,
+        map_seed::{FingerprintReports, MapFingerprint},
         namelists::CityNameList,
+        roads::upgrade_road,
         strategic_hud::LockedCities,
         strategic_map::{BelongsTo, Caravan, CaravanId, Player, SelectedCity},
+        trade::TradeLedger,
         turn::TurnEnded,
     },
     network::{
@@ -103,6 +108,9 @@ pub fn plugin(app: &mut App) {
             read_caravan_requests,
             update_and_echo_caravan_edits,
             update_and_echo_turnend,
+            update_and_echo_trades,
+            update_and_echo_roads,
+            read_map_fingerprints,
         )
             .run_if(in_state(NetworkState::Host)),
     )
@@ -206,10 +214,12 @@ fn broadcast_seed_and_start_before_mapgen(
     mut writer: MessageWriter<ServerMessage>,
     seed: Res<GlobalRngSeed>,
     city_names: ResMut<CityNameList>,
+    map: Res<MapDefinition>,
 ) {
     writer.write(ServerMessage(NetworkMessage::Map {
         seed: seed.0,
         city_names: city_names.0.clone(),
+        map: map.clone(),
     }));
     writer.write(ServerMessage(NetworkMessage::GameStart));
 }
@@ -394,3 +404,65 @@ fn update_and_echo_turnend(
         writer.write(ServerMessage(msg.clone()));
     }
 }
+
+fn update_and_echo_trades(mut reader: Reader, mut writer: Writer, mut ledger: ResMut<TradeLedger>) {
+    for msg in reader.read() {
+        match &**msg {
+            NetworkMessage::TradeProposed { offer } => ledger.propose(offer.clone()),
+            NetworkMessage::TradeAnswered { trade_id, accepted } => {
+                ledger.answer(*trade_id, *accepted)
+            }
+            _ => continue,
+        }
+
+        writer.write(ServerMessage(msg.0.clone()));
+    }
+}
+
+fn update_and_echo_roads(
+    mut reader: Reader,
+    mut writer: Writer,
+    mut graph: ResMut<CityGraph>,
+    cities: Query<(&CityNode, &CityData)>,
+) {
+    for msg in reader.read() {
+        let NetworkMessage::RoadUpgraded { from, to, road } = &**msg else {
+            continue;
+        };
+
+        upgrade_road(&mut graph, &cities, from, to, *road);
+
+        writer.write(ServerMessage(msg.0.clone()));
+    }
+}
+
+fn read_map_fingerprints(
+    mut reader: Reader,
+    mut reports: ResMut<FingerprintReports>,
+    own: Option<Res<MapFingerprint>>,
+) {
+    for msg in reader.read() {
+        let NetworkMessage::MapFingerprint {
+            player_id,
+            fingerprint,
+        } = &**msg
+        else {
+            continue;
+        };
+
+        reports.insert(*player_id, *fingerprint);
+        let Some(own) = own.as_deref() else {
+            error!("Player {player_id} built a map before the host did");
+            continue;
+        };
+        if *fingerprint != own.0 {
+            error!(
+                "Player {player_id} generated a different map ({fingerprint:016x} instead of \
+                 {:016x}), refusing to start",
+                own.0
+            );
+        } else {
+            info!("Player {player_id} generated the same map");
+        }
+    }
+}